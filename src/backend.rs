@@ -0,0 +1,190 @@
+use crate::error::ClipmateError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+mod memory;
+mod system;
+mod wayland;
+mod x11;
+mod xclip;
+
+pub use memory::MemoryBackend;
+pub use system::SystemBackend;
+pub use wayland::WaylandBackend;
+pub use x11::X11Backend;
+pub use xclip::XclipBackend;
+
+/// How long a backend waits before running a tool it could not run again.
+pub const RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Whether a command line tool a backend drives can be run.
+///
+/// Once the tool could not be run, the backend stops running it for a while
+/// and then tries again, so that a tool installed, or a display server
+/// started, after the daemon is picked up.
+#[derive(Debug)]
+pub(crate) struct Tool {
+    name: &'static str,
+    retry_after: Duration,
+    /// When to try again, if the tool could not be run.
+    retry_at: Option<Instant>,
+}
+
+impl Tool {
+    pub(crate) fn new(name: &'static str) -> Tool {
+        Tool {
+            name,
+            retry_after: RETRY_AFTER,
+            retry_at: None,
+        }
+    }
+
+    pub(crate) fn set_retry_after(&mut self, retry_after: Duration) {
+        self.retry_after = retry_after;
+    }
+
+    /// Whether to run the tool now.
+    pub(crate) fn is_available(&self) -> bool {
+        self.retry_at.is_none_or(|at| Instant::now() >= at)
+    }
+
+    pub(crate) fn failed(&mut self, error: &io::Error) {
+        if self.retry_at.is_none() {
+            tracing::warn!(
+                tool = self.name,
+                "Cannot run {}, retrying every {}s: {}",
+                self.name,
+                self.retry_after.as_secs(),
+                error
+            );
+        }
+        self.retry_at = Some(Instant::now() + self.retry_after);
+    }
+
+    pub(crate) fn succeeded(&mut self) {
+        if self.retry_at.take().is_some() {
+            tracing::info!(tool = self.name, "{} can be run again", self.name);
+        }
+    }
+}
+
+/// The X11 selections, which Wayland mirrors except for `Secondary`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Selection {
+    /// What is copied explicitly, with Ctrl+C or a menu.
+    #[default]
+    Clipboard,
+    /// The text last selected with the mouse, pasted with the middle
+    /// button.
+    Primary,
+    /// A rarely used second selection.
+    Secondary,
+}
+
+impl Selection {
+    pub fn name(self) -> &'static str {
+        match self {
+            Selection::Clipboard => "clipboard",
+            Selection::Primary => "primary",
+            Selection::Secondary => "secondary",
+        }
+    }
+
+    pub fn is_clipboard(&self) -> bool {
+        *self == Selection::Clipboard
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Selection {
+    type Err = ClipmateError;
+
+    fn from_str(s: &str) -> Result<Selection, ClipmateError> {
+        match s {
+            "clipboard" => Ok(Selection::Clipboard),
+            "primary" => Ok(Selection::Primary),
+            "secondary" => Ok(Selection::Secondary),
+            _ => Err(ClipmateError::InvalidInput(format!(
+                "unknown selection '{}', expected clipboard, primary or secondary",
+                s
+            ))),
+        }
+    }
+}
+
+/// A source and sink for clipboard contents.
+///
+/// `ClipboardManager` only talks to the clipboard through this trait, so the
+/// daemon logic can run against any provider, including the in-memory one.
+pub trait ClipboardBackend {
+    /// Directs the calls that follow to `selection`. Backends that only
+    /// know the clipboard fail for the other selections.
+    fn select(&mut self, selection: Selection) -> io::Result<()> {
+        match selection {
+            Selection::Clipboard => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("the {} selection is not supported", selection),
+            )),
+        }
+    }
+
+    /// Returns the current text content of the clipboard.
+    fn read_text(&mut self) -> io::Result<String>;
+
+    /// Returns the clipboard content for the given MIME type, or an empty
+    /// vector when the clipboard does not hold that type.
+    fn read_type(&mut self, mime_type: &str) -> io::Result<Vec<u8>>;
+
+    /// Returns the MIME types (X11 targets) the clipboard currently offers.
+    /// Backends that cannot tell return an empty list.
+    fn available_types(&mut self) -> io::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn write_text(&mut self, text: &str) -> io::Result<()>;
+
+    fn write_image(&mut self, mime_type: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Puts `text`, if any, on the clipboard together with other
+    /// representations of the same content, given as `(mime type, data)`
+    /// pairs with the richest first.
+    ///
+    /// Backends that can only offer one type at a time write the text, or
+    /// the first representation if there is no text, and warn about the
+    /// representations left out.
+    fn write_formats(
+        &mut self,
+        text: Option<&str>,
+        formats: &[(String, Vec<u8>)],
+    ) -> io::Result<()> {
+        let left_out = match (text, formats) {
+            (Some(text), _) => {
+                self.write_text(text)?;
+                formats
+            }
+            (None, [(mime_type, data), rest @ ..]) => {
+                self.write_image(mime_type, data)?;
+                rest
+            }
+            (None, []) => return Ok(()),
+        };
+        if !left_out.is_empty() {
+            let types: Vec<&str> = left_out.iter().map(|(t, _)| t.as_str()).collect();
+            tracing::warn!(
+                "Only one type can be offered at a time here, left out {}",
+                types.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use super::{ClipboardBackend, Selection};
+use std::collections::HashMap;
+use std::io;
+
+/// What one selection of a `MemoryBackend` holds.
+#[derive(Default, Debug, Clone)]
+struct Contents {
+    text: Option<String>,
+    /// Contents by MIME type, other than the text.
+    data: HashMap<String, Vec<u8>>,
+    extra_types: Vec<String>,
+}
+
+/// Backend keeping the clipboard in process memory, for headless use and
+/// tests.
+///
+/// Like a real clipboard it holds a single item: writing text drops any
+/// image and extra types, and writing an image drops the text. The item can
+/// be offered in several types at once with `offer` or `write_formats`.
+/// Every selection holds its own item; calls go to the one last passed to
+/// `select`.
+#[derive(Default, Debug, Clone)]
+pub struct MemoryBackend {
+    selection: Selection,
+    selections: HashMap<Selection, Contents>,
+    types_listed: usize,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+
+    fn current(&self) -> Option<&Contents> {
+        self.selections.get(&self.selection)
+    }
+
+    fn current_mut(&mut self) -> &mut Contents {
+        self.selections.entry(self.selection).or_default()
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        self.current().and_then(|c| c.text.as_deref())
+    }
+
+    pub fn image(&self, mime_type: &str) -> Option<&[u8]> {
+        self.contents(mime_type)
+    }
+
+    /// The content offered for `mime_type`, other than the text.
+    pub fn contents(&self, mime_type: &str) -> Option<&[u8]> {
+        self.current()
+            .and_then(|c| c.data.get(mime_type))
+            .map(|data| data.as_slice())
+    }
+
+    /// Offers `data` for `mime_type` alongside the current content, as
+    /// browsers do with HTML next to the plain text.
+    pub fn offer(&mut self, mime_type: &str, data: &[u8]) {
+        self.current_mut()
+            .data
+            .insert(mime_type.to_string(), data.to_vec());
+    }
+
+    /// How many times the offered types were listed.
+    pub fn types_listed(&self) -> usize {
+        self.types_listed
+    }
+
+    /// Advertises an additional type alongside the current content, as
+    /// password managers do with their concealment hints.
+    pub fn offer_type(&mut self, mime_type: &str) {
+        self.current_mut().extra_types.push(mime_type.to_string());
+    }
+}
+
+impl ClipboardBackend for MemoryBackend {
+    fn select(&mut self, selection: Selection) -> io::Result<()> {
+        self.selection = selection;
+        Ok(())
+    }
+
+    fn read_text(&mut self) -> io::Result<String> {
+        self.text()
+            .map(str::to_string)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "clipboard holds no text"))
+    }
+
+    fn read_type(&mut self, mime_type: &str) -> io::Result<Vec<u8>> {
+        Ok(self
+            .contents(mime_type)
+            .map(<[u8]>::to_vec)
+            .unwrap_or_default())
+    }
+
+    fn available_types(&mut self) -> io::Result<Vec<String>> {
+        self.types_listed += 1;
+        let mut types = Vec::new();
+        let Some(contents) = self.current() else {
+            return Ok(types);
+        };
+        if contents.text.is_some() {
+            types.push("text/plain;charset=utf-8".to_string());
+            types.push("UTF8_STRING".to_string());
+        }
+        types.extend(contents.data.keys().cloned());
+        types.extend(contents.extra_types.iter().cloned());
+        Ok(types)
+    }
+
+    fn write_text(&mut self, text: &str) -> io::Result<()> {
+        self.write_formats(Some(text), &[])
+    }
+
+    fn write_image(&mut self, mime_type: &str, data: &[u8]) -> io::Result<()> {
+        self.write_formats(None, &[(mime_type.to_string(), data.to_vec())])
+    }
+
+    fn write_formats(
+        &mut self,
+        text: Option<&str>,
+        formats: &[(String, Vec<u8>)],
+    ) -> io::Result<()> {
+        *self.current_mut() = Contents {
+            text: text.map(str::to_string),
+            data: formats.iter().cloned().collect(),
+            extra_types: Vec::new(),
+        };
+        Ok(())
+    }
+}
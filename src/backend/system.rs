@@ -0,0 +1,73 @@
+use super::{ClipboardBackend, Selection, WaylandBackend, X11Backend};
+use std::env;
+use std::io;
+
+/// The backend for the session clipmate is running in: wl-clipboard when
+/// `WAYLAND_DISPLAY` is set, X11 otherwise.
+pub enum SystemBackend {
+    X11(X11Backend),
+    Wayland(WaylandBackend),
+}
+
+impl SystemBackend {
+    pub fn detect() -> SystemBackend {
+        SystemBackend::detect_for(Selection::Clipboard)
+    }
+
+    /// Like `detect`, for `selection`.
+    pub fn detect_for(selection: Selection) -> SystemBackend {
+        match env::var_os("WAYLAND_DISPLAY") {
+            Some(display) if !display.is_empty() => {
+                SystemBackend::Wayland(WaylandBackend::for_selection(selection))
+            }
+            _ => SystemBackend::X11(X11Backend::for_selection(selection)),
+        }
+    }
+
+    fn inner(&mut self) -> &mut dyn ClipboardBackend {
+        match self {
+            SystemBackend::X11(backend) => backend,
+            SystemBackend::Wayland(backend) => backend,
+        }
+    }
+}
+
+impl Default for SystemBackend {
+    fn default() -> Self {
+        SystemBackend::detect()
+    }
+}
+
+impl ClipboardBackend for SystemBackend {
+    fn select(&mut self, selection: Selection) -> io::Result<()> {
+        self.inner().select(selection)
+    }
+
+    fn read_text(&mut self) -> io::Result<String> {
+        self.inner().read_text()
+    }
+
+    fn read_type(&mut self, mime_type: &str) -> io::Result<Vec<u8>> {
+        self.inner().read_type(mime_type)
+    }
+
+    fn available_types(&mut self) -> io::Result<Vec<String>> {
+        self.inner().available_types()
+    }
+
+    fn write_text(&mut self, text: &str) -> io::Result<()> {
+        self.inner().write_text(text)
+    }
+
+    fn write_image(&mut self, mime_type: &str, data: &[u8]) -> io::Result<()> {
+        self.inner().write_image(mime_type, data)
+    }
+
+    fn write_formats(
+        &mut self,
+        text: Option<&str>,
+        formats: &[(String, Vec<u8>)],
+    ) -> io::Result<()> {
+        self.inner().write_formats(text, formats)
+    }
+}
@@ -0,0 +1,146 @@
+use super::{ClipboardBackend, Selection, Tool};
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Backend driving `wl-paste` and `wl-copy` from wl-clipboard.
+///
+/// Like `XclipBackend`, it stops spawning `wl-paste` for a while once it
+/// turns out to be missing. Wayland has no secondary selection.
+pub struct WaylandBackend {
+    primary: bool,
+    wl_paste: Tool,
+}
+
+impl Default for WaylandBackend {
+    fn default() -> Self {
+        WaylandBackend::new()
+    }
+}
+
+impl WaylandBackend {
+    pub fn new() -> WaylandBackend {
+        WaylandBackend::for_selection(Selection::Clipboard)
+    }
+
+    pub fn for_selection(selection: Selection) -> WaylandBackend {
+        WaylandBackend {
+            primary: selection == Selection::Primary,
+            wl_paste: Tool::new("wl-paste"),
+        }
+    }
+
+    /// Waits `retry_after` instead of `RETRY_AFTER` before running
+    /// `wl-paste` again once it could not be run.
+    pub fn retry_after(mut self, retry_after: Duration) -> WaylandBackend {
+        self.wl_paste.set_retry_after(retry_after);
+        self
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.wl_paste.is_available()
+    }
+
+    /// Lists the MIME types currently offered on the clipboard.
+    pub fn list_types(&mut self) -> io::Result<Vec<String>> {
+        let output = self.paste(&["--list-types"])?;
+        Ok(String::from_utf8_lossy(&output)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    fn selection_args(&self) -> &'static [&'static str] {
+        if self.primary {
+            &["--primary"]
+        } else {
+            &[]
+        }
+    }
+
+    fn paste(&mut self, args: &[&str]) -> io::Result<Vec<u8>> {
+        if !self.wl_paste.is_available() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "wl-paste is not available",
+            ));
+        }
+        let output = Command::new("wl-paste")
+            .args(self.selection_args())
+            .args(args)
+            .stderr(Stdio::null())
+            .output();
+
+        match output {
+            Ok(output) => {
+                self.wl_paste.succeeded();
+                if !output.status.success() {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "nothing is copied"));
+                }
+                Ok(output.stdout)
+            }
+            Err(e) => {
+                self.wl_paste.failed(&e);
+                Err(e)
+            }
+        }
+    }
+
+    fn copy(&mut self, mime_type: &str, data: &[u8]) -> io::Result<()> {
+        let mut child = Command::new("wl-copy")
+            .args(self.selection_args())
+            .args(["--type", mime_type])
+            .stdin(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(data)?;
+        }
+        // wl-copy forks a server to own the clipboard; the parent exits as
+        // soon as it has read its input.
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("wl-copy exited with {}", status)));
+        }
+        Ok(())
+    }
+}
+
+impl ClipboardBackend for WaylandBackend {
+    fn select(&mut self, selection: Selection) -> io::Result<()> {
+        match selection {
+            Selection::Clipboard => self.primary = false,
+            Selection::Primary => self.primary = true,
+            Selection::Secondary => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Wayland has no secondary selection",
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn read_text(&mut self) -> io::Result<String> {
+        let data = self.paste(&["--no-newline", "--type", "text"])?;
+        String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn read_type(&mut self, mime_type: &str) -> io::Result<Vec<u8>> {
+        // wl-paste fails when the type is not offered, which saves listing
+        // the types first on every read.
+        Ok(self.paste(&["--type", mime_type]).unwrap_or_default())
+    }
+
+    fn available_types(&mut self) -> io::Result<Vec<String>> {
+        self.list_types()
+    }
+
+    fn write_text(&mut self, text: &str) -> io::Result<()> {
+        self.copy("text/plain;charset=utf-8", text.as_bytes())
+    }
+
+    fn write_image(&mut self, mime_type: &str, data: &[u8]) -> io::Result<()> {
+        self.copy(mime_type, data)
+    }
+}
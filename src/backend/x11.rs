@@ -0,0 +1,106 @@
+use super::{ClipboardBackend, Selection, XclipBackend};
+use crate::owner::{self, Offer};
+use clipboard::{ClipboardContext, ClipboardProvider};
+use std::env;
+use std::io;
+use std::path::PathBuf;
+
+/// Backend using the `clipboard` crate's X11 context for text and `xclip`
+/// for images, which the crate cannot handle.
+///
+/// The crate only knows the CLIPBOARD selection, so text in any other
+/// selection goes through `xclip` as well. Content in several formats at
+/// once is served by a background `clipmate serve-selection`, see
+/// `owner::serve_from`.
+pub struct X11Backend {
+    selection: Selection,
+    use_context: bool,
+    xclip: XclipBackend,
+    /// The executable serving several formats, if not the running one.
+    server: Option<PathBuf>,
+}
+
+impl Default for X11Backend {
+    fn default() -> Self {
+        X11Backend::for_selection(Selection::Clipboard)
+    }
+}
+
+impl X11Backend {
+    pub fn for_selection(selection: Selection) -> X11Backend {
+        X11Backend {
+            selection,
+            use_context: selection == Selection::Clipboard,
+            xclip: XclipBackend::new(selection),
+            server: None,
+        }
+    }
+
+    /// Serves content in several formats by running `exe serve-selection`
+    /// instead of the running executable.
+    pub fn serve_with(mut self, exe: PathBuf) -> X11Backend {
+        self.server = Some(exe);
+        self
+    }
+}
+
+fn context() -> io::Result<ClipboardContext> {
+    ClipboardProvider::new().map_err(|e| io::Error::other(e.to_string()))
+}
+
+impl ClipboardBackend for X11Backend {
+    fn select(&mut self, selection: Selection) -> io::Result<()> {
+        self.selection = selection;
+        self.use_context = selection == Selection::Clipboard;
+        self.xclip.select(selection)
+    }
+
+    fn read_text(&mut self) -> io::Result<String> {
+        if !self.use_context {
+            return self.xclip.read_text();
+        }
+        context()?
+            .get_contents()
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    fn read_type(&mut self, mime_type: &str) -> io::Result<Vec<u8>> {
+        self.xclip.read_type(mime_type)
+    }
+
+    fn available_types(&mut self) -> io::Result<Vec<String>> {
+        self.xclip.available_types()
+    }
+
+    fn write_text(&mut self, text: &str) -> io::Result<()> {
+        if !self.use_context {
+            return self.xclip.write_text(text);
+        }
+        context()?
+            .set_contents(text.to_string())
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    fn write_image(&mut self, mime_type: &str, data: &[u8]) -> io::Result<()> {
+        self.xclip.write_image(mime_type, data)
+    }
+
+    fn write_formats(
+        &mut self,
+        text: Option<&str>,
+        formats: &[(String, Vec<u8>)],
+    ) -> io::Result<()> {
+        match (text, formats) {
+            (Some(text), []) => return self.write_text(text),
+            (None, []) => return Ok(()),
+            (None, [(mime_type, data)]) => return self.write_image(mime_type, data),
+            _ => {}
+        }
+        let exe = match &self.server {
+            Some(exe) => exe.clone(),
+            None => env::current_exe()?,
+        };
+        let offer = Offer::new(text.map(str::to_string), formats.to_vec());
+        owner::serve_from(&exe, self.selection, &offer)
+    }
+}
@@ -0,0 +1,112 @@
+use super::{ClipboardBackend, Selection, Tool};
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Backend driving the `xclip` command line tool.
+///
+/// If `xclip` cannot be spawned the backend reports an empty clipboard
+/// until it tries again, `RETRY_AFTER` later.
+pub struct XclipBackend {
+    selection: Selection,
+    xclip: Tool,
+}
+
+impl Default for XclipBackend {
+    fn default() -> Self {
+        XclipBackend::new(Selection::Clipboard)
+    }
+}
+
+impl XclipBackend {
+    pub fn new(selection: Selection) -> XclipBackend {
+        XclipBackend {
+            selection,
+            xclip: Tool::new("xclip"),
+        }
+    }
+
+    /// Waits `retry_after` instead of `RETRY_AFTER` before running `xclip`
+    /// again once it could not be run.
+    pub fn retry_after(mut self, retry_after: Duration) -> XclipBackend {
+        self.xclip.set_retry_after(retry_after);
+        self
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.xclip.is_available()
+    }
+
+    fn read(&mut self, mime_type: &str) -> io::Result<Vec<u8>> {
+        if !self.xclip.is_available() {
+            return Ok(Vec::new());
+        }
+        let output = Command::new("xclip")
+            .args(["-selection", self.selection.name(), "-t", mime_type, "-o"])
+            .stderr(Stdio::null())
+            .output();
+
+        match output {
+            Ok(output) => {
+                self.xclip.succeeded();
+                Ok(output.stdout)
+            }
+            Err(e) => {
+                self.xclip.failed(&e);
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    fn write(&mut self, mime_type: &str, data: &[u8]) -> io::Result<()> {
+        let mut child = Command::new("xclip")
+            .args(["-selection", self.selection.name(), "-t", mime_type])
+            .stdin(Stdio::piped())
+            .spawn()
+            .inspect_err(|e| self.xclip.failed(e))?;
+        self.xclip.succeeded();
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(data)?;
+        }
+        // Like wl-copy, xclip forks to own the selection once it has read
+        // its input, so the parent exits right away and must be reaped.
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("xclip exited with {}", status)));
+        }
+        Ok(())
+    }
+}
+
+impl ClipboardBackend for XclipBackend {
+    fn select(&mut self, selection: Selection) -> io::Result<()> {
+        self.selection = selection;
+        Ok(())
+    }
+
+    fn read_text(&mut self) -> io::Result<String> {
+        let data = self.read("UTF8_STRING")?;
+        String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn read_type(&mut self, mime_type: &str) -> io::Result<Vec<u8>> {
+        self.read(mime_type)
+    }
+
+    fn available_types(&mut self) -> io::Result<Vec<String>> {
+        let data = self.read("TARGETS")?;
+        Ok(String::from_utf8_lossy(&data)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    fn write_text(&mut self, text: &str) -> io::Result<()> {
+        self.write("UTF8_STRING", text.as_bytes())
+    }
+
+    fn write_image(&mut self, mime_type: &str, data: &[u8]) -> io::Result<()> {
+        self.write(mime_type, data)
+    }
+}
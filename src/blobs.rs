@@ -0,0 +1,264 @@
+use crate::crypto::{self, Key};
+use crate::error::{ClipmateError, Result};
+use crate::persist;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Suffix of blobs stored zstd-compressed.
+const COMPRESSED_SUFFIX: &str = ".zst";
+
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// The outcome of `BlobStore::verify`.
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// How many referenced blobs were read back.
+    pub checked: usize,
+    /// Referenced blobs with no file.
+    pub missing: Vec<String>,
+    /// Referenced blobs that fail to decrypt or decompress, or whose
+    /// contents no longer match their id.
+    pub corrupt: Vec<String>,
+    /// Blob files no item refers to.
+    pub orphaned: Vec<PathBuf>,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.corrupt.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+/// Stores item contents too large to keep in the history, named by the hash
+/// of their contents so that identical contents are stored once.
+///
+/// A blob with id `abcdef...` lives in `ab/cdef...`, with a `.zst` suffix if
+/// it was compressed. Blobs are compressed before they are encrypted.
+///
+/// Reference counts are not persisted: the history is the source of truth,
+/// and `retain` is called for every blob it refers to when it is loaded.
+pub struct BlobStore {
+    root: PathBuf,
+    key: Option<Key>,
+    compress: bool,
+    refs: HashMap<String, usize>,
+}
+
+impl BlobStore {
+    pub fn new(root: PathBuf, key: Option<Key>, compress: bool) -> BlobStore {
+        BlobStore {
+            root,
+            key,
+            compress,
+            refs: HashMap::new(),
+        }
+    }
+
+    /// The id `data` is stored under.
+    pub fn id_of(&self, data: &[u8]) -> String {
+        crypto::content_id(self.key.as_ref(), data)
+    }
+
+    fn path(&self, id: &str, compressed: bool) -> Result<PathBuf> {
+        if id.len() < 3 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ClipmateError::InvalidInput(format!(
+                "invalid blob id '{}'",
+                id
+            )));
+        }
+        let mut name = id[2..].to_string();
+        if compressed {
+            name.push_str(COMPRESSED_SUFFIX);
+        }
+        Ok(self.root.join(&id[..2]).join(name))
+    }
+
+    /// The file holding blob `id`, if there is one.
+    fn find(&self, id: &str) -> Result<Option<PathBuf>> {
+        for compressed in [self.compress, !self.compress] {
+            let path = self.path(id, compressed)?;
+            if path.exists() {
+                return Ok(Some(path));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Stores `data` unless a blob with the same contents exists, takes a
+    /// reference to it and returns its id.
+    pub fn put(&mut self, data: &[u8]) -> Result<String> {
+        let id = self.id_of(data);
+        if self.find(&id)?.is_none() {
+            let path = self.path(&id, self.compress)?;
+            let contents = if self.compress {
+                zstd::encode_all(data, COMPRESSION_LEVEL)?
+            } else {
+                data.to_vec()
+            };
+            persist::create_dir(path.parent().unwrap())?;
+            persist::write_atomic(&path, &crypto::seal(self.key.as_ref(), contents)?)?;
+        }
+        self.retain(&id);
+        Ok(id)
+    }
+
+    /// Reads the contents of blob `id`.
+    pub fn get(&self, id: &str) -> Result<Vec<u8>> {
+        let path = self.find(id)?.ok_or_else(|| {
+            ClipmateError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("blob {} is missing", id),
+            ))
+        })?;
+        read_blob(self.key.as_ref(), &path)
+    }
+
+    /// Takes a reference to blob `id`, which is stored already.
+    pub fn retain(&mut self, id: &str) {
+        *self.refs.entry(id.to_string()).or_insert(0) += 1;
+    }
+
+    /// Drops a reference to blob `id`, deleting it once no reference is
+    /// left.
+    pub fn release(&mut self, id: &str) -> Result<()> {
+        let Some(count) = self.refs.get_mut(id) else {
+            return Ok(());
+        };
+        *count -= 1;
+        if *count > 0 {
+            return Ok(());
+        }
+        self.refs.remove(id);
+        for compressed in [false, true] {
+            match fs::remove_file(self.path(id, compressed)?) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// How many references blob `id` has.
+    pub fn refcount(&self, id: &str) -> usize {
+        self.refs.get(id).copied().unwrap_or(0)
+    }
+
+    /// Size of blob `id` on disk, or 0 if it is missing.
+    pub fn size(&self, id: &str) -> u64 {
+        match self.find(id) {
+            Ok(Some(path)) => fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// Lists every blob file with the id it is named after.
+    fn files(&self) -> Result<Vec<(String, PathBuf)>> {
+        let mut files = Vec::new();
+        let shards = match fs::read_dir(&self.root) {
+            Ok(shards) => shards,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(files),
+            Err(e) => return Err(e.into()),
+        };
+        for shard in shards {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            let prefix = shard.file_name().to_string_lossy().into_owned();
+            for entry in fs::read_dir(shard.path())? {
+                let entry = entry?;
+                if !entry.file_type()?.is_file() {
+                    continue;
+                }
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let rest = name.strip_suffix(COMPRESSED_SUFFIX).unwrap_or(&name);
+                files.push((format!("{}{}", prefix, rest), entry.path()));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Deletes every blob file nothing refers to, including the leftovers of
+    /// interrupted writes. Returns how many were deleted.
+    pub fn gc(&self) -> Result<usize> {
+        let mut deleted = 0;
+        for (id, path) in self.files()? {
+            if self.refcount(&id) == 0 {
+                fs::remove_file(path)?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Reads back every referenced blob and checks it still matches its id.
+    pub fn verify(&self) -> Result<FsckReport> {
+        let mut report = FsckReport::default();
+        let mut ids: Vec<&String> = self.refs.keys().collect();
+        ids.sort();
+        for id in ids {
+            let Some(path) = self.find(id)? else {
+                report.missing.push(id.clone());
+                continue;
+            };
+            report.checked += 1;
+            match read_blob(self.key.as_ref(), &path) {
+                Ok(data) if self.id_of(&data) == *id => {}
+                Ok(_) | Err(ClipmateError::Crypto(_)) => report.corrupt.push(id.clone()),
+                Err(ClipmateError::Io(e)) if e.kind() != io::ErrorKind::NotFound => {
+                    report.corrupt.push(id.clone())
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        report.orphaned = self
+            .files()?
+            .into_iter()
+            .filter(|(id, _)| self.refcount(id) == 0)
+            .map(|(_, path)| path)
+            .collect();
+        report.orphaned.sort();
+        Ok(report)
+    }
+
+    /// Stores every referenced blob again under `key`, which changes their
+    /// ids, and returns the new id of each old one.
+    ///
+    /// The old files are left in place for `gc` to delete once nothing
+    /// refers to them any more, so that a history still naming them stays
+    /// readable until it is replaced.
+    pub fn rekey(&mut self, key: Key) -> Result<HashMap<String, String>> {
+        let mut contents = Vec::new();
+        for (id, &count) in &self.refs {
+            contents.push((id.clone(), count, self.get(id)?));
+        }
+        self.key = Some(key);
+        self.refs.clear();
+        let mut ids = HashMap::new();
+        for (old_id, count, data) in contents {
+            let id = self.id_of(&data);
+            let path = self.path(&id, self.compress)?;
+            let data = if self.compress {
+                zstd::encode_all(&data[..], COMPRESSION_LEVEL)?
+            } else {
+                data
+            };
+            persist::create_dir(path.parent().unwrap())?;
+            persist::write_atomic(&path, &crypto::seal(self.key.as_ref(), data)?)?;
+            *self.refs.entry(id.clone()).or_insert(0) += count;
+            ids.insert(old_id, id);
+        }
+        Ok(ids)
+    }
+}
+
+fn read_blob(key: Option<&Key>, path: &Path) -> Result<Vec<u8>> {
+    let data = crypto::open(key, fs::read(path)?)?;
+    if path.to_string_lossy().ends_with(COMPRESSED_SUFFIX) {
+        Ok(zstd::decode_all(&data[..])?)
+    } else {
+        Ok(data)
+    }
+}
@@ -0,0 +1,452 @@
+use crate::backend::Selection;
+use crate::error::{ClipmateError, Result};
+use crate::paths::{self, DataPaths};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CONFIG_FILE: &str = "clipmate/config.toml";
+const SOCKET_FILE: &str = "clipmate.sock";
+
+/// Daemon and storage settings, read from
+/// `$XDG_CONFIG_HOME/clipmate/config.toml`.
+///
+/// Every field has a default, so the file only needs to list the settings
+/// that differ from them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub daemon: DaemonConfig,
+    pub capture: CaptureConfig,
+    pub history: HistoryConfig,
+    pub secrets: SecretsConfig,
+    pub storage: StorageConfig,
+    pub encryption: EncryptionConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// How the daemon learns that something was copied.
+    pub watch: WatchMode,
+    /// How often the clipboard is polled while it keeps changing, in
+    /// milliseconds.
+    pub poll_interval_ms: u64,
+    /// How rarely the clipboard is polled once it has stopped changing, in
+    /// milliseconds.
+    pub max_poll_interval_ms: u64,
+    /// Whether the daemon takes over the clipboard after each copy, so that
+    /// it can still be pasted once the application that copied it exits.
+    /// Only supported on X11.
+    pub own_clipboard: bool,
+    /// Overrides where the daemon listens for commands.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket: Option<PathBuf>,
+    /// The least severe messages the daemon logs.
+    pub log_level: LogLevel,
+}
+
+/// How the daemon learns that something was copied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchMode {
+    /// Notifications from the display server if it sends them, polling
+    /// otherwise.
+    Auto,
+    /// Notifications from the display server only.
+    Events,
+    /// Polling only.
+    Poll,
+}
+
+/// How much the daemon logs, from only errors to everything it does.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// The selections to watch. `selection` with a single name is accepted
+    /// too, as older versions only watched one.
+    #[serde(alias = "selection", deserialize_with = "one_or_many")]
+    pub selections: Vec<Selection>,
+    /// How long, in milliseconds, the primary and secondary selections must
+    /// stay unchanged to be recorded, so that a selection being dragged
+    /// with the mouse is only recorded once it is complete.
+    pub debounce_ms: u64,
+    /// Image MIME types to capture, in order of preference.
+    pub image_types: Vec<String>,
+    /// Images of this many bytes or fewer are ignored.
+    pub min_image_size: usize,
+    /// Other MIME types recorded alongside copied text and images when the
+    /// clipboard offers them, and offered again when the item is restored.
+    pub formats: Vec<String>,
+    /// Whether copied files are stored along with their paths, so that they
+    /// can be pasted again after they were deleted or changed.
+    pub snapshot_files: bool,
+    /// Copied files larger than this many bytes are never stored.
+    pub snapshot_max_size: u64,
+    /// Regular expressions; text matching any of them is not recorded.
+    pub ignore: Vec<String>,
+}
+
+/// Retention limits for the whole history, with separate limits for text
+/// and image items in the `text` and `image` tables.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_items: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<String>,
+    pub text: RetentionPolicy,
+    pub image: RetentionPolicy,
+}
+
+/// Limits beyond which the oldest items are pruned. Unset limits do not
+/// apply.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionPolicy {
+    /// Number of items to keep.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_items: Option<usize>,
+    /// Total size of the kept items, in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    /// Age after which items are dropped, such as `30d` or `12h`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<String>,
+}
+
+impl RetentionPolicy {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_items.is_none() && self.max_bytes.is_none() && self.max_age.is_none()
+    }
+
+    /// `max_age` as a duration. Invalid values are rejected when the config
+    /// is parsed, so they are treated as unset here.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+            .as_deref()
+            .and_then(|age| parse_duration(age).ok())
+    }
+
+    fn validate(&self, table: &str) -> Result<()> {
+        if let Some(age) = &self.max_age {
+            parse_duration(age)
+                .map_err(|e| ClipmateError::Config(format!("{}.max_age: {}", table, e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl HistoryConfig {
+    /// The limits applying to the history as a whole.
+    pub fn overall(&self) -> RetentionPolicy {
+        RetentionPolicy {
+            max_items: self.max_items,
+            max_bytes: self.max_bytes,
+            max_age: self.max_age.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Overrides the XDG data directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<PathBuf>,
+    /// How many changes a journal history may hold before the daemon
+    /// compacts it into a snapshot.
+    pub compact_after: usize,
+    /// Texts longer than this many bytes are kept in the blob store, with
+    /// only their beginning in the history.
+    pub blob_threshold: usize,
+    /// Whether new blobs are compressed with zstd.
+    pub compress: bool,
+}
+
+/// Encryption of the history file and images at rest.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncryptionConfig {
+    pub enabled: bool,
+    /// File whose contents serve as the key material. Without it the key
+    /// comes from a passphrase entered with `clipmate unlock`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyfile: Option<PathBuf>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            data_dir: None,
+            compact_after: 1000,
+            blob_threshold: 16 * 1024,
+            compress: false,
+        }
+    }
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig {
+            watch: WatchMode::Auto,
+            poll_interval_ms: 250,
+            max_poll_interval_ms: 500,
+            own_clipboard: false,
+            socket: None,
+            log_level: LogLevel::Info,
+        }
+    }
+}
+
+/// What happens to copied text that looks like a secret.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretAction {
+    /// The item is not recorded.
+    Skip,
+    /// The item is recorded with the secrets replaced by a marker.
+    Mask,
+    /// The item is recorded as is and deleted after `expire_after`.
+    Expire,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretsConfig {
+    /// Whether copied text is checked for secrets at all.
+    pub enabled: bool,
+    pub action: SecretAction,
+    /// How long secrets are kept with the `expire` action.
+    pub expire_after: String,
+    /// Also flag long random-looking tokens. Catches more secrets but may
+    /// flag harmless identifiers.
+    pub entropy: bool,
+    /// Extra regular expressions marking secrets.
+    pub patterns: Vec<String>,
+}
+
+impl Default for SecretsConfig {
+    fn default() -> Self {
+        SecretsConfig {
+            enabled: true,
+            action: SecretAction::Skip,
+            expire_after: "10m".to_string(),
+            entropy: false,
+            patterns: Vec::new(),
+        }
+    }
+}
+
+/// Deserializes either a single value or a list of them.
+fn one_or_many<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            selections: vec![Selection::Clipboard],
+            debounce_ms: 0,
+            image_types: vec!["image/png".to_string()],
+            min_image_size: 50,
+            formats: vec![
+                "text/html".to_string(),
+                "text/rtf".to_string(),
+                "text/uri-list".to_string(),
+            ],
+            snapshot_files: false,
+            snapshot_max_size: 10 * 1024 * 1024,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Default location of the config file.
+    pub fn default_path() -> Option<PathBuf> {
+        paths::xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join(CONFIG_FILE))
+    }
+
+    /// Reads the config at `path`, or at the default location if `path` is
+    /// `None`. A missing default file yields the default config, a missing
+    /// explicit one is an error.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Config::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => Config::parse(&contents).map_err(|e| match e {
+                ClipmateError::Config(msg) => {
+                    ClipmateError::Config(format!("{}: {}", path.display(), msg))
+                }
+                e => e,
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Config> {
+        let config: Config =
+            toml::from_str(contents).map_err(|e| ClipmateError::Config(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.daemon.poll_interval_ms == 0 {
+            return Err(ClipmateError::Config(
+                "daemon.poll_interval_ms must be greater than 0".to_string(),
+            ));
+        }
+        if self.daemon.max_poll_interval_ms < self.daemon.poll_interval_ms {
+            return Err(ClipmateError::Config(
+                "daemon.max_poll_interval_ms must not be less than daemon.poll_interval_ms"
+                    .to_string(),
+            ));
+        }
+        if self.capture.selections.is_empty() {
+            return Err(ClipmateError::Config(
+                "capture.selections must list at least one selection".to_string(),
+            ));
+        }
+        for pattern in &self.capture.ignore {
+            regex::Regex::new(pattern).map_err(|e| {
+                ClipmateError::Config(format!("invalid ignore pattern '{}': {}", pattern, e))
+            })?;
+        }
+        parse_duration(&self.secrets.expire_after)
+            .map_err(|e| ClipmateError::Config(format!("secrets.expire_after: {}", e)))?;
+        for pattern in &self.secrets.patterns {
+            regex::Regex::new(pattern).map_err(|e| {
+                ClipmateError::Config(format!("invalid secret pattern '{}': {}", pattern, e))
+            })?;
+        }
+        self.history.overall().validate("history")?;
+        self.history.text.validate("history.text")?;
+        self.history.image.validate("history.image")?;
+        Ok(())
+    }
+
+    /// Resolves where history and images are stored.
+    pub fn data_paths(&self) -> Result<DataPaths> {
+        DataPaths::resolve(self.storage.data_dir.as_deref())
+    }
+
+    /// The settings changed in `reloaded` that a running daemon cannot
+    /// apply, as it only reads them when it starts.
+    pub fn restart_needed(&self, reloaded: &Config) -> Vec<&'static str> {
+        let changed = [
+            ("daemon.watch", self.daemon.watch != reloaded.daemon.watch),
+            (
+                "daemon.own_clipboard",
+                self.daemon.own_clipboard != reloaded.daemon.own_clipboard,
+            ),
+            (
+                "daemon.socket",
+                self.daemon.socket != reloaded.daemon.socket,
+            ),
+            (
+                "daemon.log_level",
+                self.daemon.log_level != reloaded.daemon.log_level,
+            ),
+            // Polling reads whichever selections are configured, while
+            // notifications are only asked for those watched at the start.
+            (
+                "capture.selections",
+                self.daemon.watch != WatchMode::Poll
+                    && self.capture.selections != reloaded.capture.selections,
+            ),
+            (
+                "storage.data_dir",
+                self.data_paths().ok() != reloaded.data_paths().ok(),
+            ),
+            ("encryption", self.encryption != reloaded.encryption),
+        ];
+        changed
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(setting, _)| setting)
+            .collect()
+    }
+
+    /// Where the daemon listens for commands: `daemon.socket` if set,
+    /// `$XDG_RUNTIME_DIR/clipmate.sock` otherwise, or the data directory
+    /// if there is no runtime directory.
+    pub fn socket_path(&self) -> Result<PathBuf> {
+        if let Some(socket) = &self.daemon.socket {
+            return Ok(socket.clone());
+        }
+        match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir).join(SOCKET_FILE)),
+            _ => Ok(self.data_paths()?.data_dir().join(SOCKET_FILE)),
+        }
+    }
+
+    /// Renders the config as TOML, as `clipmate config show` prints it.
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|e| ClipmateError::Config(e.to_string()))
+    }
+}
+
+/// Parses a duration such as `90s`, `15m`, `12h`, `7d` or `2w`.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let invalid = || {
+        ClipmateError::InvalidInput(format!(
+            "invalid duration '{}', expected a number followed by s, m, h, d or w",
+            value
+        ))
+    };
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    amount
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
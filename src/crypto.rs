@@ -0,0 +1,291 @@
+use crate::config::EncryptionConfig;
+use crate::error::{ClipmateError, Result};
+use crate::paths::{self, DataPaths};
+use crate::persist;
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+/// Prefix marking encrypted files; plaintext history always starts with `{`
+/// and images with their format's own magic bytes.
+const MAGIC: &[u8; 8] = b"CLIPENC1";
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+const KEY_FILE: &str = "key.json";
+const CHECK_PLAINTEXT: &[u8] = b"clipmate";
+
+/// A 256-bit key for XChaCha20-Poly1305, wiped from memory on drop.
+#[derive(Clone)]
+pub struct Key {
+    bytes: [u8; 32],
+    /// Whether `open` passes data that is not encrypted through instead of
+    /// refusing it.
+    accepts_plaintext: bool,
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+impl Key {
+    /// Derives a key from a passphrase, or the contents of a keyfile, with
+    /// Argon2id.
+    pub fn derive(secret: &[u8], salt: &[u8]) -> Result<Key> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(secret, salt, &mut key)
+            .map_err(|e| ClipmateError::Crypto(e.to_string()))?;
+        Ok(Key {
+            bytes: key,
+            accepts_plaintext: false,
+        })
+    }
+
+    /// The same key, also reading data that is not encrypted as it is. Only
+    /// `clipmate rekey` uses it, to encrypt what was written before
+    /// encryption was turned on.
+    pub fn accepting_plaintext(mut self) -> Key {
+        self.accepts_plaintext = true;
+        self
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Key> {
+        let mut key = [0u8; 32];
+        if bytes.len() != key.len() {
+            return None;
+        }
+        key.copy_from_slice(bytes);
+        Some(Key {
+            bytes: key,
+            accepts_plaintext: false,
+        })
+    }
+}
+
+/// Whether `data` was produced by `encrypt`.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypts and authenticates `plaintext` under a fresh random nonce.
+pub fn encrypt(key: &Key, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new((&key.bytes).into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| ClipmateError::Crypto("encryption failed".to_string()))?;
+
+    let mut data = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    Ok(data)
+}
+
+/// Decrypts data written by `encrypt`, failing if it was encrypted under a
+/// different key or tampered with.
+pub fn decrypt(key: &Key, data: &[u8]) -> Result<Vec<u8>> {
+    if !is_encrypted(data) || data.len() < MAGIC.len() + NONCE_LEN {
+        return Err(ClipmateError::Crypto("data is not encrypted".to_string()));
+    }
+    let (nonce, ciphertext) = data[MAGIC.len()..].split_at(NONCE_LEN);
+    XChaCha20Poly1305::new((&key.bytes).into())
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| ClipmateError::Crypto("wrong key or corrupted data".to_string()))
+}
+
+/// Encrypts `data` if a key is given and passes it through otherwise.
+pub fn seal(key: Option<&Key>, data: Vec<u8>) -> Result<Vec<u8>> {
+    match key {
+        Some(key) => encrypt(key, &data),
+        None => Ok(data),
+    }
+}
+
+/// Decrypts `data` if it is encrypted and passes it through otherwise.
+///
+/// With a key, data that is not encrypted is refused unless the key accepts
+/// plaintext, so that a file swapped for a plaintext one cannot slip items
+/// into an encrypted history.
+pub fn open(key: Option<&Key>, data: Vec<u8>) -> Result<Vec<u8>> {
+    if is_encrypted(&data) {
+        return match key {
+            Some(key) => decrypt(key, &data),
+            None => Err(ClipmateError::Locked),
+        };
+    }
+    match key {
+        Some(key) if !key.accepts_plaintext => Err(ClipmateError::Crypto(
+            "found unencrypted data, run 'clipmate rekey' to encrypt the history".to_string(),
+        )),
+        _ => Ok(data),
+    }
+}
+
+/// Names content by its SHA-256 hash, keyed when encryption is enabled so
+/// that the name reveals nothing about the content to whoever lacks the key.
+pub fn content_id(key: Option<&Key>, data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    if let Some(key) = key {
+        hasher.update(key.bytes);
+    }
+    hasher.update(data);
+    to_hex(&hasher.finalize())
+}
+
+/// Per data directory key parameters, stored in `key.json`: the Argon2 salt
+/// and a known plaintext encrypted under the key, to check passphrases.
+#[derive(Serialize, Deserialize)]
+pub struct KeyParams {
+    salt: String,
+    check: String,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl KeyParams {
+    fn path(paths: &DataPaths) -> PathBuf {
+        paths.data_dir().join(KEY_FILE)
+    }
+
+    pub fn load(paths: &DataPaths) -> Result<Option<KeyParams>> {
+        match fs::read_to_string(KeyParams::path(paths)) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Derives a key from `secret` under a fresh salt, returning the key
+    /// and the parameters to store for it.
+    pub fn generate(secret: &[u8]) -> Result<(Key, KeyParams)> {
+        let mut salt = [0u8; SALT_LEN];
+        chacha20poly1305::aead::rand_core::RngCore::fill_bytes(&mut OsRng, &mut salt);
+        let key = Key::derive(secret, &salt)?;
+        let params = KeyParams {
+            salt: to_hex(&salt),
+            check: to_hex(&encrypt(&key, CHECK_PLAINTEXT)?),
+        };
+        Ok((key, params))
+    }
+
+    pub fn save(&self, paths: &DataPaths) -> Result<()> {
+        persist::create_dir(paths.data_dir())?;
+        persist::write_atomic(&KeyParams::path(paths), &serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Derives the key for `secret`, failing if it is not the one these
+    /// parameters were generated with.
+    pub fn unlock(&self, secret: &[u8]) -> Result<Key> {
+        let invalid = || ClipmateError::Crypto("key.json is corrupted".to_string());
+        let salt = from_hex(&self.salt).ok_or_else(invalid)?;
+        let check = from_hex(&self.check).ok_or_else(invalid)?;
+        let key = Key::derive(secret, &salt)?;
+        match decrypt(&key, &check) {
+            Ok(plaintext) if plaintext == CHECK_PLAINTEXT => Ok(key),
+            _ => Err(ClipmateError::Crypto(
+                "wrong passphrase or keyfile".to_string(),
+            )),
+        }
+    }
+}
+
+/// Where `clipmate unlock` caches the key for `paths`: a file under
+/// `$XDG_RUNTIME_DIR`, which lives in memory and is removed at logout.
+fn cached_key_path(paths: &DataPaths) -> Result<PathBuf> {
+    let runtime_dir = paths::runtime_dir().ok_or_else(|| {
+        ClipmateError::Crypto(
+            "XDG_RUNTIME_DIR is not set, use encryption.keyfile instead".to_string(),
+        )
+    })?;
+    let id = Sha256::digest(paths.data_dir().as_os_str().as_encoded_bytes());
+    Ok(runtime_dir.join(format!("key-{}", &to_hex(&id)[..16])))
+}
+
+/// Caches `key` for later `clipmate` invocations and the daemon.
+pub fn cache_key(paths: &DataPaths, key: &Key) -> Result<()> {
+    let path = cached_key_path(paths)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)?;
+    file.write_all(&key.bytes)?;
+    Ok(())
+}
+
+/// Removes the cached key, if any.
+pub fn forget_key(paths: &DataPaths) -> Result<()> {
+    match fs::remove_file(cached_key_path(paths)?) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn read_cached_key(paths: &DataPaths) -> Result<Option<Key>> {
+    match fs::read(cached_key_path(paths)?) {
+        Ok(mut bytes) => {
+            let key = Key::from_bytes(&bytes);
+            bytes.zeroize();
+            Ok(key)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Unlocks the key with a keyfile, creating the key parameters on first use.
+pub fn key_from_keyfile(paths: &DataPaths, keyfile: &Path) -> Result<Key> {
+    let mut secret = fs::read(keyfile)?;
+    let key = match KeyParams::load(paths)? {
+        Some(params) => params.unlock(&secret),
+        None => KeyParams::generate(&secret).and_then(|(key, params)| {
+            params.save(paths)?;
+            Ok(key)
+        }),
+    };
+    secret.zeroize();
+    key
+}
+
+/// Finds the key to use for `paths`: none if encryption is disabled, the
+/// keyfile's if one is configured, and the one cached by `clipmate unlock`
+/// otherwise. Fails with `ClipmateError::Locked` if that cache is empty.
+pub fn load_key(config: &EncryptionConfig, paths: &DataPaths) -> Result<Option<Key>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    if let Some(keyfile) = &config.keyfile {
+        return key_from_keyfile(paths, keyfile).map(Some);
+    }
+    match read_cached_key(paths)? {
+        Some(key) => Ok(Some(key)),
+        None => Err(ClipmateError::Locked),
+    }
+}
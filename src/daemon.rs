@@ -0,0 +1,283 @@
+use crate::backend::ClipboardBackend;
+use crate::backend::Selection;
+use crate::config::DaemonConfig;
+use crate::error::{ClipmateError, Result};
+use crate::logging::RateLimit;
+use crate::manager::{ClipboardItem, ClipboardManager};
+use crate::owner::Owner;
+use crate::watch::{Event, PollWatcher, Watcher};
+use std::fmt;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How often expired items are removed and the history compacted.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the same failure is logged while it keeps happening.
+const REPEATED_FAILURE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A failed daemon task. The daemon carries on after reporting it.
+#[derive(Debug)]
+pub enum Failure {
+    ReadText(ClipmateError),
+    ReadImage(ClipmateError),
+    RemoveExpired(ClipmateError),
+    Compact(ClipmateError),
+    /// Loading the history changed by another process failed.
+    Reload(ClipmateError),
+    /// The watcher stopped sending events, and polling took over.
+    Watcher(&'static str, io::Error),
+    /// Taking over the clipboard failed.
+    Own(ClipmateError),
+}
+
+impl Failure {
+    /// Short name of the failed task, logged with the failure.
+    pub fn task(&self) -> &'static str {
+        match self {
+            Failure::ReadText(_) => "read_text",
+            Failure::ReadImage(_) => "read_image",
+            Failure::RemoveExpired(_) => "remove_expired",
+            Failure::Compact(_) => "compact",
+            Failure::Reload(_) => "reload",
+            Failure::Watcher(..) => "watch",
+            Failure::Own(_) => "own",
+        }
+    }
+
+    /// Logs the failure, noting how many times the same failure was held
+    /// back since it was last logged. The daemon recovers from a failing
+    /// watcher, so that is only a warning.
+    fn log(&self, held_back: usize) {
+        let repeated = match held_back {
+            0 => String::new(),
+            n => format!(" (repeated {} times since last logged)", n),
+        };
+        match self {
+            Failure::Watcher(..) => tracing::warn!(task = self.task(), "{}{}", self, repeated),
+            _ => tracing::error!(task = self.task(), "{}{}", self, repeated),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::ReadText(e) => {
+                write!(
+                    f,
+                    "Error while getting text content from the clipboard: {}",
+                    e
+                )
+            }
+            Failure::ReadImage(e) => {
+                write!(
+                    f,
+                    "Error while getting image content from the clipboard: {}",
+                    e
+                )
+            }
+            Failure::RemoveExpired(e) => write!(f, "Error while removing expired items: {}", e),
+            Failure::Compact(e) => write!(f, "Error while compacting the history: {}", e),
+            Failure::Reload(e) => write!(f, "Error while reloading the history: {}", e),
+            Failure::Own(e) => write!(f, "Error while taking over the clipboard: {}", e),
+            Failure::Watcher(name, e) => {
+                write!(
+                    f,
+                    "Stopped watching the clipboard with {}, polling it: {}",
+                    name, e
+                )
+            }
+        }
+    }
+}
+
+/// An item the daemon recorded, with its number.
+type Recorded = (usize, ClipboardItem);
+
+/// Everyone to tell about the items the daemon records.
+#[derive(Clone, Default)]
+pub struct Subscribers(Arc<Mutex<Vec<Sender<Recorded>>>>);
+
+impl Subscribers {
+    /// Receives every item recorded from now on, with its number.
+    pub fn subscribe(&self) -> Receiver<Recorded> {
+        let (sender, receiver) = mpsc::channel();
+        self.0.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends `item` to every subscriber, forgetting those that are gone.
+    fn publish(&self, item_number: usize, item: &ClipboardItem) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.send((item_number, item.clone())).is_ok());
+    }
+}
+
+/// Records what is copied, reading the clipboard whenever its watcher
+/// reports a change.
+///
+/// The manager is shared with the threads answering commands, which lock
+/// it between the daemon's reads.
+pub struct Daemon<B: ClipboardBackend> {
+    manager: Arc<Mutex<ClipboardManager<B>>>,
+    watcher: Box<dyn Watcher>,
+    /// Serves what was copied last, if the daemon owns the clipboard.
+    owner: Option<Box<dyn Owner>>,
+    subscribers: Subscribers,
+    last_maintenance: Instant,
+    /// The daemon settings the watcher was last configured with, to notice
+    /// when SIGHUP reloaded them.
+    config: DaemonConfig,
+}
+
+impl<B: ClipboardBackend> Daemon<B> {
+    pub fn new(manager: ClipboardManager<B>, watcher: Box<dyn Watcher>) -> Daemon<B> {
+        Daemon {
+            config: manager.config().daemon.clone(),
+            manager: Arc::new(Mutex::new(manager)),
+            watcher,
+            owner: None,
+            subscribers: Subscribers::default(),
+            last_maintenance: Instant::now(),
+        }
+    }
+
+    /// Makes the daemon take over the clipboard through `owner` whenever
+    /// something new is copied to it.
+    pub fn set_owner(&mut self, owner: Box<dyn Owner>) {
+        self.owner = Some(owner);
+    }
+
+    pub fn manager(&self) -> MutexGuard<'_, ClipboardManager<B>> {
+        self.manager.lock().unwrap()
+    }
+
+    /// The manager, for threads answering commands while the daemon runs.
+    pub fn shared_manager(&self) -> Arc<Mutex<ClipboardManager<B>>> {
+        self.manager.clone()
+    }
+
+    pub fn subscribers(&self) -> Subscribers {
+        self.subscribers.clone()
+    }
+
+    /// Name of the watcher in use, which changes to `poll` if the one the
+    /// daemon started with fails.
+    pub fn watcher_name(&self) -> &'static str {
+        self.watcher.name()
+    }
+
+    /// Records what is in the clipboard now, then what is copied, forever,
+    /// logging what fails. A failure that keeps happening is only logged
+    /// once every `REPEATED_FAILURE_INTERVAL`.
+    pub fn run(mut self) -> ! {
+        let mut limit = RateLimit::new(REPEATED_FAILURE_INTERVAL);
+        let mut failures = self.check();
+        loop {
+            for failure in failures {
+                if let Some(held_back) = limit.check(&failure.to_string()) {
+                    failure.log(held_back);
+                }
+            }
+            failures = self.step();
+        }
+    }
+
+    /// Waits for the next change, or until a task is due, and handles it.
+    pub fn step(&mut self) -> Vec<Failure> {
+        let config = self.manager().config().daemon.clone();
+        if config != self.config {
+            self.watcher.reconfigure(&config);
+            self.config = config;
+        }
+
+        let mut failures = Vec::new();
+        let now = Instant::now();
+        let mut timeout =
+            (self.last_maintenance + MAINTENANCE_INTERVAL).saturating_duration_since(now);
+        let deadline = self.manager().debounce_deadline();
+        if let Some(deadline) = deadline {
+            timeout = timeout.min(deadline.saturating_duration_since(now));
+        }
+
+        match self.watcher.wait(timeout) {
+            Ok(Event::Changed) => failures.extend(self.check()),
+            Ok(Event::Timeout) => {
+                let deadline = self.manager().debounce_deadline();
+                if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                    failures.extend(self.check());
+                }
+            }
+            Err(e) => {
+                failures.push(Failure::Watcher(self.watcher.name(), e));
+                let poll = PollWatcher::from_config(&self.manager().config().daemon);
+                self.watcher = Box::new(poll);
+            }
+        }
+
+        if self.last_maintenance.elapsed() >= MAINTENANCE_INTERVAL {
+            self.last_maintenance = Instant::now();
+            let mut manager = self.manager.lock().unwrap();
+            if let Err(e) = manager.remove_expired() {
+                failures.push(Failure::RemoveExpired(e));
+            }
+            if let Err(e) = manager.compact_history() {
+                failures.push(Failure::Compact(e));
+            }
+        }
+        failures
+    }
+
+    /// Reads the watched selections, tells the watcher whether anything new
+    /// was recorded, and then the subscribers, and takes over the clipboard
+    /// if it was copied to it.
+    fn check(&mut self) -> Vec<Failure> {
+        let mut failures = Vec::new();
+        let mut manager = self.manager.lock().unwrap();
+        // Items deleted by another process must not pass for new ones.
+        if let Err(e) = manager.refresh() {
+            failures.push(Failure::Reload(e));
+        }
+        let last_id = |manager: &ClipboardManager<B>| manager.get_history().last().map(|i| i.id);
+        let before = last_id(&manager);
+        let (text, image) = manager.update_content();
+        if let Err(e) = text {
+            failures.push(Failure::ReadText(e));
+        }
+        if let Err(e) = image {
+            failures.push(Failure::ReadImage(e));
+        }
+        let changed = last_id(&manager) != before;
+        self.watcher.checked(changed);
+        if let (true, Some(newest)) = (changed, manager.get_history().last()) {
+            self.subscribers
+                .publish(manager.get_history().len(), newest);
+            if let Some(owner) = &mut self.owner {
+                if let Err(e) = own_newest(owner.as_mut(), &manager) {
+                    failures.push(Failure::Own(e));
+                }
+            }
+        }
+        failures
+    }
+}
+
+/// Serves the newest item from the clipboard if it was copied there.
+/// Other selections are left to their owners, as taking them over would
+/// clear what is selected in the application.
+fn own_newest<B: ClipboardBackend>(
+    owner: &mut dyn Owner,
+    manager: &ClipboardManager<B>,
+) -> Result<()> {
+    let history = manager.get_history();
+    if history.last().map(|item| item.selection) != Some(Selection::Clipboard) {
+        return Ok(());
+    }
+    let offer = manager.offer(history.len())?;
+    owner.own(offer).map_err(ClipmateError::Backend)
+}
@@ -0,0 +1,338 @@
+use crate::config::Config;
+use crate::crypto;
+use crate::error::{ClipmateError, Result};
+use crate::lock::{self, HistoryLock};
+use crate::paths::{self, DataPaths};
+use crate::store::{self, StoreFormat};
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use x11rb::connection::RequestConnection;
+use x11rb::protocol::xfixes;
+
+/// How a check came out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Something works less well than it could.
+    Warning,
+    /// Something the daemon needs does not work.
+    Problem,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Problem => "problem",
+        })
+    }
+}
+
+/// The outcome of checking one thing clipmate depends on.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// The display server clipmate talks to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Session {
+    X11,
+    Wayland,
+}
+
+fn env_value(var: &str) -> Option<String> {
+    env::var_os(var)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string_lossy().into_owned())
+}
+
+/// Checks the configuration at `config_path`, or the default one, with the
+/// data directory overridden by `data_dir`, and then everything the daemon
+/// needs: the display server, the clipboard tools, the storage and the
+/// daemon itself. Checks that depend on the configuration use the defaults
+/// if it cannot be loaded.
+pub fn run(config_path: Option<&Path>, data_dir: Option<&Path>) -> Vec<Check> {
+    let (config_check, mut config) = match Config::load(config_path) {
+        Ok(config) => {
+            let detail = match config_path
+                .map(Path::to_path_buf)
+                .or_else(Config::default_path)
+            {
+                Some(path) if path.exists() => format!("loaded {}", path.display()),
+                _ => "no configuration file, using the defaults".to_string(),
+            };
+            (Check::new("config", Status::Ok, detail), config)
+        }
+        Err(e) => (
+            Check::new("config", Status::Problem, e.to_string()),
+            Config::default(),
+        ),
+    };
+    if let Some(data_dir) = data_dir {
+        config.storage.data_dir = Some(data_dir.to_path_buf());
+    }
+
+    let mut checks = vec![config_check];
+    let session = check_session(&mut checks);
+    check_tools(session, &mut checks);
+    check_storage(&config, &mut checks);
+    checks
+}
+
+fn check_session(checks: &mut Vec<Check>) -> Option<Session> {
+    let session_type = env_value("XDG_SESSION_TYPE")
+        .map(|kind| format!(", XDG_SESSION_TYPE={}", kind))
+        .unwrap_or_default();
+    if let Some(display) = env_value("WAYLAND_DISPLAY") {
+        checks.push(Check::new(
+            "session",
+            Status::Ok,
+            format!("Wayland (WAYLAND_DISPLAY={}{})", display, session_type),
+        ));
+        return Some(Session::Wayland);
+    }
+    let Some(display) = env_value("DISPLAY") else {
+        checks.push(Check::new(
+            "session",
+            Status::Problem,
+            "neither WAYLAND_DISPLAY nor DISPLAY is set, so there is no clipboard to watch",
+        ));
+        return None;
+    };
+    checks.push(Check::new(
+        "session",
+        Status::Ok,
+        format!("X11 (DISPLAY={}{})", display, session_type),
+    ));
+    checks.push(check_x_server(&display));
+    Some(Session::X11)
+}
+
+fn check_x_server(display: &str) -> Check {
+    let conn = match x11rb::connect(None) {
+        Ok((conn, _)) => conn,
+        Err(e) => {
+            return Check::new(
+                "x server",
+                Status::Problem,
+                format!("cannot connect to {}: {}", display, e),
+            )
+        }
+    };
+    match conn.extension_information(xfixes::X11_EXTENSION_NAME) {
+        Ok(Some(_)) => Check::new(
+            "x server",
+            Status::Ok,
+            format!(
+                "connected to {}, copies are watched through XFixes",
+                display
+            ),
+        ),
+        _ => Check::new(
+            "x server",
+            Status::Warning,
+            format!(
+                "connected to {}, but it lacks XFixes, so the daemon polls for copies",
+                display
+            ),
+        ),
+    }
+}
+
+/// The executable `name` in the directories of `path`, formatted like
+/// `$PATH`.
+pub fn find_tool(name: &str, path: &OsStr) -> Option<PathBuf> {
+    env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .find(|file| {
+            fs::metadata(file)
+                .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        })
+}
+
+fn check_tools(session: Option<Session>, checks: &mut Vec<Check>) {
+    let path = env::var_os("PATH").unwrap_or_default();
+    let tools: &[(&str, Status, &str)] = match session {
+        Some(Session::Wayland) => &[
+            ("wl-paste", Status::Problem, "the clipboard cannot be read"),
+            ("wl-copy", Status::Problem, "items cannot be copied back"),
+        ],
+        Some(Session::X11) => &[(
+            "xclip",
+            Status::Warning,
+            "only text of the clipboard is read and written, without images or other formats",
+        )],
+        None => &[
+            ("xclip", Status::Warning, "needed for images on X11"),
+            ("wl-paste", Status::Warning, "needed on Wayland"),
+            ("wl-copy", Status::Warning, "needed on Wayland"),
+        ],
+    };
+    for &(name, missing, consequence) in tools {
+        checks.push(match find_tool(name, &path) {
+            Some(file) => Check::new(name, Status::Ok, file.display().to_string()),
+            None => Check::new(name, missing, format!("not found in PATH; {}", consequence)),
+        });
+    }
+}
+
+/// Whether a file can be created in `dir`, which must exist.
+fn writable(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".doctor-{}", process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    fs::remove_file(probe)
+}
+
+fn check_storage(config: &Config, checks: &mut Vec<Check>) {
+    match config.data_paths() {
+        Ok(paths) => {
+            let usable = check_data_dir(&paths, checks);
+            if usable {
+                checks.push(check_history(config, &paths));
+            }
+            checks.push(check_daemon(config, &paths));
+        }
+        Err(e) => checks.push(Check::new("data directory", Status::Problem, e.to_string())),
+    }
+    checks.push(match paths::state_dir() {
+        Ok(dir) => check_log_dir(&dir),
+        Err(e) => Check::new("log directory", Status::Warning, e.to_string()),
+    });
+}
+
+/// Checks the data directory, returning whether the history in it can be
+/// checked.
+fn check_data_dir(paths: &DataPaths, checks: &mut Vec<Check>) -> bool {
+    let dir = paths.data_dir();
+    let check = |status, detail: String| {
+        Check::new(
+            "data directory",
+            status,
+            format!("{}: {}", dir.display(), detail),
+        )
+    };
+    let meta = match fs::metadata(dir) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            checks.push(check(Status::Ok, "not created yet".to_string()));
+            return false;
+        }
+        Err(e) => {
+            checks.push(check(Status::Problem, e.to_string()));
+            return false;
+        }
+    };
+    if !meta.is_dir() {
+        checks.push(check(Status::Problem, "not a directory".to_string()));
+        return false;
+    }
+    if let Err(e) = writable(dir) {
+        checks.push(check(Status::Problem, format!("not writable: {}", e)));
+        return false;
+    }
+    let mode = meta.permissions().mode();
+    if mode & 0o077 != 0 {
+        checks.push(check(
+            Status::Warning,
+            format!(
+                "accessible to other users (mode {:o}), run chmod 700 on it",
+                mode & 0o777
+            ),
+        ));
+    } else {
+        checks.push(check(Status::Ok, "writable".to_string()));
+    }
+    true
+}
+
+fn check_history(config: &Config, paths: &DataPaths) -> Check {
+    let format = StoreFormat::detect(paths);
+    let load = || -> Result<usize> {
+        let key = crypto::load_key(&config.encryption, paths)?;
+        let lock = HistoryLock::open(paths)?;
+        let _guard = lock.shared()?;
+        let history = store::open(format, paths, key, &config.storage)?.load()?;
+        Ok(history.items.len())
+    };
+    match load() {
+        Ok(count) => Check::new(
+            "history",
+            Status::Ok,
+            format!(
+                "{} item{} in the {} store",
+                count,
+                if count == 1 { "" } else { "s" },
+                format!("{:?}", format).to_lowercase()
+            ),
+        ),
+        Err(ClipmateError::Locked) => Check::new(
+            "history",
+            Status::Warning,
+            "encrypted and locked, run 'clipmate unlock' before starting the daemon",
+        ),
+        Err(e) => Check::new("history", Status::Problem, e.to_string()),
+    }
+}
+
+fn check_daemon(config: &Config, paths: &DataPaths) -> Check {
+    let socket = match config.socket_path() {
+        Ok(socket) => socket,
+        Err(e) => return Check::new("daemon", Status::Problem, e.to_string()),
+    };
+    match lock::running_daemon(&paths.pid_file()) {
+        Ok(Some(pid)) => Check::new(
+            "daemon",
+            Status::Ok,
+            format!("running (pid {}), listening at {}", pid, socket.display()),
+        ),
+        Ok(None) => Check::new(
+            "daemon",
+            Status::Warning,
+            format!(
+                "not running, start it with 'clipmate daemon start'; it would listen at {}",
+                socket.display()
+            ),
+        ),
+        Err(e) => Check::new("daemon", Status::Problem, e.to_string()),
+    }
+}
+
+fn check_log_dir(dir: &Path) -> Check {
+    let detail = |detail: &str| format!("{}: {}", dir.display(), detail);
+    if !dir.exists() {
+        return Check::new("log directory", Status::Ok, detail("not created yet"));
+    }
+    match writable(dir) {
+        Ok(()) => Check::new("log directory", Status::Ok, detail("writable")),
+        Err(e) => Check::new(
+            "log directory",
+            Status::Warning,
+            detail(&format!(
+                "not writable, the daemon cannot log in the background: {}",
+                e
+            )),
+        ),
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned by the clipmate library.
+#[derive(Debug)]
+pub enum ClipmateError {
+    /// Reading or writing the history or an image file failed.
+    Io(io::Error),
+    /// The history file exists but is not valid history JSON.
+    Parse(serde_json::Error),
+    /// The clipboard backend could not be reached or refused the operation.
+    Backend(io::Error),
+    /// No history item has the given (1-based) number.
+    ItemNotFound(usize),
+    /// A user supplied value could not be understood.
+    InvalidInput(String),
+    /// The configuration file is malformed or holds invalid values.
+    Config(String),
+    /// The history is encrypted and no key is available; run `clipmate unlock`.
+    Locked,
+    /// Encrypting or decrypting failed, or the passphrase is wrong.
+    Crypto(String),
+    /// Reading or writing the SQLite history database failed.
+    Database(rusqlite::Error),
+    /// The daemon could not be talked to, or failed to handle a request.
+    Daemon(String),
+}
+
+pub type Result<T> = std::result::Result<T, ClipmateError>;
+
+impl fmt::Display for ClipmateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipmateError::Io(e) => write!(f, "I/O error: {}", e),
+            ClipmateError::Parse(e) => write!(f, "failed to parse clipboard history: {}", e),
+            ClipmateError::Backend(e) => write!(f, "clipboard unavailable: {}", e),
+            ClipmateError::ItemNotFound(n) => {
+                write!(f, "item {} not found in clipboard history", n)
+            }
+            ClipmateError::InvalidInput(msg) => write!(f, "{}", msg),
+            ClipmateError::Config(msg) => write!(f, "invalid configuration: {}", msg),
+            ClipmateError::Locked => {
+                write!(
+                    f,
+                    "clipboard history is encrypted, run 'clipmate unlock' first"
+                )
+            }
+            ClipmateError::Crypto(msg) => write!(f, "encryption error: {}", msg),
+            ClipmateError::Database(e) => write!(f, "history database error: {}", e),
+            ClipmateError::Daemon(msg) => write!(f, "daemon error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ClipmateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClipmateError::Io(e) | ClipmateError::Backend(e) => Some(e),
+            ClipmateError::Parse(e) => Some(e),
+            ClipmateError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClipmateError {
+    fn from(e: io::Error) -> Self {
+        ClipmateError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ClipmateError {
+    fn from(e: serde_json::Error) -> Self {
+        ClipmateError::Parse(e)
+    }
+}
+
+impl From<rusqlite::Error> for ClipmateError {
+    fn from(e: rusqlite::Error) -> Self {
+        ClipmateError::Database(e)
+    }
+}
@@ -0,0 +1,90 @@
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::path::{Path, PathBuf};
+
+/// The type GNOME and other GTK file managers copy files as: `copy` or
+/// `cut` followed by one URI per line.
+pub const GNOME_COPIED_FILES: &str = "x-special/gnome-copied-files";
+
+/// The standard list of URIs, one per line, that KDE and most other
+/// applications copy and accept files as.
+pub const URI_LIST: &str = "text/uri-list";
+
+/// Characters escaped in `file://` URIs, besides controls.
+const PATH_ESCAPES: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b']')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// The local path a `file://` URI points to, or `None` for other URIs.
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    // The host, if any, is ignored: only local files can be pasted.
+    let path = &rest[rest.find('/')?..];
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    // The history keeps one path per line.
+    if path.contains(['\n', '\r']) {
+        return None;
+    }
+    Some(PathBuf::from(path.into_owned()))
+}
+
+fn path_to_uri(path: &Path) -> String {
+    format!(
+        "file://{}",
+        utf8_percent_encode(&path.to_string_lossy(), PATH_ESCAPES)
+    )
+}
+
+/// Parses a `text/uri-list`, returning `None` unless it lists at least one
+/// URI and all of them are local files with no line breaks in their paths.
+pub fn parse_uri_list(list: &str) -> Option<Vec<PathBuf>> {
+    let paths = list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(uri_to_path)
+        .collect::<Option<Vec<_>>>()?;
+    (!paths.is_empty()).then_some(paths)
+}
+
+/// Parses `x-special/gnome-copied-files`, whose first line says whether the
+/// files were copied or cut.
+pub fn parse_gnome_copied_files(data: &str) -> Option<Vec<PathBuf>> {
+    let (_, uris) = data.split_once('\n')?;
+    parse_uri_list(uris)
+}
+
+/// `paths` as a `text/uri-list`, with the CRLF line ends it requires.
+pub fn to_uri_list(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("{}\r\n", path_to_uri(path)))
+        .collect()
+}
+
+/// `paths` as `x-special/gnome-copied-files`, offered for copying.
+pub fn to_gnome_copied_files(paths: &[PathBuf]) -> String {
+    let uris: Vec<String> = paths.iter().map(|path| path_to_uri(path)).collect();
+    format!("copy\n{}", uris.join("\n"))
+}
+
+/// The file names of `paths`, separated by commas, as shown in the history.
+pub fn summary(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| match path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => path.to_string_lossy(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,456 @@
+use crate::backend::{ClipboardBackend, Selection};
+use crate::daemon::Subscribers;
+use crate::error::{ClipmateError, Result};
+use crate::manager::{ClipboardItem, ClipboardItemType, ClipboardManager, Stats};
+use crate::persist;
+use crate::search::SearchQuery;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, DirBuilder, Permissions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Version of the protocol spoken over the daemon's socket. Requests and
+/// responses carry it, and the daemon refuses requests of other versions.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The longest request line the daemon reads, newline included. Requests
+/// carry no contents, so anything longer is not one.
+const MAX_REQUEST_LEN: u64 = 64 * 1024;
+
+/// A command for the daemon. Items are numbered as `clipmate history`
+/// numbers them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Request {
+    /// Every item, or only the pinned ones.
+    List {
+        #[serde(default)]
+        pinned: bool,
+    },
+    /// One item, with its whole text if it is a text item.
+    Get {
+        item: usize,
+    },
+    /// Puts an item in a selection.
+    Set {
+        item: usize,
+        #[serde(default)]
+        selection: Selection,
+    },
+    /// Deletes items by number, or the unpinned items in an inclusive
+    /// range.
+    Delete {
+        #[serde(default)]
+        items: Vec<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        range: Option<(usize, usize)>,
+    },
+    Pin {
+        item: usize,
+        pinned: bool,
+    },
+    Search {
+        query: SearchQuery,
+    },
+    Stats,
+    /// Streams every item recorded from now on. The connection carries no
+    /// other requests afterwards.
+    Subscribe,
+}
+
+impl Request {
+    /// The name of the request, which unlike its arguments cannot quote
+    /// anything copied, such as a search for a password.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Request::List { .. } => "list",
+            Request::Get { .. } => "get",
+            Request::Set { .. } => "set",
+            Request::Delete { .. } => "delete",
+            Request::Pin { .. } => "pin",
+            Request::Search { .. } => "search",
+            Request::Stats => "stats",
+            Request::Subscribe => "subscribe",
+        }
+    }
+}
+
+/// An item with its number.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NumberedItem {
+    pub number: usize,
+    pub item: ClipboardItem,
+}
+
+/// What a request failed with, so that the client can fail the same way
+/// as it would have without the daemon.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    ItemNotFound(usize),
+    InvalidInput,
+    Backend,
+    Locked,
+    UnsupportedVersion,
+    Other,
+}
+
+/// The daemon's answer to a request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Items {
+        items: Vec<NumberedItem>,
+    },
+    Item {
+        item: NumberedItem,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        text: Option<String>,
+    },
+    /// The request changed `count` items.
+    Done {
+        count: usize,
+    },
+    Stats {
+        stats: Stats,
+    },
+    /// Items recorded from now on follow.
+    Subscribed,
+    /// An item the daemon just recorded.
+    Recorded {
+        item: NumberedItem,
+    },
+    Error {
+        kind: ErrorKind,
+        message: String,
+    },
+}
+
+impl Response {
+    fn error(error: &ClipmateError) -> Response {
+        let (kind, message) = match error {
+            ClipmateError::ItemNotFound(n) => (ErrorKind::ItemNotFound(*n), error.to_string()),
+            ClipmateError::InvalidInput(msg) => (ErrorKind::InvalidInput, msg.clone()),
+            ClipmateError::Backend(e) => (ErrorKind::Backend, e.to_string()),
+            ClipmateError::Locked => (ErrorKind::Locked, error.to_string()),
+            _ => (ErrorKind::Other, error.to_string()),
+        };
+        Response::Error { kind, message }
+    }
+
+    /// The response, or the error it carries.
+    pub fn into_result(self) -> Result<Response> {
+        match self {
+            Response::Error { kind, message } => Err(match kind {
+                ErrorKind::ItemNotFound(n) => ClipmateError::ItemNotFound(n),
+                ErrorKind::InvalidInput => ClipmateError::InvalidInput(message),
+                ErrorKind::Backend => ClipmateError::Backend(io::Error::other(message)),
+                ErrorKind::Locked => ClipmateError::Locked,
+                ErrorKind::UnsupportedVersion | ErrorKind::Other => ClipmateError::Daemon(message),
+            }),
+            response => Ok(response),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RequestMessage {
+    version: u32,
+    request: Request,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ResponseMessage {
+    version: u32,
+    response: Response,
+}
+
+/// Just the version of a message, read before the rest so that messages
+/// of other versions get a useful error.
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+fn numbered(items: impl IntoIterator<Item = (usize, ClipboardItem)>) -> Vec<NumberedItem> {
+    items
+        .into_iter()
+        .map(|(number, item)| NumberedItem { number, item })
+        .collect()
+}
+
+/// Carries out `request` on `manager`, after loading the history again if
+/// another process changed it. Errors are returned as `Response::Error`.
+///
+/// The daemon answers requests with this, and the CLI does too when no
+/// daemon is running.
+pub fn handle<B: ClipboardBackend>(
+    manager: &mut ClipboardManager<B>,
+    request: Request,
+) -> Response {
+    match manager.refresh().and_then(|_| respond(manager, request)) {
+        Ok(response) => response,
+        Err(e) => Response::error(&e),
+    }
+}
+
+fn respond<B: ClipboardBackend>(
+    manager: &mut ClipboardManager<B>,
+    request: Request,
+) -> Result<Response> {
+    Ok(match request {
+        Request::List { pinned } => Response::Items {
+            items: numbered(
+                (1..)
+                    .zip(manager.get_history().iter().cloned())
+                    .filter(|(_, item)| !pinned || item.pinned),
+            ),
+        },
+        Request::Get { item: number } => {
+            let item = manager.get_item(number)?.clone();
+            let text = match item.item_type {
+                ClipboardItemType::TEXT => Some(manager.item_text(number)?),
+                _ => None,
+            };
+            Response::Item {
+                item: NumberedItem { number, item },
+                text,
+            }
+        }
+        Request::Set { item, selection } => {
+            manager.restore_to(item, selection)?;
+            Response::Done { count: 1 }
+        }
+        Request::Delete { items, range } => Response::Done {
+            count: match range {
+                Some((first, last)) => manager.delete_range(first, last)?,
+                None => manager.delete(&items)?,
+            },
+        },
+        Request::Pin { item, pinned } => {
+            manager.set_pinned(item, pinned)?;
+            Response::Done { count: 1 }
+        }
+        Request::Search { query } => Response::Items {
+            items: numbered(
+                manager
+                    .search(&query)?
+                    .into_iter()
+                    .map(|(number, item)| (number, item.clone())),
+            ),
+        },
+        Request::Stats => Response::Stats {
+            stats: manager.stats(),
+        },
+        Request::Subscribe => {
+            return Err(ClipmateError::InvalidInput(
+                "only the daemon can stream new items".to_string(),
+            ))
+        }
+    })
+}
+
+fn write_message(stream: &mut UnixStream, message: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line)
+}
+
+/// Listens for commands at `path`, replacing a socket left behind by a
+/// daemon that is gone. Fails if a daemon is listening there already.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(ClipmateError::Daemon(format!(
+            "a daemon is already listening at {}",
+            path.display()
+        )));
+    }
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    persist::create_dir(parent)?;
+
+    // The history may hold secrets; only the user may ask for it. The
+    // socket is bound in a directory only the user can enter and moved in
+    // place once private, so that nobody can connect in between.
+    let staging = parent.join(format!(".bind-{}", process::id()));
+    match fs::remove_dir_all(&staging) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("sock");
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, Permissions::from_mode(0o600))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_dir_all(&staging);
+    Ok(bound?)
+}
+
+/// Answers the commands arriving on `listener` from background threads,
+/// one per connection.
+pub fn serve<B: ClipboardBackend + Send + 'static>(
+    listener: UnixListener,
+    manager: Arc<Mutex<ClipboardManager<B>>>,
+    subscribers: Subscribers,
+) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let manager = manager.clone();
+            let subscribers = subscribers.clone();
+            thread::spawn(move || {
+                if let Err(e) = serve_connection(stream, &manager, &subscribers) {
+                    tracing::debug!("Connection closed: {}", e);
+                }
+            });
+        }
+    });
+}
+
+/// Answers requests on `stream`, one JSON line each, until the client
+/// hangs up.
+fn serve_connection<B: ClipboardBackend>(
+    mut stream: UnixStream,
+    manager: &Mutex<ClipboardManager<B>>,
+    subscribers: &Subscribers,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = (&mut reader)
+            .take(MAX_REQUEST_LEN + 1)
+            .read_line(&mut line)?;
+        if read == 0 {
+            return Ok(());
+        }
+        if read as u64 > MAX_REQUEST_LEN {
+            let message = format!("request longer than {} bytes", MAX_REQUEST_LEN);
+            let kind = ErrorKind::InvalidInput;
+            return respond_with(&mut stream, Response::Error { kind, message });
+        }
+        let request = parse_request(&line);
+        tracing::debug!(
+            kind = request.as_ref().map_or("invalid", Request::kind),
+            "Request"
+        );
+        let response = match request {
+            Ok(Request::Subscribe) => {
+                let recorded = subscribers.subscribe();
+                respond_with(&mut stream, Response::Subscribed)?;
+                for (number, item) in recorded {
+                    let item = NumberedItem { number, item };
+                    respond_with(&mut stream, Response::Recorded { item })?;
+                }
+                return Ok(());
+            }
+            Ok(request) => handle(&mut manager.lock().unwrap(), request),
+            Err((kind, message)) => Response::Error { kind, message },
+        };
+        respond_with(&mut stream, response)?;
+    }
+}
+
+fn respond_with(stream: &mut UnixStream, response: Response) -> io::Result<()> {
+    let message = ResponseMessage {
+        version: PROTOCOL_VERSION,
+        response,
+    };
+    write_message(stream, &message)
+}
+
+/// Reads a request line, or returns what is wrong with it.
+fn parse_request(line: &str) -> std::result::Result<Request, (ErrorKind, String)> {
+    let invalid =
+        |e: serde_json::Error| (ErrorKind::InvalidInput, format!("invalid request: {}", e));
+    let version: Version = serde_json::from_str(line).map_err(invalid)?;
+    if version.version != PROTOCOL_VERSION {
+        return Err((
+            ErrorKind::UnsupportedVersion,
+            format!(
+                "protocol version {} is not supported, the daemon speaks version {}",
+                version.version, PROTOCOL_VERSION
+            ),
+        ));
+    }
+    let message: RequestMessage = serde_json::from_str(line).map_err(invalid)?;
+    Ok(message.request)
+}
+
+/// A connection to the daemon.
+pub struct Client {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+}
+
+impl Client {
+    /// Connects to the daemon listening at `path`, or returns `None` if no
+    /// daemon is running.
+    pub fn connect(path: &Path) -> Result<Option<Client>> {
+        let stream = match UnixStream::connect(path) {
+            Ok(stream) => stream,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => {
+                return Err(ClipmateError::Daemon(format!(
+                    "cannot connect to {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        let reader = BufReader::new(stream.try_clone()?);
+        Ok(Some(Client { stream, reader }))
+    }
+
+    /// Sends `request` and waits for the response.
+    pub fn request(&mut self, request: Request) -> Result<Response> {
+        let message = RequestMessage {
+            version: PROTOCOL_VERSION,
+            request,
+        };
+        write_message(&mut self.stream, &message)?;
+        self.receive()
+    }
+
+    /// Waits for the next response, such as the next item recorded after
+    /// a `Subscribe` request.
+    pub fn receive(&mut self) -> Result<Response> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(ClipmateError::Daemon(
+                "the daemon closed the connection".to_string(),
+            ));
+        }
+        let version: Version = serde_json::from_str(&line)?;
+        if version.version != PROTOCOL_VERSION {
+            return Err(ClipmateError::Daemon(format!(
+                "the daemon speaks protocol version {}, this client version {}; restart the daemon",
+                version.version, PROTOCOL_VERSION
+            )));
+        }
+        let message: ResponseMessage = serde_json::from_str(&line)?;
+        message.response.into_result()
+    }
+}
@@ -1 +1,22 @@
+pub mod backend;
+pub mod blobs;
+pub mod config;
+pub mod crypto;
+pub mod daemon;
+pub mod doctor;
+pub mod error;
+pub mod files;
+pub mod ipc;
+pub mod lock;
+pub mod logging;
 pub mod manager;
+pub mod owner;
+pub mod paths;
+mod persist;
+pub mod search;
+pub mod secrets;
+pub mod service;
+pub mod store;
+pub mod watch;
+
+pub use error::{ClipmateError, Result};
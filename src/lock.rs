@@ -0,0 +1,174 @@
+use crate::error::{ClipmateError, Result};
+use crate::paths::DataPaths;
+use crate::persist;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Advisory lock coordinating the processes sharing a history.
+///
+/// Every process holds it shared while loading the history and exclusively
+/// while changing it. The lock file also counts the changes made to the
+/// history, so a process can tell whether the history it loaded is still
+/// current or has to be loaded again.
+pub struct HistoryLock {
+    file: File,
+}
+
+/// Holds a `HistoryLock` until dropped.
+pub struct LockGuard {
+    file: File,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+impl HistoryLock {
+    /// Opens the lock of the history in `paths`, creating the data
+    /// directory if needed.
+    pub fn open(paths: &DataPaths) -> Result<HistoryLock> {
+        persist::create_dir(paths.data_dir())?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(paths.lock_file())?;
+        Ok(HistoryLock { file })
+    }
+
+    /// Waits until no process is changing the history.
+    pub fn shared(&self) -> Result<LockGuard> {
+        let file = self.file.try_clone()?;
+        file.lock_shared()?;
+        Ok(LockGuard { file })
+    }
+
+    /// Waits until no other process is reading or changing the history.
+    pub fn exclusive(&self) -> Result<LockGuard> {
+        let file = self.file.try_clone()?;
+        file.lock()?;
+        Ok(LockGuard { file })
+    }
+
+    /// How many times the history was changed. Only meaningful while the
+    /// lock is held.
+    pub fn generation(&self) -> Result<u64> {
+        let mut contents = String::new();
+        (&self.file).seek(SeekFrom::Start(0))?;
+        (&self.file).read_to_string(&mut contents)?;
+        Ok(contents.trim().parse().unwrap_or(0))
+    }
+
+    /// Records a change to the history and returns the new generation. The
+    /// lock must be held exclusively.
+    pub(crate) fn bump(&self) -> Result<u64> {
+        let generation = self.generation()? + 1;
+        self.file.set_len(0)?;
+        (&self.file).seek(SeekFrom::Start(0))?;
+        (&self.file).write_all(generation.to_string().as_bytes())?;
+        Ok(generation)
+    }
+}
+
+/// The pid file of a running daemon, locked for as long as it runs so that
+/// a second daemon for the same history refuses to start.
+pub struct PidFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl PidFile {
+    /// Locks the pid file at `path` and writes the current process id to
+    /// it. Fails if another daemon holds it.
+    pub fn acquire(path: &Path) -> Result<PidFile> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => {
+                    let message = match read_pid(&mut file) {
+                        Some(pid) => format!("another daemon is already running (pid {})", pid),
+                        None => "another daemon is already running".to_string(),
+                    };
+                    return Err(ClipmateError::Daemon(message));
+                }
+                Err(TryLockError::Error(e)) => return Err(e.into()),
+            }
+            // A daemon exiting unlinks the file while it holds the lock, so
+            // the one just locked may be gone; lock the one now in its place.
+            if !is_file_at(&file, path)? {
+                continue;
+            }
+            file.set_len(0)?;
+            writeln!(file, "{}", process::id())?;
+            return Ok(PidFile {
+                path: path.to_path_buf(),
+                file,
+            });
+        }
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Unlink while still holding the lock, and only the file naming this
+        // process, so that a daemon starting meanwhile keeps its own.
+        if read_pid(&mut self.file) == Some(process::id())
+            && is_file_at(&self.file, &self.path).unwrap_or(false)
+        {
+            let _ = fs::remove_file(&self.path);
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+/// Whether `file` is the one currently at `path`.
+fn is_file_at(file: &File, path: &Path) -> Result<bool> {
+    let opened = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(current.dev() == opened.dev() && current.ino() == opened.ino()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+/// The process id of the daemon holding the pid file at `path`, or `None`
+/// if no daemon is running.
+pub fn running_daemon(path: &Path) -> Result<Option<u32>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    match file.try_lock_shared() {
+        Ok(()) => Ok(None),
+        Err(TryLockError::WouldBlock) => read_pid(&mut file).map(Some).ok_or_else(|| {
+            ClipmateError::Daemon(format!(
+                "a daemon is running but {} holds no pid",
+                path.display()
+            ))
+        }),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
@@ -0,0 +1,101 @@
+use crate::config::LogLevel;
+use crate::error::Result;
+use crate::paths;
+use std::collections::HashMap;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::level_filters::LevelFilter;
+
+const DAEMON_LOG: &str = "daemon.log";
+
+/// Where the daemon logs to when it runs in the background: `daemon.log`
+/// in the state directory.
+pub fn daemon_log() -> Result<PathBuf> {
+    Ok(paths::state_dir()?.join(DAEMON_LOG))
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> LevelFilter {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// Logs messages at `level` and above to stderr, which is the daemon's log
+/// file when it runs in the background. Colors are only used on terminals.
+pub fn init(level: LogLevel) {
+    tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::from(level))
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .with_target(false)
+        .init();
+}
+
+/// Logs warnings and errors of the library to stderr, for commands other
+/// than the daemon, without the timestamps a log needs.
+pub fn init_cli() {
+    tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::WARN)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .with_target(false)
+        .without_time()
+        .init();
+}
+
+/// Lets each distinct message through at most once per interval, so that a
+/// failure repeating on every poll does not flood the log.
+pub struct RateLimit {
+    interval: Duration,
+    seen: HashMap<String, Seen>,
+}
+
+struct Seen {
+    logged_at: Instant,
+    held_back: usize,
+}
+
+impl RateLimit {
+    pub fn new(interval: Duration) -> RateLimit {
+        RateLimit {
+            interval,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Whether to log `message` now. If so, returns how many times it was
+    /// held back since it was last logged.
+    pub fn check(&mut self, message: &str) -> Option<usize> {
+        let interval = self.interval;
+        // Messages that stopped repeating would be let through anyway.
+        self.seen
+            .retain(|_, seen| seen.held_back > 0 || seen.logged_at.elapsed() < interval);
+        match self.seen.get_mut(message) {
+            Some(seen) if seen.logged_at.elapsed() < interval => {
+                seen.held_back += 1;
+                None
+            }
+            Some(seen) => {
+                let held_back = seen.held_back;
+                seen.logged_at = Instant::now();
+                seen.held_back = 0;
+                Some(held_back)
+            }
+            None => {
+                let seen = Seen {
+                    logged_at: Instant::now(),
+                    held_back: 0,
+                };
+                self.seen.insert(message.to_string(), seen);
+                Some(0)
+            }
+        }
+    }
+}
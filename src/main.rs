@@ -1,49 +1,975 @@
-use clap::{App, Arg, SubCommand};
-use clipboard_manager_lib::manager::ClipboardManager;
-use std::sync::Arc;
-use std::{thread, time::Duration};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use clipboard_manager_lib::backend::SystemBackend;
+use clipboard_manager_lib::config::{parse_duration, Config, WatchMode};
+use clipboard_manager_lib::crypto::{self, KeyParams};
+use clipboard_manager_lib::daemon::Daemon;
+use clipboard_manager_lib::doctor::{self, Status};
+use clipboard_manager_lib::files;
+use clipboard_manager_lib::ipc::{self, Client, Request, Response};
+use clipboard_manager_lib::lock::{self, PidFile};
+use clipboard_manager_lib::logging;
+use clipboard_manager_lib::manager::{self, ClipboardItem, ClipboardItemType, ClipboardManager};
+use clipboard_manager_lib::owner::{self, X11Owner};
+use clipboard_manager_lib::paths::{self, DataPaths, LEGACY_HISTORY_FILE};
+use clipboard_manager_lib::search::{SearchMode, SearchQuery};
+use clipboard_manager_lib::service;
+use clipboard_manager_lib::store::{self, StoreFormat};
+use clipboard_manager_lib::watch::{self, PollWatcher, Watcher};
+use clipboard_manager_lib::{ClipmateError, Result};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{self, Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{error, info, warn};
 
 fn main() {
+    if let Err(e) = run() {
+        eprintln!("clipmate: {}", e);
+        process::exit(exit_code(&e));
+    }
+}
+
+fn exit_code(error: &ClipmateError) -> i32 {
+    match error {
+        ClipmateError::ItemNotFound(_)
+        | ClipmateError::InvalidInput(_)
+        | ClipmateError::Config(_) => 2,
+        ClipmateError::Backend(_) => 3,
+        ClipmateError::Locked => 4,
+        ClipmateError::Io(_)
+        | ClipmateError::Parse(_)
+        | ClipmateError::Crypto(_)
+        | ClipmateError::Database(_)
+        | ClipmateError::Daemon(_) => 1,
+    }
+}
+
+fn print_item(item_number: usize, item: &ClipboardItem) {
+    let pin = if item.pinned { " [pinned]" } else { "" };
+    let selection = if item.selection.is_clipboard() {
+        String::new()
+    } else {
+        format!(" [{}]", item.selection)
+    };
+    let data = match item.item_type {
+        ClipboardItemType::FILES => files::summary(&item.paths()),
+        _ => item.data.clone(),
+    };
+    println!(
+        "{}: {} {:?}{}{}",
+        item_number, data, item.item_type, selection, pin
+    );
+}
+
+fn item_number_arg() -> Arg<'static, 'static> {
+    Arg::with_name("item")
+        .help("Item number, as shown by history")
+        .required(true)
+        .index(1)
+}
+
+fn parse_item_number(value: &str) -> Result<usize> {
+    value
+        .parse()
+        .map_err(|_| ClipmateError::InvalidInput(format!("'{}' is not a valid item number", value)))
+}
+
+/// Parses an inclusive `FIRST..LAST` range of item numbers.
+fn parse_range(value: &str) -> Result<(usize, usize)> {
+    let invalid =
+        || ClipmateError::InvalidInput(format!("'{}' is not a range such as 5..20", value));
+    let (first, last) = value.split_once("..").ok_or_else(invalid)?;
+    let first = first.parse().map_err(|_| invalid())?;
+    let last = last
+        .strip_prefix('=')
+        .unwrap_or(last)
+        .parse()
+        .map_err(|_| invalid())?;
+    Ok((first, last))
+}
+
+fn search_query(args: &ArgMatches) -> Result<SearchQuery> {
+    Ok(SearchQuery {
+        pattern: args.value_of("query").unwrap().to_string(),
+        mode: if args.is_present("regex") {
+            SearchMode::Regex
+        } else if args.is_present("fuzzy") {
+            SearchMode::Fuzzy
+        } else if args.is_present("ignore-case") {
+            SearchMode::IgnoreCase
+        } else {
+            SearchMode::Substring
+        },
+        item_type: match args.value_of("type") {
+            Some("text") => Some(ClipboardItemType::TEXT),
+            Some("image") => Some(ClipboardItemType::IMAGE),
+            Some("files") => Some(ClipboardItemType::FILES),
+            _ => None,
+        },
+        pinned: if args.is_present("pinned") {
+            Some(true)
+        } else if args.is_present("unpinned") {
+            Some(false)
+        } else {
+            None
+        },
+        newer_than: args
+            .value_of("newer-than")
+            .map(parse_duration)
+            .transpose()?,
+        older_than: args
+            .value_of("older-than")
+            .map(parse_duration)
+            .transpose()?,
+    })
+}
+
+/// The request for commands the daemon can carry out, which are sent to it
+/// when it is running so that the CLI never races with it on the history.
+fn daemon_request(matches: &ArgMatches) -> Result<Option<Request>> {
+    let item_number = |args: &ArgMatches| parse_item_number(args.value_of("item").unwrap());
+    Ok(Some(match matches.subcommand() {
+        ("history", Some(args)) if args.is_present("follow") => Request::Subscribe,
+        ("history", Some(args)) => Request::List {
+            pinned: args.is_present("pinned"),
+        },
+        ("search", Some(args)) => Request::Search {
+            query: search_query(args)?,
+        },
+        ("get", Some(args)) => Request::Get {
+            item: item_number(args)?,
+        },
+        ("pin", Some(args)) => Request::Pin {
+            item: item_number(args)?,
+            pinned: true,
+        },
+        ("unpin", Some(args)) => Request::Pin {
+            item: item_number(args)?,
+            pinned: false,
+        },
+        ("delete", Some(args)) => match args.value_of("range") {
+            Some(range) => Request::Delete {
+                items: Vec::new(),
+                range: Some(parse_range(range)?),
+            },
+            None => Request::Delete {
+                items: args
+                    .values_of("items")
+                    .unwrap()
+                    .map(parse_item_number)
+                    .collect::<Result<Vec<_>>>()?,
+                range: None,
+            },
+        },
+        ("stats", _) => Request::Stats,
+        ("", None) => match matches.value_of("item") {
+            Some(item) => Request::Set {
+                item: parse_item_number(item)?,
+                selection: matches
+                    .value_of("selection")
+                    .map(str::parse)
+                    .transpose()?
+                    .unwrap_or_default(),
+            },
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    }))
+}
+
+/// Prints the outcome of the command `daemon_request` made a request for.
+fn print_response(matches: &ArgMatches, response: Response) -> Result<()> {
+    match (matches.subcommand(), response) {
+        (("search", Some(args)), Response::Items { items }) if args.is_present("numbers") => {
+            for item in items {
+                println!("{}", item.number);
+            }
+        }
+        (_, Response::Items { items }) => {
+            for item in items {
+                print_item(item.number, &item.item);
+            }
+        }
+        (_, Response::Item { item, text }) => match (item.item.item_type, text) {
+            (_, Some(text)) => println!("{}", text),
+            (ClipboardItemType::FILES, None) => println!("{}", item.item.data),
+            _ => {
+                return Err(ClipmateError::InvalidInput(format!(
+                    "item {} is an image",
+                    item.number
+                )))
+            }
+        },
+        (("pin", Some(args)), Response::Done { .. }) => {
+            println!("Pinned item {}", args.value_of("item").unwrap())
+        }
+        (("unpin", Some(args)), Response::Done { .. }) => {
+            println!("Unpinned item {}", args.value_of("item").unwrap())
+        }
+        (("delete", _), Response::Done { count }) => println!("Deleted {} item(s)", count),
+        (_, Response::Done { .. }) => {
+            let item = matches.value_of("item").unwrap();
+            match matches.value_of("selection") {
+                Some(selection) => println!("Set the {} selection to item {}", selection, item),
+                None => println!("Clipboard set to item {}", item),
+            }
+        }
+        (_, Response::Stats { stats }) => {
+            println!("Items: {} ({} pinned)", stats.items, stats.pinned);
+            println!(
+                "Text: {}, images: {}, files: {}",
+                stats.text, stats.images, stats.files
+            );
+            println!("Size: {} bytes", stats.bytes);
+        }
+        (_, response) => {
+            return Err(ClipmateError::Daemon(format!(
+                "unexpected response {:?}",
+                response
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// A file holding an item while it is edited, removed when dropped.
+struct EditFile(PathBuf);
+
+impl Drop for EditFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Lets the user edit `text` in `$VISUAL` or `$EDITOR` and returns the
+/// result. The temporary file lives in `$XDG_RUNTIME_DIR`, which is kept in
+/// memory, is readable only by the user, and is removed afterwards, even
+/// when clipmate is interrupted while the editor runs.
+fn edit_in_editor(text: &str) -> Result<String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    let dir = paths::runtime_dir().ok_or_else(|| {
+        ClipmateError::InvalidInput(
+            "XDG_RUNTIME_DIR is not set, it is needed to edit items".to_string(),
+        )
+    })?;
+    DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+    let path = dir.join(format!("edit-{}.txt", process::id()));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?;
+    let file_guard = EditFile(path);
+    file.write_all(text.as_bytes())?;
+    drop(file);
+
+    // Outlive a Ctrl-C or a closed terminal, which reach the editor too, so
+    // that the file is still removed.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let mut handlers = Vec::new();
+    for signal in [SIGINT, SIGTERM, SIGHUP] {
+        handlers.push(signal_hook::flag::register(signal, interrupted.clone())?);
+    }
+    // Run through the shell so EDITOR may carry arguments, like
+    // `code --wait`.
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&file_guard.0)
+        .status();
+    for handler in handlers {
+        signal_hook::low_level::unregister(handler);
+    }
+    let status = status?;
+    if interrupted.load(Ordering::SeqCst) {
+        return Err(ClipmateError::InvalidInput(
+            "interrupted, item left unchanged".to_string(),
+        ));
+    }
+    if !status.success() {
+        return Err(ClipmateError::InvalidInput(format!(
+            "editor exited with {}, item left unchanged",
+            status
+        )));
+    }
+    let mut edited = fs::read_to_string(&file_guard.0)?;
+    drop(file_guard);
+
+    // Editors add a final newline; drop it unless the original had one.
+    if !text.ends_with('\n') && edited.ends_with('\n') {
+        edited.pop();
+    }
+    Ok(edited)
+}
+
+/// Reads a passphrase from `CLIPMATE_PASSPHRASE`, or from the terminal
+/// without echoing it.
+fn read_passphrase(prompt: &str) -> Result<String> {
+    match env::var("CLIPMATE_PASSPHRASE") {
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => Ok(rpassword::prompt_password(prompt)?),
+    }
+}
+
+/// Reads a new passphrase, asking twice when reading from the terminal.
+fn new_passphrase() -> Result<String> {
+    let passphrase = read_passphrase("New passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(ClipmateError::InvalidInput(
+            "the passphrase must not be empty".to_string(),
+        ));
+    }
+    if env::var_os("CLIPMATE_PASSPHRASE").is_none()
+        && read_passphrase("Repeat passphrase: ")? != passphrase
+    {
+        return Err(ClipmateError::InvalidInput(
+            "the passphrases do not match".to_string(),
+        ));
+    }
+    Ok(passphrase)
+}
+
+fn unlock(config: &Config, paths: &DataPaths) -> Result<()> {
+    if !config.encryption.enabled {
+        return Err(ClipmateError::InvalidInput(
+            "encryption is disabled, set encryption.enabled in the config".to_string(),
+        ));
+    }
+    if config.encryption.keyfile.is_some() {
+        println!("The history is unlocked with the configured keyfile");
+        return Ok(());
+    }
+    let key = match KeyParams::load(paths)? {
+        Some(params) => params.unlock(read_passphrase("Passphrase: ")?.as_bytes())?,
+        None => {
+            println!("Choose a passphrase to encrypt the clipboard history with");
+            let (key, params) = KeyParams::generate(new_passphrase()?.as_bytes())?;
+            params.save(paths)?;
+            key
+        }
+    };
+    crypto::cache_key(paths, &key)?;
+    println!("Unlocked");
+    Ok(())
+}
+
+fn load_config(matches: &ArgMatches) -> Result<Config> {
+    let mut config = Config::load(matches.value_of("config").map(Path::new))?;
+    if let Some(data_dir) = matches.value_of("data-dir") {
+        config.storage.data_dir = Some(data_dir.into());
+    }
+    Ok(config)
+}
+
+/// Prints the outcome of every check, and exits with status 1 if any found
+/// a problem.
+fn run_doctor(matches: &ArgMatches) -> Result<()> {
+    let checks = doctor::run(
+        matches.value_of("config").map(Path::new),
+        matches.value_of("data-dir").map(Path::new),
+    );
+    for check in &checks {
+        println!("{:<8} {}: {}", check.status, check.name, check.detail);
+    }
+    let problems = checks
+        .iter()
+        .filter(|check| check.status == Status::Problem)
+        .count();
+    if problems > 0 {
+        println!(
+            "Found {} problem{}",
+            problems,
+            if problems == 1 { "" } else { "s" }
+        );
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn foreground_arg() -> Arg<'static, 'static> {
+    Arg::with_name("foreground")
+        .long("foreground")
+        .help("Runs the daemon in the foreground, logging to stderr")
+}
+
+/// Arguments running the daemon in the foreground with the same config file
+/// and data directory as this invocation.
+fn daemon_args(matches: &ArgMatches) -> Result<Vec<OsString>> {
+    let mut args = Vec::new();
+    for name in ["config", "data-dir"] {
+        if let Some(value) = matches.value_of_os(name) {
+            args.push(format!("--{}", name).into());
+            args.push(path::absolute(value)?.into_os_string());
+        }
+    }
+    args.extend(["daemon".into(), "--foreground".into()]);
+    Ok(args)
+}
+
+fn start_daemon(matches: &ArgMatches, config: &Config, paths: &DataPaths) -> Result<()> {
+    if let Some(pid) = lock::running_daemon(&paths.pid_file())? {
+        println!("The daemon is already running (pid {})", pid);
+        return Ok(());
+    }
+    let mut command = Command::new(env::current_exe()?);
+    command.args(daemon_args(matches)?);
+    let log = logging::daemon_log()?;
+    let pid = service::start(command, &config.socket_path()?, &log)?;
+    println!("Started the daemon (pid {})", pid);
+    Ok(())
+}
+
+fn stop_daemon(paths: &DataPaths) -> Result<()> {
+    match service::stop(&paths.pid_file())? {
+        Some(pid) => println!("Stopped the daemon (pid {})", pid),
+        None => println!("The daemon is not running"),
+    }
+    Ok(())
+}
+
+/// Prints whether the daemon is running, exiting with status 3 if it is
+/// not, as init scripts do.
+fn daemon_status(config: &Config, paths: &DataPaths) -> Result<()> {
+    match lock::running_daemon(&paths.pid_file())? {
+        Some(pid) => {
+            println!("The daemon is running (pid {})", pid);
+            println!("Listening at {}", config.socket_path()?.display());
+            Ok(())
+        }
+        None => {
+            println!("The daemon is not running");
+            process::exit(3);
+        }
+    }
+}
+
+/// Stops the daemon cleanly on SIGTERM and SIGINT, once it has recorded
+/// what it was recording and flushed the history, and reloads the
+/// configuration on SIGHUP.
+fn handle_signals(
+    matches: &ArgMatches,
+    manager: Arc<Mutex<ClipboardManager>>,
+    pid_file: PidFile,
+    socket: PathBuf,
+) -> Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT, SIGHUP])?;
+    let config_path = matches.value_of("config").map(PathBuf::from);
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                let reloaded = Config::load(config_path.as_deref()).and_then(|config| {
+                    let mut manager = manager.lock().unwrap();
+                    let restart_needed = manager.config().restart_needed(&config);
+                    manager.set_config(config)?;
+                    Ok(restart_needed)
+                });
+                match reloaded {
+                    Ok(restart_needed) if restart_needed.is_empty() => {
+                        info!("Reloaded the configuration")
+                    }
+                    Ok(restart_needed) => warn!(
+                        "Reloaded the configuration, changes to {} take effect once the daemon restarts",
+                        restart_needed.join(", ")
+                    ),
+                    Err(e) => error!("Cannot reload the configuration: {}", e),
+                }
+                continue;
+            }
+            info!(signal, "Stopping");
+            // Holding the manager until exiting keeps commands from changing
+            // the history after it was flushed.
+            let mut manager = manager.lock().unwrap();
+            if let Err(e) = manager.flush() {
+                error!("Error while flushing the history: {}", e);
+            }
+            let _ = fs::remove_file(&socket);
+            drop(pid_file);
+            process::exit(0);
+        }
+    });
+    Ok(())
+}
+
+fn run() -> Result<()> {
     let matches = App::new("clipmate")
         .version("0.1.0")
         .author("trizin")
         .about("Manages clipboard history")
-        .subcommand(SubCommand::with_name("daemon").about("Starts the clipboard daemon"))
-        .subcommand(SubCommand::with_name("history").about("Displays clipboard history"))
+        .subcommand(
+            SubCommand::with_name("daemon")
+                .about("Runs the clipboard daemon in the foreground, or manages it")
+                .arg(foreground_arg())
+                .subcommand(
+                    SubCommand::with_name("start")
+                        .about("Starts the daemon in the background")
+                        .arg(foreground_arg()),
+                )
+                .subcommand(SubCommand::with_name("stop").about("Stops the daemon"))
+                .subcommand(
+                    SubCommand::with_name("restart")
+                        .about("Stops the daemon if it is running and starts it again"),
+                )
+                .subcommand(
+                    SubCommand::with_name("status").about("Tells whether the daemon is running"),
+                )
+                .subcommand(
+                    SubCommand::with_name("install-service")
+                        .about("Writes a systemd user unit running the daemon"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Displays clipboard history")
+                .arg(
+                    Arg::with_name("pinned")
+                        .long("pinned")
+                        .help("Only shows pinned items"),
+                )
+                .arg(
+                    Arg::with_name("follow")
+                        .short("f")
+                        .long("follow")
+                        .conflicts_with("pinned")
+                        .help("Shows items as the daemon records them"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Prints the whole text of an item")
+                .arg(item_number_arg()),
+        )
+        .subcommand(SubCommand::with_name("stats").about("Counts the items in the history"))
+        .subcommand(
+            SubCommand::with_name("pin")
+                .about("Pins an item so it is never pruned or cleared")
+                .arg(item_number_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("unpin")
+                .about("Unpins an item")
+                .arg(item_number_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("delete")
+                .about("Deletes items from the history")
+                .arg(
+                    Arg::with_name("items")
+                        .help("Item numbers, as shown by history")
+                        .multiple(true)
+                        .required_unless("range")
+                        .conflicts_with("range"),
+                )
+                .arg(
+                    Arg::with_name("range")
+                        .long("range")
+                        .value_name("FIRST..LAST")
+                        .help("Deletes the unpinned items in an inclusive range, such as 5..20"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("clear")
+                .about("Deletes all unpinned items; unpin items to clear them too")
+                .arg(
+                    Arg::with_name("older-than")
+                        .long("older-than")
+                        .value_name("AGE")
+                        .help("Only deletes items older than AGE, such as 7d or 12h"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Searches the clipboard history")
+                .arg(
+                    Arg::with_name("query")
+                        .help("Text to look for")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("ignore-case")
+                        .short("i")
+                        .long("ignore-case")
+                        .help("Matches regardless of case"),
+                )
+                .arg(
+                    Arg::with_name("regex")
+                        .short("r")
+                        .long("regex")
+                        .conflicts_with_all(&["ignore-case", "fuzzy"])
+                        .help("Treats the query as a regular expression"),
+                )
+                .arg(
+                    Arg::with_name("fuzzy")
+                        .short("f")
+                        .long("fuzzy")
+                        .conflicts_with("ignore-case")
+                        .help("Matches items containing the query's characters in order"),
+                )
+                .arg(
+                    Arg::with_name("type")
+                        .long("type")
+                        .value_name("TYPE")
+                        .possible_values(&["text", "image", "files"])
+                        .help("Only matches items of this type"),
+                )
+                .arg(
+                    Arg::with_name("pinned")
+                        .long("pinned")
+                        .help("Only matches pinned items"),
+                )
+                .arg(
+                    Arg::with_name("unpinned")
+                        .long("unpinned")
+                        .conflicts_with("pinned")
+                        .help("Only matches unpinned items"),
+                )
+                .arg(
+                    Arg::with_name("newer-than")
+                        .long("newer-than")
+                        .value_name("AGE")
+                        .help("Only matches items copied within AGE, such as 2d"),
+                )
+                .arg(
+                    Arg::with_name("older-than")
+                        .long("older-than")
+                        .value_name("AGE")
+                        .help("Only matches items copied more than AGE ago"),
+                )
+                .arg(
+                    Arg::with_name("numbers")
+                        .short("n")
+                        .long("numbers")
+                        .help("Prints only the item numbers"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Converts the history to another storage format")
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("FORMAT")
+                        .possible_values(&["json", "journal", "sqlite"])
+                        .required(true)
+                        .help("The format to convert to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("unlock")
+                .about("Asks for the passphrase and caches the key for this session"),
+        )
+        .subcommand(SubCommand::with_name("lock").about("Forgets the cached key"))
+        .subcommand(
+            SubCommand::with_name("rekey")
+                .about("Re-encrypts the history under a new passphrase or keyfile")
+                .arg(
+                    Arg::with_name("keyfile")
+                        .long("keyfile")
+                        .value_name("FILE")
+                        .help("Uses FILE as the new key material instead of a passphrase"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("Checks that every stored image and long text is intact")
+                .arg(
+                    Arg::with_name("gc")
+                        .long("gc")
+                        .help("Deletes stored blobs no item refers to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("edit")
+                .about("Edits a text item in $EDITOR")
+                .arg(item_number_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name(owner::SERVE_COMMAND)
+                .setting(AppSettings::Hidden)
+                .arg(
+                    Arg::with_name("selection")
+                        .long("selection")
+                        .value_name("SELECTION")
+                        .possible_values(&["clipboard", "primary", "secondary"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Checks the session, clipboard tools and storage the daemon needs"),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Inspects the configuration")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("show").about("Prints the effective configuration"),
+                ),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .env("CLIPMATE_CONFIG")
+                .value_name("FILE")
+                .global(true)
+                .help("Configuration file [default: $XDG_CONFIG_HOME/clipmate/config.toml]"),
+        )
+        .arg(
+            Arg::with_name("data-dir")
+                .long("data-dir")
+                .env("CLIPMATE_DATA_DIR")
+                .value_name("DIR")
+                .global(true)
+                .help("Directory holding the clipboard history and images"),
+        )
         .arg(
             Arg::with_name("item")
                 .help("Item number to set to current clipboard")
                 .index(1),
         )
+        .arg(
+            Arg::with_name("selection")
+                .long("selection")
+                .value_name("SELECTION")
+                .possible_values(&["clipboard", "primary", "secondary"])
+                .requires("item")
+                .help("Puts the item in this selection instead of the clipboard"),
+        )
         .get_matches();
 
-    let history_file_path = Arc::new("./.clipboard_history.json".to_string());
-    let mut manager = ClipboardManager::new(Arc::clone(&history_file_path));
+    match matches.subcommand() {
+        ("doctor", _) => return run_doctor(&matches),
+        (owner::SERVE_COMMAND, Some(args)) => {
+            let selection = args.value_of("selection").unwrap().parse()?;
+            return owner::serve_stdio(selection).map_err(ClipmateError::Backend);
+        }
+        _ => {}
+    }
+
+    let mut config = load_config(&matches)?;
+    let paths = config.data_paths()?;
+
+    if let ("config", Some(_)) = matches.subcommand() {
+        config.storage.data_dir = Some(paths.data_dir().to_path_buf());
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
+    match matches.subcommand() {
+        ("unlock", _) => return unlock(&config, &paths),
+        ("lock", _) => {
+            crypto::forget_key(&paths)?;
+            println!("Key forgotten");
+            return Ok(());
+        }
+        _ => {}
+    }
 
-    match matches.subcommand_name() {
-        Some("daemon") => {
-            thread::spawn(move || loop {
-                manager.update_clipboard_content();
-                manager.update_image_content();
-                thread::sleep(Duration::from_millis(500));
-            });
-            loop {
-                thread::sleep(Duration::from_millis(1000));
+    if let ("daemon", Some(args)) = matches.subcommand() {
+        let foreground = match args.subcommand() {
+            ("stop", _) => return stop_daemon(&paths),
+            ("status", _) => return daemon_status(&config, &paths),
+            ("install-service", _) => {
+                let path = service::install(&env::current_exe()?, &daemon_args(&matches)?)?;
+                println!("Wrote {}", path.display());
+                println!("Enable it with: systemctl --user enable --now clipmate");
+                return Ok(());
             }
+            ("restart", _) => {
+                stop_daemon(&paths)?;
+                false
+            }
+            ("start", Some(start)) => start.is_present("foreground"),
+            // Without a subcommand the daemon runs in the terminal, as it
+            // always has.
+            _ => true,
+        };
+        if !foreground {
+            return start_daemon(&matches, &config, &paths);
+        }
+    }
+
+    if matches.subcommand_name() == Some("daemon") {
+        logging::init(config.daemon.log_level);
+    } else {
+        logging::init_cli();
+    }
+
+    let legacy_file = Path::new(LEGACY_HISTORY_FILE);
+    if legacy_file.exists() {
+        let migrated = crypto::load_key(&config.encryption, &paths).and_then(|key| {
+            manager::migrate_legacy_history(legacy_file, &paths, key, &config.storage)
+        });
+        match migrated {
+            Ok(true) => eprintln!(
+                "Moved {} from the current directory to {}",
+                LEGACY_HISTORY_FILE,
+                paths.data_dir().display()
+            ),
+            Ok(false) => {}
+            Err(e) => warn!(
+                "Left {} in the current directory, it cannot be moved: {}",
+                LEGACY_HISTORY_FILE, e
+            ),
         }
-        Some("history") => {
-            let history = manager.get_history();
-            for (i, item) in history.iter().enumerate() {
-                println!("{}: {} {:?}", i + 1, item.data, item.item_type);
+    }
+
+    if let ("migrate", Some(args)) = matches.subcommand() {
+        let format: StoreFormat = args.value_of("to").unwrap().parse()?;
+        let key = crypto::load_key(&config.encryption, &paths)?;
+        if store::migrate(&paths, key, format, &config.storage)? {
+            println!("Converted the history to {}", args.value_of("to").unwrap());
+        } else {
+            println!("The history already is in {}", args.value_of("to").unwrap());
+        }
+        return Ok(());
+    }
+
+    if let Some(request) = daemon_request(&matches)? {
+        let response = match Client::connect(&config.socket_path()?)? {
+            Some(mut client) => {
+                let follow = matches!(request, Request::Subscribe);
+                let response = client.request(request)?;
+                if follow {
+                    loop {
+                        if let Response::Recorded { item } = client.receive()? {
+                            print_item(item.number, &item.item);
+                        }
+                    }
+                }
+                response
+            }
+            None => {
+                if let Request::Subscribe = request {
+                    return Err(ClipmateError::InvalidInput(
+                        "following the history needs a running daemon".to_string(),
+                    ));
+                }
+                let mut manager = ClipboardManager::new(config)?;
+                ipc::handle(&mut manager, request).into_result()?
+            }
+        };
+        return print_response(&matches, response);
+    }
+
+    let mut manager = match matches.subcommand_name() {
+        Some("rekey") => ClipboardManager::for_rekey(config, SystemBackend::detect())?,
+        _ => ClipboardManager::new(config)?,
+    };
+
+    match matches.subcommand() {
+        ("daemon", _) => {
+            let pid_file = PidFile::acquire(&paths.pid_file())?;
+            let socket = manager.config().socket_path()?;
+            let listener = ipc::bind(&socket)?;
+            manager.enforce_retention()?;
+            let daemon = &manager.config().daemon;
+            let watcher: Box<dyn Watcher> = match daemon.watch {
+                WatchMode::Poll => Box::new(PollWatcher::from_config(daemon)),
+                WatchMode::Events => Box::new(
+                    watch::events(&manager.config().capture.selections)
+                        .map_err(ClipmateError::Backend)?,
+                ),
+                WatchMode::Auto => match watch::events(&manager.config().capture.selections) {
+                    Ok(watcher) => Box::new(watcher),
+                    Err(e) => {
+                        warn!("Cannot watch the clipboard for changes, polling it: {}", e);
+                        Box::new(PollWatcher::from_config(daemon))
+                    }
+                },
+            };
+            let own_clipboard = daemon.own_clipboard;
+            let mut daemon = Daemon::new(manager, watcher);
+            ipc::serve(listener, daemon.shared_manager(), daemon.subscribers());
+            if own_clipboard {
+                match X11Owner::spawn() {
+                    Ok(owner) => daemon.set_owner(Box::new(owner)),
+                    Err(e) => warn!("Cannot take over the clipboard: {}", e),
+                }
+            }
+            info!(
+                pid = process::id(),
+                watcher = daemon.watcher_name(),
+                socket = %socket.display(),
+                "Started"
+            );
+            handle_signals(&matches, daemon.shared_manager(), pid_file, socket)?;
+            daemon.run();
+        }
+        ("rekey", Some(args)) => {
+            if !manager.config().encryption.enabled {
+                return Err(ClipmateError::InvalidInput(
+                    "encryption is disabled, set encryption.enabled in the config".to_string(),
+                ));
+            }
+            let (key, params) = match args.value_of("keyfile") {
+                Some(keyfile) => KeyParams::generate(&fs::read(keyfile)?)?,
+                None => KeyParams::generate(new_passphrase()?.as_bytes())?,
+            };
+            manager.rekey(key.clone(), &params)?;
+            if args.is_present("keyfile") {
+                println!("History re-encrypted, set encryption.keyfile to the new keyfile");
+            } else {
+                crypto::cache_key(&paths, &key)?;
+                println!("History re-encrypted under the new passphrase");
             }
         }
-        _ => {
-            if let Some(item_number) = matches.value_of("item") {
-                let item_number: usize = item_number
-                    .parse()
-                    .expect("Please provide a valid item number");
-                manager.set_clipboard_text(item_number);
+        ("clear", Some(args)) => {
+            let older_than = args
+                .value_of("older-than")
+                .map(parse_duration)
+                .transpose()?;
+            let deleted = manager.clear(older_than)?;
+            println!("Deleted {} item(s)", deleted);
+        }
+        ("edit", Some(args)) => {
+            let item_number = parse_item_number(args.value_of("item").unwrap())?;
+            let text = edit_in_editor(&manager.item_text(item_number)?)?;
+            manager.edit_text(item_number, text)?;
+            println!("Updated item {}", item_number);
+        }
+        ("fsck", Some(args)) => {
+            let report = manager.fsck()?;
+            for id in &report.missing {
+                println!("missing: {}", id);
+            }
+            for id in &report.corrupt {
+                println!("corrupt: {}", id);
+            }
+            let orphaned = if args.is_present("gc") {
+                let deleted = manager.collect_garbage()?;
+                println!("Deleted {} unreferenced blob(s)", deleted);
+                0
+            } else {
+                for path in &report.orphaned {
+                    println!("unreferenced: {}", path.display());
+                }
+                report.orphaned.len()
+            };
+            println!(
+                "Checked {} blob(s): {} missing, {} corrupt, {} unreferenced",
+                report.checked,
+                report.missing.len(),
+                report.corrupt.len(),
+                orphaned
+            );
+            if !report.missing.is_empty() || !report.corrupt.is_empty() || orphaned > 0 {
+                process::exit(1);
             }
         }
+        _ => {}
     }
+    Ok(())
 }
@@ -1,236 +1,1398 @@
-use clipboard::{ClipboardContext, ClipboardProvider};
+use crate::backend::{ClipboardBackend, Selection, SystemBackend};
+use crate::blobs::{BlobStore, FsckReport};
+use crate::config::{parse_duration, Config, SecretAction, StorageConfig};
+use crate::crypto::{self, Key, KeyParams};
+use crate::error::{ClipmateError, Result};
+use crate::files::{self, GNOME_COPIED_FILES, URI_LIST};
+use crate::lock::HistoryLock;
+use crate::owner::Offer;
+use crate::paths::DataPaths;
+use crate::persist;
+use crate::search::{self, SearchMode, SearchQuery};
+use crate::secrets::{self, SecretDetector, SecretScanner};
+use crate::store::{self, Change, HistoryStore, StoreFormat};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use std::process::Command;
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ClipboardItemType {
     TEXT,
     IMAGE,
+    /// Files copied in a file manager. The item's data lists their paths,
+    /// one per line.
+    FILES,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClipboardItem {
+    /// Identifies the item in the store. Unlike its number, the id does not
+    /// change when older items are removed.
+    #[serde(default)]
+    pub id: u64,
     pub time: u128,
     pub item_type: ClipboardItemType,
     pub data: String,
+    /// Pinned items are never removed by retention limits or `clear`.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Time after which the item is deleted, for secrets stored with the
+    /// `expire` action.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u128>,
+    /// Id of the blob holding the item's contents: the image, or the whole
+    /// text when it was too long to keep in `data`, which then only holds
+    /// its beginning.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+    /// Other representations of the content, such as HTML next to plain
+    /// text, offered again when the item is restored.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub formats: Vec<Format>,
+    /// Copies of the files of a `FILES` item, taken when it was recorded
+    /// if `capture.snapshot_files` is enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub snapshots: Vec<FileSnapshot>,
+    /// The selection the item was copied to.
+    #[serde(default, skip_serializing_if = "Selection::is_clipboard")]
+    pub selection: Selection,
+}
+
+/// A representation of an item's content in another MIME type.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Format {
+    pub mime_type: String,
+    /// Id of the blob holding the data.
+    pub blob: String,
+}
+
+/// The contents of a copied file, kept in case the file is gone by the time
+/// the item is restored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileSnapshot {
+    pub path: PathBuf,
+    /// Id of the blob holding the contents.
+    pub blob: String,
+}
+
+impl ClipboardItem {
+    /// The paths of a `FILES` item.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.data.lines().map(PathBuf::from).collect()
+    }
+
+    fn is_expired(&self, now: u128) -> bool {
+        !self.pinned && self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Ids of every blob the item refers to.
+    fn blob_ids(&self) -> impl Iterator<Item = &String> {
+        self.blob
+            .iter()
+            .chain(self.formats.iter().map(|format| &format.blob))
+            .chain(self.snapshots.iter().map(|snapshot| &snapshot.blob))
+    }
+}
+
+/// Counts over the whole history, as `clipmate stats` prints them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub items: usize,
+    pub pinned: usize,
+    pub text: usize,
+    pub images: usize,
+    pub files: usize,
+    /// Size of the items' contents, including blobs, in bytes.
+    pub bytes: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct ClipboardHistory {
-    items: Vec<ClipboardItem>,
-    image_counter: u128,
-    text_counter: u128,
+    pub(crate) items: Vec<ClipboardItem>,
+    pub(crate) image_counter: u128,
+    pub(crate) text_counter: u128,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) files_counter: u128,
+}
+
+fn is_zero(value: &u128) -> bool {
+    *value == 0
 }
 
 impl ClipboardHistory {
-    fn new() -> ClipboardHistory {
-        ClipboardHistory {
-            items: Vec::new(),
-            image_counter: 0,
-            text_counter: 0,
+    /// Gives ids to items stored by versions that had none.
+    pub(crate) fn assign_ids(&mut self) {
+        let next_id = self.next_id();
+        let unassigned = self.items.iter_mut().filter(|item| item.id == 0);
+        for (id, item) in (next_id..).zip(unassigned) {
+            item.id = id;
         }
     }
 
-    fn add_item(&mut self, item: String, item_type: ClipboardItemType) {
-        if item_type == ClipboardItemType::IMAGE {
-            self.image_counter += 1;
-        }
-        if item_type == ClipboardItemType::TEXT {
-            self.text_counter += 1;
-        }
-        self.items.push(ClipboardItem {
+    fn next_id(&self) -> u64 {
+        self.items.iter().map(|item| item.id).max().unwrap_or(0) + 1
+    }
+
+    fn add_item(&mut self, item: String, item_type: ClipboardItemType) -> &mut ClipboardItem {
+        self.push(ClipboardItem {
+            id: self.next_id(),
             time: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
-                .as_nanos() as u128,
-            item_type: item_type,
+                .as_nanos(),
+            item_type,
             data: item,
+            pinned: false,
+            expires_at: None,
+            blob: None,
+            formats: Vec::new(),
+            snapshots: Vec::new(),
+            selection: Selection::Clipboard,
         });
+        self.items.last_mut().unwrap()
+    }
+
+    /// Appends `item`, counting it in the per-type counters.
+    pub(crate) fn push(&mut self, item: ClipboardItem) {
+        match item.item_type {
+            ClipboardItemType::TEXT => self.text_counter += 1,
+            ClipboardItemType::IMAGE => self.image_counter += 1,
+            ClipboardItemType::FILES => self.files_counter += 1,
+        }
+        self.items.push(item);
     }
 
     fn get_item(&self, index: usize) -> Option<&ClipboardItem> {
         self.items.get(index)
     }
 
-    fn get_counter(&self, item_type: ClipboardItemType) -> u128 {
+    fn get_item_mut(&mut self, index: usize) -> Option<&mut ClipboardItem> {
+        self.items.get_mut(index)
+    }
+
+    /// Removes the items for which `keep` is false and returns them, keeping
+    /// the per-type counters in line with the remaining items.
+    pub(crate) fn remove_items(&mut self, keep: &[bool]) -> Vec<ClipboardItem> {
+        let mut removed = Vec::new();
+        let mut keep = keep.iter();
+        for item in std::mem::take(&mut self.items) {
+            if *keep.next().unwrap_or(&true) {
+                self.items.push(item);
+                continue;
+            }
+            match item.item_type {
+                ClipboardItemType::TEXT => self.text_counter = self.text_counter.saturating_sub(1),
+                ClipboardItemType::IMAGE => {
+                    self.image_counter = self.image_counter.saturating_sub(1)
+                }
+                ClipboardItemType::FILES => {
+                    self.files_counter = self.files_counter.saturating_sub(1)
+                }
+            }
+            removed.push(item);
+        }
+        removed
+    }
+
+    pub(crate) fn get_counter(&self, item_type: ClipboardItemType) -> u128 {
         match item_type {
             ClipboardItemType::TEXT => self.text_counter,
             ClipboardItemType::IMAGE => self.image_counter,
+            ClipboardItemType::FILES => self.files_counter,
         }
     }
 }
 
-#[derive(Default)]
-pub struct ClipboardManager {
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos()
+}
+
+const IMAGE_TYPES: &[(&str, &str)] = &[
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/bmp", "bmp"),
+    ("image/webp", "webp"),
+];
+
+/// File extension used to store images of the given MIME type.
+fn image_extension(mime_type: &str) -> &str {
+    IMAGE_TYPES
+        .iter()
+        .find(|(mime, _)| *mime == mime_type)
+        .map(|(_, ext)| *ext)
+        .unwrap_or_else(|| mime_type.rsplit('/').next().unwrap_or("bin"))
+}
+
+/// MIME type of a stored image, from its file extension.
+fn image_mime_type(image_name: &str) -> String {
+    let ext = image_name.rsplit('.').next().unwrap_or_default();
+    IMAGE_TYPES
+        .iter()
+        .find(|(_, e)| *e == ext)
+        .map(|(mime, _)| mime.to_string())
+        .unwrap_or_else(|| format!("image/{}", ext))
+}
+
+/// How much of a text kept in the blob store is also kept in the history,
+/// for display and search.
+const TEXT_PREVIEW_LEN: usize = 1024;
+
+/// The first `TEXT_PREVIEW_LEN` bytes of `text`, cut at a character
+/// boundary.
+fn text_preview(text: &str) -> String {
+    let mut end = TEXT_PREVIEW_LEN.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+/// The text of an item, if it has any, and its contents by MIME type.
+type ItemContents = (Option<String>, Vec<(String, Vec<u8>)>);
+
+/// Types password managers offer next to a secret to ask clipboard managers
+/// not to record it.
+const CONCEALED_TYPES: &[&str] = &[
+    "x-kde-passwordManagerHint",
+    "application/x-nspasteboard-concealed-type",
+    "application/x-nspasteboard-transient-type",
+    "org.nspasteboard.ConcealedType",
+    "org.nspasteboard.TransientType",
+];
+
+pub struct ClipboardManager<B: ClipboardBackend = SystemBackend> {
     history: ClipboardHistory,
-    history_file_path: Arc<String>,
-    xclip_available: bool,
+    config: Config,
+    paths: DataPaths,
+    ignore: Vec<Regex>,
+    secrets: SecretScanner,
+    /// The last text read from each selection, so that text which was
+    /// masked or skipped is not processed again on every poll.
+    last_seen_text: HashMap<Selection, String>,
+    /// Text read from a debounced selection, with when it was first seen,
+    /// until it is recorded.
+    pending_text: HashMap<Selection, (String, Instant)>,
+    /// The selection being read, which new items are recorded from.
+    capturing: Selection,
+    /// The types the selection being read offers, listed once per read.
+    offered: Option<Vec<String>>,
+    /// Key encrypting the history and images, if encryption is enabled.
+    key: Option<Key>,
+    store: Box<dyn HistoryStore>,
+    blobs: BlobStore,
+    /// Coordinates with the other processes using the history.
+    lock: HistoryLock,
+    /// The generation of the history as last loaded or written, which tells
+    /// whether another process changed it since.
+    generation: u64,
+    backend: B,
+}
+
+/// The regexes of the text not to record.
+fn compile_ignore(config: &Config) -> Result<Vec<Regex>> {
+    config
+        .capture
+        .ignore
+        .iter()
+        .map(|pattern| Regex::new(pattern))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| ClipmateError::Config(e.to_string()))
+}
+
+/// Opens the history in `paths` with its store and blobs.
+fn open_history(
+    config: &Config,
+    paths: &DataPaths,
+    key: Option<Key>,
+) -> Result<(Box<dyn HistoryStore>, ClipboardHistory, BlobStore)> {
+    let mut store = store::open(
+        StoreFormat::detect(paths),
+        paths,
+        key.clone(),
+        &config.storage,
+    )?;
+    let history = store.load()?;
+    let mut blobs = BlobStore::new(paths.blobs_dir(), key, config.storage.compress);
+    for id in history.items.iter().flat_map(ClipboardItem::blob_ids) {
+        blobs.retain(id);
+    }
+    Ok((store, history, blobs))
 }
 
 impl ClipboardManager {
-    pub fn new(history_file_path: Arc<String>) -> ClipboardManager {
-        let history: ClipboardHistory;
-        let file = File::open(&*history_file_path);
+    pub fn new(config: Config) -> Result<ClipboardManager> {
+        let backend = SystemBackend::detect();
+        ClipboardManager::with_backend(config, backend)
+    }
+}
+
+impl<B: ClipboardBackend> ClipboardManager<B> {
+    pub fn with_backend(config: Config, backend: B) -> Result<ClipboardManager<B>> {
+        ClipboardManager::open(config, backend, false)
+    }
+
+    /// Opens the history for `rekey`, reading data that is not encrypted
+    /// yet even though encryption is enabled. Everywhere else such data is
+    /// refused.
+    pub fn for_rekey(config: Config, backend: B) -> Result<ClipboardManager<B>> {
+        ClipboardManager::open(config, backend, true)
+    }
+
+    fn open(config: Config, backend: B, accept_plaintext: bool) -> Result<ClipboardManager<B>> {
+        let paths = config.data_paths()?;
+        let mut key = crypto::load_key(&config.encryption, &paths)?;
+        if accept_plaintext {
+            key = key.map(Key::accepting_plaintext);
+        }
+        let lock = HistoryLock::open(&paths)?;
+        let guard = lock.shared()?;
+        let generation = lock.generation()?;
+        let (store, history, blobs) = open_history(&config, &paths, key.clone())?;
+        drop(guard);
+        let ignore = compile_ignore(&config)?;
+        let secrets = SecretScanner::from_config(&config.secrets)?;
+
+        let mut manager = ClipboardManager {
+            history,
+            config,
+            paths,
+            ignore,
+            secrets,
+            last_seen_text: HashMap::new(),
+            pending_text: HashMap::new(),
+            capturing: Selection::Clipboard,
+            offered: None,
+            key,
+            store,
+            blobs,
+            lock,
+            generation,
+            backend,
+        };
+        manager.write(Self::import_images)?;
+        Ok(manager)
+    }
+
+    /// Loads the history again if another process changed it since it was
+    /// last loaded. Returns whether it did.
+    pub fn refresh(&mut self) -> Result<bool> {
+        let _guard = self.lock.shared()?;
+        self.refresh_locked()
+    }
+
+    fn refresh_locked(&mut self) -> Result<bool> {
+        let generation = self.lock.generation()?;
+        if generation == self.generation {
+            return Ok(false);
+        }
+        // The key may have changed with the history, after a rekey.
+        let key = crypto::load_key(&self.config.encryption, &self.paths)?;
+        let (store, history, blobs) = open_history(&self.config, &self.paths, key.clone())?;
+        self.key = key;
+        self.store = store;
+        self.history = history;
+        self.blobs = blobs;
+        self.generation = generation;
+        Ok(true)
+    }
+
+    /// Runs `change` holding the history lock exclusively, on the history
+    /// as it is stored now rather than as it was loaded, so that changes
+    /// other processes made in between are kept.
+    fn write<T>(&mut self, change: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let _guard = self.lock.exclusive()?;
+        self.refresh_locked()?;
+        change(self)
+    }
 
-        match file {
-            Ok(mut f) => {
-                let mut contents = String::new();
-                f.read_to_string(&mut contents)
-                    .expect("Failed to read the file");
-                history = serde_json::from_str(&contents).expect("Failed to parse the file");
+    /// Persists `changes`, and tells the other processes the history
+    /// changed.
+    fn commit(&mut self, changes: &[Change]) -> Result<()> {
+        self.store.commit(&self.history, changes)?;
+        self.generation = self.lock.bump()?;
+        Ok(())
+    }
+
+    /// Moves the image files written by versions without a blob store into
+    /// it. Items whose file is missing are left as they are.
+    fn import_images(&mut self) -> Result<()> {
+        let images_dir = self.paths.images_dir();
+        if !images_dir.exists() {
+            return Ok(());
+        }
+        let mut updated = Vec::new();
+        for item in self.history.items.iter_mut() {
+            if item.item_type != ClipboardItemType::IMAGE || item.blob.is_some() {
+                continue;
             }
-            Err(_) => {
-                history = ClipboardHistory::new();
+            let data = match fs::read(images_dir.join(&item.data)) {
+                Ok(data) => data,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let data = crypto::open(self.key.as_ref(), data)?;
+            item.blob = Some(self.blobs.put(&data)?);
+            updated.push(item.clone());
+        }
+        if !updated.is_empty() {
+            let changes: Vec<Change> = updated.iter().map(Change::Update).collect();
+            self.commit(&changes)?;
+        }
+        for item in &updated {
+            match fs::remove_file(images_dir.join(&item.data)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
             }
         }
+        // Fails if files no item refers to are left, which is fine.
+        let _ = fs::remove_dir(&images_dir);
+        Ok(())
+    }
 
-        ClipboardManager {
-            history,
-            history_file_path,
-            xclip_available: true,
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Applies a changed configuration, as the daemon does on SIGHUP. The
+    /// store is opened again if its settings changed, and the retention
+    /// limits are enforced right away. The data directory and the key stay
+    /// the same, and detectors added with `add_secret_detector` are dropped.
+    pub fn set_config(&mut self, mut config: Config) -> Result<()> {
+        let ignore = compile_ignore(&config)?;
+        let secrets = SecretScanner::from_config(&config.secrets)?;
+        config.storage.data_dir = Some(self.paths.data_dir().to_path_buf());
+        let reopen = config.storage != self.config.storage;
+        self.ignore = ignore;
+        self.secrets = secrets;
+        self.config = config;
+        if reopen {
+            let _guard = self.lock.shared()?;
+            let (store, history, blobs) =
+                open_history(&self.config, &self.paths, self.key.clone())?;
+            self.store = store;
+            self.history = history;
+            self.blobs = blobs;
+            self.generation = self.lock.generation()?;
+        }
+        self.enforce_retention()?;
+        Ok(())
+    }
+
+    /// Records the text still waiting for the debounce delay and compacts
+    /// the stored history, so that nothing is lost when the daemon stops.
+    pub fn flush(&mut self) -> Result<()> {
+        self.write(|manager| {
+            let mut pending: Vec<_> = manager.pending_text.drain().collect();
+            pending.sort_by_key(|(_, (_, since))| *since);
+            let mut result = Ok(());
+            for (selection, (text, _)) in pending {
+                manager.capturing = selection;
+                manager.last_seen_text.insert(selection, text.clone());
+                if result.is_ok() && !manager.is_last_clipboard_text(&text) {
+                    result = manager.save_text_with_formats(text, Vec::new());
+                }
+            }
+            manager.capturing = Selection::Clipboard;
+            result?;
+            manager.compact_locked()?;
+            Ok(())
+        })
+    }
+
+    pub fn paths(&self) -> &DataPaths {
+        &self.paths
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn get_counter(&self, item_type: ClipboardItemType) -> u128 {
+        self.history.get_counter(item_type)
+    }
+
+    pub fn save_text(&mut self, text: String) -> Result<()> {
+        self.write(|manager| manager.save_text_with_formats(text, Vec::new()))
+    }
+
+    /// Saves `text` along with its representations in other types, which
+    /// are dropped if the text holds a secret.
+    fn save_text_with_formats(
+        &mut self,
+        text: String,
+        mut formats: Vec<(String, Vec<u8>)>,
+    ) -> Result<()> {
+        if text.is_empty() || self.ignore.iter().any(|re| re.is_match(&text)) {
+            return Ok(());
+        }
+
+        let found = self.secrets.scan(&text);
+        let mut expires_at = None;
+        let text = if found.is_empty() {
+            text
+        } else {
+            formats.clear();
+            match self.config.secrets.action {
+                SecretAction::Skip => return Ok(()),
+                SecretAction::Mask => secrets::mask(&text, &found),
+                SecretAction::Expire => {
+                    let keep_for = parse_duration(&self.config.secrets.expire_after)?;
+                    expires_at = Some(now_nanos() + keep_for.as_nanos());
+                    text
+                }
+            }
+        };
+
+        let (text, blob) = self.store_text(text)?;
+        let formats = self.store_formats(formats)?;
+        let item = self.history.add_item(text, ClipboardItemType::TEXT);
+        item.expires_at = expires_at;
+        item.blob = blob;
+        item.formats = formats;
+        item.selection = self.capturing;
+        let item = item.clone();
+        self.add_and_save(item)
+    }
+
+    /// Puts `text` in the blob store if it is over the threshold, returning
+    /// what to keep in the item's `data` and `blob`.
+    fn store_text(&mut self, text: String) -> Result<(String, Option<String>)> {
+        if text.len() <= self.config.storage.blob_threshold {
+            return Ok((text, None));
+        }
+        let id = self.blobs.put(text.as_bytes())?;
+        Ok((text_preview(&text), Some(id)))
+    }
+
+    fn store_formats(&mut self, formats: Vec<(String, Vec<u8>)>) -> Result<Vec<Format>> {
+        formats
+            .into_iter()
+            .map(|(mime_type, data)| {
+                Ok(Format {
+                    mime_type,
+                    blob: self.blobs.put(&data)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Drops items beyond the configured retention limits and deletes the
+    /// blobs they leave unreferenced, along with any other blob no item
+    /// refers to. Returns how many items were removed.
+    ///
+    /// Limits are enforced on every insert; the daemon also calls this on
+    /// startup so that changed limits and expired items take effect.
+    pub fn enforce_retention(&mut self) -> Result<usize> {
+        self.write(|manager| {
+            let removed = manager.prune();
+            if !removed.is_empty() {
+                manager.commit(&[Change::Prune(&removed)])?;
+            }
+            manager.release_blobs(&removed)?;
+            manager.blobs.gc()?;
+            Ok(removed.len())
+        })
+    }
+
+    /// Removes expired items and those exceeding the retention limits from
+    /// the in-memory history and returns them. Pinned items neither count
+    /// towards the limits nor get removed.
+    fn prune(&mut self) -> Vec<ClipboardItem> {
+        let now = now_nanos();
+        let mut keep: Vec<bool> = self
+            .history
+            .items
+            .iter()
+            .map(|item| !item.is_expired(now))
+            .collect();
+
+        let history_config = &self.config.history;
+        let policies = [
+            (history_config.text.clone(), Some(ClipboardItemType::TEXT)),
+            (history_config.image.clone(), Some(ClipboardItemType::IMAGE)),
+            (history_config.overall(), None),
+        ];
+        if policies.iter().all(|(policy, _)| policy.is_unlimited()) {
+            return self.history.remove_items(&keep);
+        }
+
+        let sizes: Vec<u64> = self
+            .history
+            .items
+            .iter()
+            .map(|item| self.item_size(item))
+            .collect();
+
+        for (policy, item_type) in &policies {
+            let max_age = policy.max_age().map(|age| age.as_nanos());
+            let mut count = 0;
+            let mut bytes = 0;
+            // Walk from the newest item so the oldest ones are dropped first.
+            for (i, item) in self.history.items.iter().enumerate().rev() {
+                if !keep[i] || item.pinned || item_type.is_some_and(|t| t != item.item_type) {
+                    continue;
+                }
+                count += 1;
+                bytes += sizes[i];
+                let expired = max_age.is_some_and(|age| now.saturating_sub(item.time) > age);
+                if expired
+                    || policy.max_items.is_some_and(|max| count > max)
+                    || policy.max_bytes.is_some_and(|max| bytes > max)
+                {
+                    keep[i] = false;
+                }
+            }
         }
+
+        self.history.remove_items(&keep)
     }
 
-    pub fn save_text(&mut self, text: String) {
-        if text.len() < 1 {
-            return;
+    /// Deletes the items whose expiry time has passed. The daemon calls this
+    /// on every poll.
+    pub fn remove_expired(&mut self) -> Result<usize> {
+        self.write(|manager| {
+            let now = now_nanos();
+            if !manager
+                .history
+                .items
+                .iter()
+                .any(|item| item.is_expired(now))
+            {
+                return Ok(0);
+            }
+            let keep: Vec<bool> = manager
+                .history
+                .items
+                .iter()
+                .map(|item| !item.is_expired(now))
+                .collect();
+            manager.remove_and_save(&keep, true)
+        })
+    }
+
+    /// Adds a detector to those run on every saved text, on top of the ones
+    /// enabled in the config.
+    pub fn add_secret_detector(&mut self, detector: Box<dyn SecretDetector>) {
+        self.secrets.add(detector);
+    }
+
+    fn item_size(&self, item: &ClipboardItem) -> u64 {
+        let inline = match item.item_type {
+            ClipboardItemType::TEXT if item.blob.is_none() => item.data.len() as u64,
+            _ => 0,
+        };
+        inline + item.blob_ids().map(|id| self.blobs.size(id)).sum::<u64>()
+    }
+
+    /// Drops the references `removed` items held on their blobs.
+    fn release_blobs(&mut self, removed: &[ClipboardItem]) -> Result<()> {
+        for id in removed.iter().flat_map(ClipboardItem::blob_ids) {
+            self.blobs.release(id)?;
         }
-        self.history.add_item(text, ClipboardItemType::TEXT);
-        self.save_history();
+        Ok(())
     }
 
-    fn save_history(&self) {
-        let history_string = serde_json::to_string(&self.history).unwrap();
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&*self.history_file_path)
-            .unwrap();
-        file.write_all(history_string.as_bytes())
-            .expect("Failed to write to the file");
+    /// Checks that every blob the history refers to is intact, and lists
+    /// the blobs nothing refers to.
+    pub fn fsck(&self) -> Result<FsckReport> {
+        self.blobs.verify()
+    }
+
+    /// Deletes the blobs no item refers to and returns how many there were.
+    /// Blobs other processes added since the history was loaded are kept.
+    pub fn collect_garbage(&mut self) -> Result<usize> {
+        self.write(|manager| manager.blobs.gc())
+    }
+
+    /// Re-encrypts the history and every blob under `key`, encrypting them
+    /// for the first time if they were stored in plaintext, and stores
+    /// `params` for it once that succeeded, so that the old key still opens
+    /// the history if it failed.
+    ///
+    /// No copy of the history readable with the old key is left behind.
+    pub fn rekey(&mut self, key: Key, params: &KeyParams) -> Result<()> {
+        self.write(|manager| {
+            let ids = manager.blobs.rekey(key.clone())?;
+            for item in manager.history.items.iter_mut() {
+                if let Some(id) = item.blob.as_mut() {
+                    *id = ids[id.as_str()].clone();
+                }
+                for format in item.formats.iter_mut() {
+                    format.blob = ids[format.blob.as_str()].clone();
+                }
+                for snapshot in item.snapshots.iter_mut() {
+                    snapshot.blob = ids[snapshot.blob.as_str()].clone();
+                }
+            }
+
+            manager.store.set_key(Some(key.clone()));
+            manager.key = Some(key);
+            manager.store.replace(&manager.history)?;
+            params.save(&manager.paths)?;
+            manager.generation = manager.lock.bump()?;
+            manager.blobs.gc()?;
+            Ok(())
+        })
+    }
+
+    /// Compacts the stored history if the store has grown enough to need
+    /// it. The daemon calls this on every poll.
+    pub fn compact_history(&mut self) -> Result<bool> {
+        self.write(Self::compact_locked)
+    }
+
+    fn compact_locked(&mut self) -> Result<bool> {
+        let compacted = self.store.compact(&self.history)?;
+        if compacted {
+            self.generation = self.lock.bump()?;
+        }
+        Ok(compacted)
     }
 
     pub fn get_history(&self) -> &Vec<ClipboardItem> {
         &self.history.items
     }
 
-    pub fn set_clipboard_text(&self, item_number: usize) {
-        let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
-        match self.history.get_item(item_number - 1) {
-            Some(item) => {
-                match item.item_type {
-                    ClipboardItemType::TEXT => {
-                        ctx.set_contents(item.data.to_string()).unwrap();
-                    }
-                    ClipboardItemType::IMAGE => {
-                        Command::new("xclip")
-                            .args(&["-selection", "clipboard", "-t", "image/png"])
-                            .stdin(std::process::Stdio::piped())
-                            .spawn()
-                            .unwrap()
-                            .stdin
-                            .unwrap()
-                            .write_all(
-                                std::fs::read(&item.data)
-                                    .expect("Failed to read image file")
-                                    .as_slice(),
-                            )
-                            .unwrap();
-                    }
-                }
-                println!("Clipboard set to item {}", item_number);
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        for item in &self.history.items {
+            stats.items += 1;
+            stats.pinned += item.pinned as usize;
+            match item.item_type {
+                ClipboardItemType::TEXT => stats.text += 1,
+                ClipboardItemType::IMAGE => stats.images += 1,
+                ClipboardItemType::FILES => stats.files += 1,
             }
-            None => {
-                println!("Item {} not found in clipboard history", item_number);
+            stats.bytes += self.item_size(item);
+        }
+        stats
+    }
+
+    /// Returns the items matching `query` with their 1-based numbers.
+    ///
+    /// Substring searches are narrowed down with the store's text index, if
+    /// it has one.
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<(usize, &ClipboardItem)>> {
+        let candidates = match query.mode {
+            SearchMode::Substring | SearchMode::IgnoreCase => {
+                self.store.find_text(&query.pattern)?
             }
+            SearchMode::Regex | SearchMode::Fuzzy => None,
+        };
+        match candidates {
+            Some(ids) => search::search_where(&self.history.items, query, |item| {
+                item.item_type != ClipboardItemType::TEXT || ids.contains(&item.id)
+            }),
+            None => search::search(&self.history.items, query),
+        }
+    }
+
+    /// Returns the whole text of the text item with the given (1-based)
+    /// number, reading it from the blob store if it is kept there.
+    pub fn item_text(&self, item_number: usize) -> Result<String> {
+        let item = self.get_item(item_number)?;
+        if item.item_type != ClipboardItemType::TEXT {
+            return Err(ClipmateError::InvalidInput(format!(
+                "item {} is not a text item",
+                item_number
+            )));
         }
+        self.full_text(item)
+    }
+
+    fn full_text(&self, item: &ClipboardItem) -> Result<String> {
+        match &item.blob {
+            Some(id) => String::from_utf8(self.blobs.get(id)?)
+                .map_err(|_| ClipmateError::InvalidInput(format!("blob {} is not UTF-8", id))),
+            None => Ok(item.data.clone()),
+        }
+    }
+
+    /// Returns the item with the given (1-based) number.
+    pub fn get_item(&self, item_number: usize) -> Result<&ClipboardItem> {
+        item_number
+            .checked_sub(1)
+            .and_then(|index| self.history.get_item(index))
+            .ok_or(ClipmateError::ItemNotFound(item_number))
+    }
+
+    fn get_item_mut(&mut self, item_number: usize) -> Result<&mut ClipboardItem> {
+        item_number
+            .checked_sub(1)
+            .and_then(|index| self.history.get_item_mut(index))
+            .ok_or(ClipmateError::ItemNotFound(item_number))
+    }
+
+    /// Pins or unpins the item with the given (1-based) number.
+    pub fn set_pinned(&mut self, item_number: usize, pinned: bool) -> Result<()> {
+        self.write(|manager| {
+            let item = manager.get_item_mut(item_number)?;
+            if item.pinned == pinned {
+                return Ok(());
+            }
+            item.pinned = pinned;
+            let item = item.clone();
+            manager.commit(&[Change::Update(&item)])
+        })
+    }
+
+    /// Deletes the items with the given (1-based) numbers, pinned or not,
+    /// along with the blobs only they referred to. Fails without deleting
+    /// anything if a number does not exist.
+    pub fn delete(&mut self, item_numbers: &[usize]) -> Result<usize> {
+        self.write(|manager| {
+            let mut keep = vec![true; manager.history.items.len()];
+            for &item_number in item_numbers {
+                manager.get_item(item_number)?;
+                keep[item_number - 1] = false;
+            }
+            manager.remove_and_save(&keep, false)
+        })
+    }
+
+    /// Deletes the unpinned items numbered `first` to `last`, inclusive.
+    /// `last` may run past the end of the history.
+    pub fn delete_range(&mut self, first: usize, last: usize) -> Result<usize> {
+        self.write(|manager| {
+            if first == 0 || first > last {
+                return Err(ClipmateError::InvalidInput(format!(
+                    "invalid range {}..{}",
+                    first, last
+                )));
+            }
+            manager.get_item(first)?;
+            let keep: Vec<bool> = manager
+                .history
+                .items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| item.pinned || i + 1 < first || i + 1 > last)
+                .collect();
+            manager.remove_and_save(&keep, false)
+        })
+    }
+
+    /// Deletes every unpinned item, or only those older than `older_than`.
+    pub fn clear(&mut self, older_than: Option<Duration>) -> Result<usize> {
+        self.write(|manager| {
+            let now = now_nanos();
+            let keep: Vec<bool> = manager
+                .history
+                .items
+                .iter()
+                .map(|item| {
+                    item.pinned
+                        || older_than
+                            .is_some_and(|age| now.saturating_sub(item.time) <= age.as_nanos())
+                })
+                .collect();
+            manager.remove_and_save(&keep, false)
+        })
+    }
+
+    /// Replaces the text of a text item.
+    pub fn edit_text(&mut self, item_number: usize, text: String) -> Result<()> {
+        self.write(|manager| {
+            let item = manager.get_item_mut(item_number)?;
+            if item.item_type != ClipboardItemType::TEXT {
+                return Err(ClipmateError::InvalidInput(format!(
+                    "item {} is not a text item",
+                    item_number
+                )));
+            }
+            if text.is_empty() {
+                return Err(ClipmateError::InvalidInput(
+                    "the edited text is empty, use delete to remove an item".to_string(),
+                ));
+            }
+            let item = item.clone();
+            if manager.full_text(&item)? == text {
+                return Ok(());
+            }
+            let (data, blob) = manager.store_text(text)?;
+            let item_mut = manager.get_item_mut(item_number)?;
+            item_mut.data = data;
+            item_mut.blob = blob;
+            let updated = item_mut.clone();
+            manager.commit(&[Change::Edit(&updated)])?;
+            manager.release_blobs(&[item])
+        })
     }
 
-    pub fn save_image(&mut self, image_data: Vec<u8>, image_name: String) {
-        if std::path::Path::new(&image_name).exists() {
-            return;
+    /// Removes the items not to `keep`, as pruned ones if `pruned` and as
+    /// removed on request otherwise.
+    fn remove_and_save(&mut self, keep: &[bool], pruned: bool) -> Result<usize> {
+        let removed = self.history.remove_items(keep);
+        if removed.is_empty() {
+            return Ok(0);
         }
+        let change = if pruned {
+            Change::Prune(&removed)
+        } else {
+            Change::Remove(&removed)
+        };
+        self.commit(&[change])?;
+        self.release_blobs(&removed)?;
+        Ok(removed.len())
+    }
 
-        let mut file = File::create(&image_name).expect("Failed to create image file");
-        file.write_all(&image_data)
-            .expect("Failed to write to image file");
+    pub fn set_clipboard_text(&mut self, item_number: usize) -> Result<()> {
+        self.restore_to(item_number, Selection::Clipboard)
+    }
 
-        self.history
-            .add_item(image_name.clone(), ClipboardItemType::IMAGE);
-        self.save_history();
-        // println!("Image saved as {}", image_name);
+    /// Puts the item with the given (1-based) number in `selection`.
+    pub fn restore_to(&mut self, item_number: usize, selection: Selection) -> Result<()> {
+        self.backend
+            .select(selection)
+            .map_err(ClipmateError::Backend)?;
+        let result = self.write_item(item_number);
+        self.backend
+            .select(Selection::Clipboard)
+            .map_err(ClipmateError::Backend)?;
+        result
     }
 
-    fn last_clipboard_image(&self) -> String {
-        self.history
+    fn write_item(&mut self, item_number: usize) -> Result<()> {
+        let (text, formats) = self.item_contents(item_number)?;
+        self.backend
+            .write_formats(text.as_deref(), &formats)
+            .map_err(ClipmateError::Backend)
+    }
+
+    /// What the item with the given (1-based) number offers to applications
+    /// pasting it.
+    pub fn offer(&self, item_number: usize) -> Result<Offer> {
+        let (text, formats) = self.item_contents(item_number)?;
+        Ok(Offer::new(text, formats))
+    }
+
+    /// The text of an item and its other representations, as MIME types
+    /// with their data.
+    fn item_contents(&self, item_number: usize) -> Result<ItemContents> {
+        let item = item_number
+            .checked_sub(1)
+            .and_then(|index| self.history.get_item(index))
+            .ok_or(ClipmateError::ItemNotFound(item_number))?;
+
+        let mut formats = Vec::new();
+        for format in &item.formats {
+            formats.push((format.mime_type.clone(), self.blobs.get(&format.blob)?));
+        }
+        match item.item_type {
+            ClipboardItemType::TEXT => Ok((Some(self.full_text(item)?), formats)),
+            ClipboardItemType::IMAGE => {
+                let image_data = match &item.blob {
+                    Some(id) => self.blobs.get(id)?,
+                    None => {
+                        return Err(ClipmateError::InvalidInput(format!(
+                            "the image of item {} is missing",
+                            item_number
+                        )))
+                    }
+                };
+                formats.insert(0, (image_mime_type(&item.data), image_data));
+                Ok((None, formats))
+            }
+            ClipboardItemType::FILES => {
+                let paths = self.restore_files(item)?;
+                // File managers paste the first two, editors the plain text.
+                let formats = [
+                    (URI_LIST.to_string(), files::to_uri_list(&paths)),
+                    (
+                        GNOME_COPIED_FILES.to_string(),
+                        files::to_gnome_copied_files(&paths),
+                    ),
+                    ("text/plain;charset=utf-8".to_string(), item.data.clone()),
+                ]
+                .map(|(mime_type, data)| (mime_type, data.into_bytes()));
+                Ok((None, formats.to_vec()))
+            }
+        }
+    }
+
+    /// The paths of a `FILES` item, with those of files that no longer exist
+    /// replaced by copies written from their snapshots, if there are any.
+    fn restore_files(&self, item: &ClipboardItem) -> Result<Vec<PathBuf>> {
+        let mut paths = item.paths();
+        for path in paths.iter_mut() {
+            if path.exists() {
+                continue;
+            }
+            let Some(snapshot) = item.snapshots.iter().find(|s| s.path == *path) else {
+                continue;
+            };
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            let restored = self.paths.restored_dir().join(name);
+            fs::create_dir_all(self.paths.restored_dir())?;
+            fs::write(&restored, self.blobs.get(&snapshot.blob)?)?;
+            *path = restored;
+        }
+        Ok(paths)
+    }
+
+    /// Saves a list of copied files, unless it is the same as the newest
+    /// one in the history. Their contents are stored too if
+    /// `capture.snapshot_files` is enabled.
+    pub fn save_files(&mut self, paths: Vec<PathBuf>) -> Result<()> {
+        self.write(|manager| manager.save_files_locked(paths))
+    }
+
+    fn save_files_locked(&mut self, paths: Vec<PathBuf>) -> Result<()> {
+        if let Some(path) = paths
+            .iter()
+            .find(|path| path.to_string_lossy().contains(['\n', '\r']))
+        {
+            return Err(ClipmateError::InvalidInput(format!(
+                "cannot record {:?}, file names with line breaks are not supported",
+                path
+            )));
+        }
+        let data = paths
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let last = self
+            .history
             .items
             .iter()
             .rev()
-            .find(|x| x.item_type == ClipboardItemType::IMAGE)
-            .map_or_else(|| String::from(""), |item| item.data.clone())
+            .find(|x| x.item_type == ClipboardItemType::FILES);
+        if data.is_empty() || last.is_some_and(|item| item.data == data) {
+            return Ok(());
+        }
+
+        let mut snapshots = Vec::new();
+        if self.config.capture.snapshot_files {
+            for path in paths {
+                match fs::metadata(&path) {
+                    Ok(meta)
+                        if meta.is_file()
+                            && meta.len() <= self.config.capture.snapshot_max_size => {}
+                    _ => continue,
+                }
+                let blob = self.blobs.put(&fs::read(&path)?)?;
+                snapshots.push(FileSnapshot { path, blob });
+            }
+        }
+        let item = self.history.add_item(data, ClipboardItemType::FILES);
+        item.snapshots = snapshots;
+        item.selection = self.capturing;
+        let item = item.clone();
+        self.add_and_save(item)
+    }
+
+    /// Saves an image of the given MIME type, unless the same image is in
+    /// the history already.
+    pub fn save_image(&mut self, image_data: Vec<u8>, mime_type: &str) -> Result<()> {
+        self.write(|manager| manager.save_image_with_formats(image_data, mime_type, Vec::new()))
+    }
+
+    fn save_image_with_formats(
+        &mut self,
+        image_data: Vec<u8>,
+        mime_type: &str,
+        formats: Vec<(String, Vec<u8>)>,
+    ) -> Result<()> {
+        if self.blobs.refcount(&self.blobs.id_of(&image_data)) > 0 {
+            return Ok(());
+        }
+        let id = self.blobs.put(&image_data)?;
+        let formats = self.store_formats(formats)?;
+        let image_name = format!("{}.{}", id, image_extension(mime_type));
+        let item = self.history.add_item(image_name, ClipboardItemType::IMAGE);
+        item.blob = Some(id);
+        item.formats = formats;
+        item.selection = self.capturing;
+        let item = item.clone();
+        self.add_and_save(item)
+    }
+
+    /// Saves `item`, just added to the history, and drops the items it
+    /// pushes past the retention limits.
+    fn add_and_save(&mut self, item: ClipboardItem) -> Result<()> {
+        let removed = self.prune();
+        self.commit(&[Change::Insert(&item), Change::Prune(&removed)])?;
+        self.release_blobs(&removed)
     }
 
-    fn last_clipboard_text(&self) -> String {
-        self.history
+    /// Whether `text` is the text of the newest text item.
+    fn is_last_clipboard_text(&self, text: &str) -> bool {
+        let last = self
+            .history
             .items
             .iter()
             .rev() // reverse the iterator so we start from the end
-            .find(|x| x.item_type == ClipboardItemType::TEXT)
-            .map_or_else(|| String::from(""), |item| item.data.clone())
+            .find(|x| x.item_type == ClipboardItemType::TEXT);
+        match last {
+            Some(ClipboardItem { blob: Some(id), .. }) => self.blobs.id_of(text.as_bytes()) == *id,
+            Some(item) => item.data == text,
+            None => text.is_empty(),
+        }
     }
 
-    pub fn update_clipboard_content(&mut self) {
-        let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
-        match ctx.get_contents() {
-            Ok(content) => {
-                if content != self.last_clipboard_text() {
-                    self.save_text(content);
-                }
+    /// Whether the clipboard content is flagged as concealed or transient by
+    /// the application that copied it. Backends that cannot list types never
+    /// report concealed content.
+    fn is_concealed(&mut self) -> bool {
+        self.offered_types()
+            .iter()
+            .any(|t| CONCEALED_TYPES.contains(&t.as_str()))
+    }
+
+    /// The types the selection being read offers, asked from the backend
+    /// only once per selection by `for_each_selection`, as listing them
+    /// may run a process. Backends that cannot list types offer none.
+    fn offered_types(&mut self) -> &[String] {
+        let backend = &mut self.backend;
+        self.offered
+            .get_or_insert_with(|| backend.available_types().unwrap_or_default())
+    }
+
+    /// Reads the configured extra formats the clipboard offers, other than
+    /// `except`. Formats that fail to read are left out.
+    fn read_formats(&mut self, except: &str) -> Vec<(String, Vec<u8>)> {
+        let offered = self.offered_types().to_vec();
+        let mut formats = Vec::new();
+        for mime_type in &self.config.capture.formats {
+            if mime_type == except || !offered.contains(mime_type) {
+                continue;
             }
-            Err(_) => {
-                println!("Error while getting text content from the clipboard");
+            match self.backend.read_type(mime_type) {
+                Ok(data) if !data.is_empty() => formats.push((mime_type.clone(), data)),
+                _ => {}
             }
         }
+        formats
     }
 
-    pub fn update_image_content(&mut self) {
-        if !self.xclip_available {
-            return;
+    /// Reads the files on the clipboard, if it holds copied files rather
+    /// than text.
+    fn read_files(&mut self) -> Option<Vec<PathBuf>> {
+        let offered = self.offered_types().to_vec();
+        let read = |backend: &mut B, mime_type: &str| {
+            backend
+                .read_type(mime_type)
+                .ok()
+                .and_then(|data| String::from_utf8(data).ok())
+        };
+        if offered.iter().any(|t| t == GNOME_COPIED_FILES) {
+            if let Some(paths) = read(&mut self.backend, GNOME_COPIED_FILES)
+                .and_then(|data| files::parse_gnome_copied_files(&data))
+            {
+                return Some(paths);
+            }
+        }
+        if offered.iter().any(|t| t == URI_LIST) {
+            return read(&mut self.backend, URI_LIST).and_then(|data| files::parse_uri_list(&data));
         }
-        let output = Command::new("xclip")
-            .args(&["-selection", "clipboard", "-t", "image/png", "-o"])
-            .output();
+        None
+    }
 
-        if output.is_err() {
-            self.xclip_available = false;
-            return;
+    /// Runs `update` on every watched selection in turn, with the backend
+    /// and `capturing` set to it. Returns the first error, after all
+    /// selections were tried.
+    fn for_each_selection(
+        &mut self,
+        mut update: impl FnMut(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let mut result = Ok(());
+        for selection in self.config.capture.selections.clone() {
+            self.capturing = selection;
+            self.offered = None;
+            let updated = self
+                .backend
+                .select(selection)
+                .map_err(ClipmateError::Backend)
+                .and_then(|()| update(self));
+            if result.is_ok() {
+                result = updated;
+            }
         }
+        self.capturing = Selection::Clipboard;
+        self.offered = None;
+        self.backend
+            .select(Selection::Clipboard)
+            .map_err(ClipmateError::Backend)?;
+        result
+    }
 
-        let image_data = output.unwrap().stdout;
+    /// Records the text or files, and then the image, in each watched
+    /// selection, listing the types each offers only once. Returns the
+    /// outcomes of reading the text and the image, which the daemon does
+    /// on every poll.
+    pub fn update_content(&mut self) -> (Result<()>, Result<()>) {
+        let mut image = Ok(());
+        let text = self.write(|manager| {
+            manager.for_each_selection(|manager| {
+                let text = manager.update_selection_text();
+                let read = manager.update_selection_image();
+                if image.is_ok() {
+                    image = read;
+                }
+                text
+            })
+        });
+        (text, image)
+    }
 
-        if !image_data.is_empty() && image_data.len() > 50 {
-            // hash image_data
-            let mut hasher = Sha256::new();
-            hasher.update(&image_data);
-            let result = hasher.finalize();
-            let image_name = format!("{:x}.png", result);
+    /// Records the text or files in each watched selection.
+    pub fn update_clipboard_content(&mut self) -> Result<()> {
+        self.write(|manager| manager.for_each_selection(Self::update_selection_text))
+    }
 
-            if self
-                .history
-                .items
-                .iter()
-                .find(|x| x.data == image_name)
-                .is_some()
-            {
-                return;
+    /// Whether `text`, read from the selection being captured, has to wait
+    /// for the debounce delay before it is recorded.
+    fn is_debounced(&mut self, text: &str) -> bool {
+        let delay = Duration::from_millis(self.config.capture.debounce_ms);
+        if self.capturing == Selection::Clipboard || delay.is_zero() {
+            return false;
+        }
+        match self.pending_text.get(&self.capturing) {
+            Some((pending, since)) if pending == text => {
+                if since.elapsed() < delay {
+                    return true;
+                }
+                self.pending_text.remove(&self.capturing);
+                false
             }
+            _ => {
+                self.pending_text
+                    .insert(self.capturing, (text.to_string(), Instant::now()));
+                true
+            }
+        }
+    }
+
+    /// When the earliest text waiting for the debounce delay may be
+    /// recorded, if any text is waiting.
+    pub fn debounce_deadline(&self) -> Option<Instant> {
+        let delay = Duration::from_millis(self.config.capture.debounce_ms);
+        self.pending_text
+            .values()
+            .map(|(_, since)| *since + delay)
+            .min()
+    }
 
-            self.save_image(image_data, image_name);
+    fn update_selection_text(&mut self) -> Result<()> {
+        if self.is_concealed() {
+            return Ok(());
+        }
+        if let Some(paths) = self.read_files() {
+            return self.save_files_locked(paths);
         }
+        let content = self.backend.read_text().map_err(ClipmateError::Backend)?;
+        if self.last_seen_text.get(&self.capturing) == Some(&content) || self.is_debounced(&content)
+        {
+            return Ok(());
+        }
+        self.last_seen_text.insert(self.capturing, content.clone());
+        if !self.is_last_clipboard_text(&content) {
+            let formats = self.read_formats("");
+            self.save_text_with_formats(content, formats)?;
+        }
+        Ok(())
+    }
+
+    /// Records the image in each watched selection.
+    pub fn update_image_content(&mut self) -> Result<()> {
+        self.write(|manager| manager.for_each_selection(Self::update_selection_image))
     }
+
+    fn update_selection_image(&mut self) -> Result<()> {
+        if self.is_concealed() {
+            return Ok(());
+        }
+        for mime_type in self.config.capture.image_types.clone() {
+            let image_data = self
+                .backend
+                .read_type(&mime_type)
+                .map_err(ClipmateError::Backend)?;
+
+            if image_data.len() > self.config.capture.min_image_size {
+                let formats = self.read_formats(&mime_type);
+                return self.save_image_with_formats(image_data, &mime_type, formats);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Moves a history file written by older versions into `paths`, along with
+/// the images it references, which were stored next to it.
+///
+/// The history is written as a JSON history encrypted under `key`, with
+/// the history lock held. Does nothing and returns `false` if there is no
+/// legacy file or `paths` already holds a history. The legacy file is
+/// renamed with a `.migrated` suffix rather than deleted.
+pub fn migrate_legacy_history(
+    legacy_file: &Path,
+    paths: &DataPaths,
+    key: Option<Key>,
+    config: &StorageConfig,
+) -> Result<bool> {
+    if !legacy_file.exists() {
+        return Ok(false);
+    }
+    let lock = HistoryLock::open(paths)?;
+    let _guard = lock.exclusive()?;
+    if paths.history_file().exists()
+        || persist::backup_path(&paths.history_file()).exists()
+        || paths.database_file().exists()
+        || paths.journal_file().exists()
+    {
+        return Ok(false);
+    }
+    let mut history = match store::read_legacy_history(legacy_file)? {
+        Some(history) => history,
+        None => return Ok(false),
+    };
+
+    let legacy_dir = match legacy_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let images_dir = paths.images_dir();
+    persist::create_dir(&images_dir)?;
+    for item in history
+        .items
+        .iter_mut()
+        .filter(|x| x.item_type == ClipboardItemType::IMAGE)
+    {
+        let source = legacy_dir.join(&item.data);
+        let name = match source.file_name() {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        if source.exists() {
+            let target = images_dir.join(&name);
+            if fs::rename(&source, &target).is_err() {
+                fs::copy(&source, &target)?;
+                fs::remove_file(&source)?;
+            }
+        }
+        item.data = name.to_string_lossy().into_owned();
+    }
+
+    store::open(StoreFormat::Json, paths, key, config)?.replace(&history)?;
+    lock.bump()?;
+
+    let mut migrated = legacy_file.as_os_str().to_owned();
+    migrated.push(".migrated");
+    fs::rename(legacy_file, migrated)?;
+    Ok(true)
 }
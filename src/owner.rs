@@ -0,0 +1,627 @@
+use crate::backend::Selection;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux, EventMask,
+    PropMode, Property, SelectionNotifyEvent, SelectionRequestEvent, Timestamp, Window,
+    WindowClass, SELECTION_NOTIFY_EVENT,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::{COPY_DEPTH_FROM_PARENT, CURRENT_TIME, NONE};
+
+/// The subcommand making `clipmate` serve an offer read from stdin, see
+/// `serve_from`.
+pub const SERVE_COMMAND: &str = "serve-selection";
+
+/// What the serving process prints once it owns the selection.
+const SERVING: &str = "serving";
+
+/// How long an INCR transfer waits for the requestor to take the next
+/// chunk before it is given up.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Targets under which text is offered, most specific first. Applications
+/// ask for one of the X11 names or a MIME type.
+const TEXT_TARGETS: &[&str] = &[
+    "UTF8_STRING",
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "STRING",
+    "TEXT",
+];
+
+/// The contents served while clipmate owns the clipboard: text, other
+/// representations by MIME type, or both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Offer {
+    text: Option<Vec<u8>>,
+    formats: Vec<(String, Vec<u8>)>,
+}
+
+impl Offer {
+    pub fn new(text: Option<String>, formats: Vec<(String, Vec<u8>)>) -> Offer {
+        Offer {
+            text: text.map(String::into_bytes),
+            formats,
+        }
+    }
+
+    /// Every target the offer can be converted to, without the `TARGETS`
+    /// and `TIMESTAMP` targets every owner supports.
+    pub fn targets(&self) -> Vec<&str> {
+        let mut targets = Vec::new();
+        if self.text().is_some() {
+            targets.extend_from_slice(TEXT_TARGETS);
+        }
+        for (mime_type, _) in &self.formats {
+            if !targets.contains(&mime_type.as_str()) {
+                targets.push(mime_type);
+            }
+        }
+        targets
+    }
+
+    /// The data for `target`, if it is offered. Text can be asked for under
+    /// any of the usual text targets, and so can a `text/plain` format when
+    /// there is no text.
+    pub fn get(&self, target: &str) -> Option<&[u8]> {
+        if TEXT_TARGETS.contains(&target) {
+            if let Some(text) = self.text() {
+                return Some(text);
+            }
+        }
+        self.formats
+            .iter()
+            .find(|(mime_type, _)| mime_type == target)
+            .map(|(_, data)| &data[..])
+    }
+
+    /// Writes the offer for `read_from`: each representation as a line
+    /// with its length and MIME type, or only its length for the text,
+    /// followed by its data.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        if let Some(text) = &self.text {
+            writeln!(out, "{}", text.len())?;
+            out.write_all(text)?;
+        }
+        for (mime_type, data) in &self.formats {
+            writeln!(out, "{} {}", data.len(), mime_type)?;
+            out.write_all(data)?;
+        }
+        out.flush()
+    }
+
+    /// Reads an offer written by `write_to`.
+    pub fn read_from(input: &mut impl BufRead) -> io::Result<Offer> {
+        let mut offer = Offer::new(None, Vec::new());
+        let mut header = String::new();
+        while input.read_line(&mut header)? > 0 {
+            let line = header.trim_end_matches('\n');
+            let (len, mime_type) = match line.split_once(' ') {
+                Some((len, mime_type)) => (len, Some(mime_type)),
+                None => (line, None),
+            };
+            let len: usize = len.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid offer header '{}'", line),
+                )
+            })?;
+            let mut data = vec![0; len];
+            input.read_exact(&mut data)?;
+            match mime_type {
+                Some(mime_type) => offer.formats.push((mime_type.to_string(), data)),
+                None => offer.text = Some(data),
+            }
+            header.clear();
+        }
+        Ok(offer)
+    }
+
+    fn text(&self) -> Option<&[u8]> {
+        self.text.as_deref().or_else(|| {
+            self.formats
+                .iter()
+                .find(|(mime_type, _)| TEXT_TARGETS.contains(&mime_type.as_str()))
+                .map(|(_, data)| &data[..])
+        })
+    }
+}
+
+/// Serves the clipboard in place of the application that copied to it, so
+/// that its contents outlive that application.
+pub trait Owner {
+    /// Takes ownership of the clipboard and serves `offer` until another
+    /// application copies something.
+    fn own(&mut self, offer: Offer) -> io::Result<()>;
+}
+
+fn x11_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+struct Atoms {
+    /// The selection served.
+    selection: Atom,
+    targets: Atom,
+    timestamp: Atom,
+    incr: Atom,
+    /// Property of clipmate's own window appended to to get a timestamp
+    /// for taking ownership.
+    own: Atom,
+}
+
+impl Atoms {
+    fn intern(conn: &RustConnection, selection: Selection) -> io::Result<Atoms> {
+        let intern = |name: &str| -> io::Result<Atom> {
+            Ok(conn
+                .intern_atom(false, name.as_bytes())
+                .map_err(x11_error)?
+                .reply()
+                .map_err(x11_error)?
+                .atom)
+        };
+        Ok(Atoms {
+            selection: match selection {
+                Selection::Clipboard => intern("CLIPBOARD")?,
+                Selection::Primary => AtomEnum::PRIMARY.into(),
+                Selection::Secondary => AtomEnum::SECONDARY.into(),
+            },
+            targets: intern("TARGETS")?,
+            timestamp: intern("TIMESTAMP")?,
+            incr: intern("INCR")?,
+            own: intern("_CLIPMATE_OWN")?,
+        })
+    }
+}
+
+/// A reply too large for one request, sent in chunks each time the
+/// requestor deletes the property holding the previous one (the ICCCM INCR
+/// protocol).
+struct Transfer {
+    requestor: Window,
+    property: Atom,
+    target: Atom,
+    data: Vec<u8>,
+    sent: usize,
+    /// When the last chunk was sent, or the transfer started.
+    updated: Instant,
+}
+
+/// Owns CLIPBOARD on X11 from a window of its own, answering paste
+/// requests from a background thread.
+///
+/// `own` needs a timestamp from the server, which the thread gets by
+/// appending to a property of the window: the offer waits in `pending`
+/// until the resulting `PropertyNotify` arrives.
+pub struct X11Owner {
+    conn: Arc<RustConnection>,
+    window: Window,
+    own_atom: Atom,
+    pending: Arc<Mutex<Option<Offer>>>,
+}
+
+impl X11Owner {
+    pub fn spawn() -> io::Result<X11Owner> {
+        let (owner, mut server) = X11Owner::connect(Selection::Clipboard)?;
+        thread::spawn(move || server.run());
+        Ok(owner)
+    }
+
+    /// Opens a connection and a window to own `selection` from, and
+    /// returns the server answering its requests without running it.
+    fn connect(selection: Selection) -> io::Result<(X11Owner, Server)> {
+        let (conn, screen) = x11rb::connect(None).map_err(x11_error)?;
+        let conn = Arc::new(conn);
+        let atoms = Atoms::intern(&conn, selection)?;
+        let window = conn.generate_id().map_err(x11_error)?;
+        let root = conn.setup().roots[screen].root;
+        conn.create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            0,
+            &CreateWindowAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )
+        .map_err(x11_error)?;
+        conn.flush().map_err(x11_error)?;
+
+        let owner = X11Owner {
+            conn: conn.clone(),
+            window,
+            own_atom: atoms.own,
+            pending: Arc::new(Mutex::new(None)),
+        };
+        let server = Server {
+            conn,
+            window,
+            atoms,
+            pending: owner.pending.clone(),
+            offer: None,
+            time: CURRENT_TIME,
+            target_names: HashMap::new(),
+            transfers: Vec::new(),
+        };
+        Ok((owner, server))
+    }
+}
+
+/// Serves `offer` from `selection` in a background process running
+/// `exe serve-selection`, which must end up in `serve_stdio`, and returns
+/// once that process owns the selection.
+///
+/// `xclip` cannot offer more than one target at a time, and the selection
+/// has to outlive `clipmate` like it outlives `xclip`.
+pub fn serve_from(exe: &Path, selection: Selection, offer: &Offer) -> io::Result<()> {
+    let mut child = Command::new(exe)
+        .args([SERVE_COMMAND, "--selection", selection.name()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        // Like the daemon, not stopped by Ctrl-C in the terminal.
+        .process_group(0)
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        offer.write_to(&mut stdin)?;
+    }
+    let mut line = String::new();
+    if let Some(stdout) = child.stdout.take() {
+        BufReader::new(stdout).read_line(&mut line)?;
+    }
+    if line.trim_end() == SERVING {
+        // Reaped once another application takes the selection over.
+        thread::spawn(move || child.wait());
+        return Ok(());
+    }
+    let status = child.wait()?;
+    Err(io::Error::other(format!(
+        "cannot serve the {} selection, {} exited with {}",
+        selection,
+        exe.display(),
+        status
+    )))
+}
+
+/// Reads an offer from stdin and serves it from `selection` until another
+/// application takes the selection over, telling the process that ran
+/// `serve_from` on stdout once it owns it.
+pub fn serve_stdio(selection: Selection) -> io::Result<()> {
+    let offer = Offer::read_from(&mut io::stdin().lock())?;
+    let (mut owner, mut server) = X11Owner::connect(selection)?;
+    owner.own(offer)?;
+    server.run_until_cleared(|| {
+        let mut stdout = io::stdout();
+        // Nobody may be listening any more, which changes nothing.
+        let _ = writeln!(stdout, "{}", SERVING).and_then(|()| stdout.flush());
+    })
+}
+
+impl Owner for X11Owner {
+    fn own(&mut self, offer: Offer) -> io::Result<()> {
+        *self.pending.lock().unwrap() = Some(offer);
+        self.conn
+            .change_property8(
+                PropMode::APPEND,
+                self.window,
+                self.own_atom,
+                AtomEnum::STRING,
+                &[],
+            )
+            .map_err(x11_error)?;
+        self.conn.flush().map_err(x11_error)
+    }
+}
+
+/// The thread side of `X11Owner`.
+struct Server {
+    conn: Arc<RustConnection>,
+    window: Window,
+    atoms: Atoms,
+    pending: Arc<Mutex<Option<Offer>>>,
+    /// What is served while clipmate owns the clipboard.
+    offer: Option<Offer>,
+    /// When clipmate took ownership.
+    time: Timestamp,
+    /// The offer's targets by atom.
+    target_names: HashMap<Atom, String>,
+    transfers: Vec<Transfer>,
+}
+
+impl Server {
+    fn run(&mut self) {
+        loop {
+            match self.conn.wait_for_event() {
+                Ok(event) => {
+                    if let Err(e) = self.handle(event) {
+                        warn!("cannot answer a clipboard request: {}", e);
+                    }
+                }
+                Err(e) => {
+                    warn!("stopped serving the clipboard: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Answers requests until another application takes the selection
+    /// over, calling `owned` once the pending offer is served.
+    fn run_until_cleared(&mut self, owned: impl FnOnce()) -> io::Result<()> {
+        let mut owned = Some(owned);
+        loop {
+            let event = self.conn.wait_for_event().map_err(x11_error)?;
+            let cleared = matches!(
+                &event,
+                Event::SelectionClear(e) if e.selection == self.atoms.selection
+            );
+            self.handle(event)?;
+            if cleared {
+                return Ok(());
+            }
+            if owned.is_none() || self.pending.lock().unwrap().is_some() {
+                continue;
+            }
+            if self.offer.is_none() {
+                return Err(io::Error::other(
+                    "another application took the selection over first",
+                ));
+            }
+            if let Some(owned) = owned.take() {
+                owned();
+            }
+        }
+    }
+
+    fn handle(&mut self, event: Event) -> io::Result<()> {
+        self.expire_transfers()?;
+        let handled = match event {
+            Event::PropertyNotify(e)
+                if e.window == self.window
+                    && e.atom == self.atoms.own
+                    && e.state == Property::NEW_VALUE =>
+            {
+                self.take_ownership(e.time)
+            }
+            Event::PropertyNotify(e) if e.state == Property::DELETE => {
+                self.continue_transfer(e.window, e.atom)
+            }
+            Event::SelectionClear(e) if e.selection == self.atoms.selection => {
+                self.offer = None;
+                self.transfers.clear();
+                Ok(())
+            }
+            Event::DestroyNotify(e) => {
+                self.transfers.retain(|t| t.requestor != e.window);
+                Ok(())
+            }
+            Event::SelectionRequest(e) => self.answer(e),
+            _ => Ok(()),
+        };
+        handled.and_then(|()| self.conn.flush().map_err(x11_error))
+    }
+
+    fn take_ownership(&mut self, time: Timestamp) -> io::Result<()> {
+        let Some(offer) = self.pending.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let mut target_names = HashMap::new();
+        for target in offer.targets() {
+            let atom = self
+                .conn
+                .intern_atom(false, target.as_bytes())
+                .map_err(x11_error)?
+                .reply()
+                .map_err(x11_error)?
+                .atom;
+            target_names.insert(atom, target.to_string());
+        }
+        self.conn
+            .set_selection_owner(self.window, self.atoms.selection, time)
+            .map_err(x11_error)?;
+        let owner = self
+            .conn
+            .get_selection_owner(self.atoms.selection)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?
+            .owner;
+        if owner == self.window {
+            self.offer = Some(offer);
+            self.time = time;
+            self.target_names = target_names;
+        }
+        Ok(())
+    }
+
+    /// Converts the clipboard for a `SelectionRequest` and tells the
+    /// requestor where the result is. `MULTIPLE` is not supported.
+    fn answer(&mut self, request: SelectionRequestEvent) -> io::Result<()> {
+        // Obsolete clients leave the property unset and expect the target.
+        let property = if request.property == NONE {
+            request.target
+        } else {
+            request.property
+        };
+        let converted = match &self.offer {
+            Some(_) if request.selection != self.atoms.selection => false,
+            Some(_) if request.time != CURRENT_TIME && request.time < self.time => false,
+            Some(_) => self.convert(request.requestor, property, request.target)?,
+            None => false,
+        };
+        let notify = SelectionNotifyEvent {
+            response_type: SELECTION_NOTIFY_EVENT,
+            sequence: 0,
+            time: request.time,
+            requestor: request.requestor,
+            selection: request.selection,
+            target: request.target,
+            property: if converted { property } else { NONE },
+        };
+        self.conn
+            .send_event(false, request.requestor, EventMask::NO_EVENT, notify)
+            .map_err(x11_error)?;
+        Ok(())
+    }
+
+    /// Writes the offer as `target` to `property` of `requestor`, starting
+    /// an INCR transfer if it is too large for one request. Returns whether
+    /// the offer has that target.
+    fn convert(&mut self, requestor: Window, property: Atom, target: Atom) -> io::Result<bool> {
+        let offer = self.offer.as_ref().unwrap();
+        if target == self.atoms.targets {
+            let mut atoms = vec![self.atoms.targets, self.atoms.timestamp];
+            atoms.extend(self.target_names.keys());
+            self.conn
+                .change_property32(
+                    PropMode::REPLACE,
+                    requestor,
+                    property,
+                    AtomEnum::ATOM,
+                    &atoms,
+                )
+                .map_err(x11_error)?;
+            return Ok(true);
+        }
+        if target == self.atoms.timestamp {
+            self.conn
+                .change_property32(
+                    PropMode::REPLACE,
+                    requestor,
+                    property,
+                    AtomEnum::INTEGER,
+                    &[self.time],
+                )
+                .map_err(x11_error)?;
+            return Ok(true);
+        }
+        let Some(data) = self
+            .target_names
+            .get(&target)
+            .and_then(|name| offer.get(name))
+        else {
+            return Ok(false);
+        };
+
+        if data.len() <= self.chunk_size() {
+            self.conn
+                .change_property8(PropMode::REPLACE, requestor, property, target, data)
+                .map_err(x11_error)?;
+            return Ok(true);
+        }
+        let transfer = Transfer {
+            requestor,
+            property,
+            target,
+            data: data.to_vec(),
+            sent: 0,
+            updated: Instant::now(),
+        };
+        // Property deletions drive the transfer, and its destruction ends it.
+        self.conn
+            .change_window_attributes(
+                requestor,
+                &ChangeWindowAttributesAux::new()
+                    .event_mask(EventMask::PROPERTY_CHANGE | EventMask::STRUCTURE_NOTIFY),
+            )
+            .map_err(x11_error)?;
+        self.conn
+            .change_property32(
+                PropMode::REPLACE,
+                requestor,
+                property,
+                self.atoms.incr,
+                &[transfer.data.len().try_into().unwrap_or(u32::MAX)],
+            )
+            .map_err(x11_error)?;
+        self.transfers
+            .retain(|t| (t.requestor, t.property) != (requestor, property));
+        self.transfers.push(transfer);
+        Ok(true)
+    }
+
+    /// The most data sent in one property change.
+    fn chunk_size(&self) -> usize {
+        (self.conn.maximum_request_bytes() / 4).min(1024 * 1024)
+    }
+
+    /// Sends the next chunk of the INCR transfer to `property` of
+    /// `requestor`, once it deleted the previous one. The transfer ends with
+    /// an empty chunk.
+    fn continue_transfer(&mut self, requestor: Window, property: Atom) -> io::Result<()> {
+        let chunk_size = self.chunk_size();
+        let Some(index) = self
+            .transfers
+            .iter()
+            .position(|t| t.requestor == requestor && t.property == property)
+        else {
+            return Ok(());
+        };
+        let transfer = &mut self.transfers[index];
+        let end = (transfer.sent + chunk_size).min(transfer.data.len());
+        self.conn
+            .change_property8(
+                PropMode::REPLACE,
+                requestor,
+                property,
+                transfer.target,
+                &transfer.data[transfer.sent..end],
+            )
+            .map_err(x11_error)?;
+        if transfer.sent == end {
+            self.transfers.remove(index);
+            self.unwatch(requestor)?;
+        } else {
+            transfer.sent = end;
+            transfer.updated = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Gives up the INCR transfers whose requestor stopped taking chunks,
+    /// so that their data does not stay around for good.
+    fn expire_transfers(&mut self) -> io::Result<()> {
+        let mut expired = Vec::new();
+        self.transfers.retain(|t| {
+            let keep = t.updated.elapsed() < TRANSFER_TIMEOUT;
+            if !keep {
+                expired.push(t.requestor);
+            }
+            keep
+        });
+        for requestor in expired {
+            self.unwatch(requestor)?;
+        }
+        Ok(())
+    }
+
+    /// Stops listening to the events of `requestor` once no transfer to it
+    /// is left.
+    fn unwatch(&self, requestor: Window) -> io::Result<()> {
+        if self.transfers.iter().any(|t| t.requestor == requestor) {
+            return Ok(());
+        }
+        self.conn
+            .change_window_attributes(
+                requestor,
+                &ChangeWindowAttributesAux::new().event_mask(EventMask::NO_EVENT),
+            )
+            .map_err(x11_error)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use crate::error::{ClipmateError, Result};
+use std::env;
+use std::path::{Path, PathBuf};
+
+const APP_DIR: &str = "clipmate";
+const HISTORY_FILE: &str = "history.json";
+const DATABASE_FILE: &str = "history.db";
+const JOURNAL_FILE: &str = "history.jsonl";
+const IMAGES_DIR: &str = "images";
+const BLOBS_DIR: &str = "blobs";
+const RESTORED_DIR: &str = "restored";
+const LOCK_FILE: &str = "history.lock";
+const PID_FILE: &str = "daemon.pid";
+
+/// History file name used by versions that stored everything in the
+/// current directory.
+pub const LEGACY_HISTORY_FILE: &str = ".clipboard_history.json";
+
+/// Where clipmate keeps its history and image files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataPaths {
+    data_dir: PathBuf,
+}
+
+impl DataPaths {
+    pub fn new<P: Into<PathBuf>>(data_dir: P) -> DataPaths {
+        DataPaths {
+            data_dir: data_dir.into(),
+        }
+    }
+
+    /// Uses `data_dir` if given, and `$XDG_DATA_HOME/clipmate` (defaulting
+    /// to `~/.local/share/clipmate`) otherwise.
+    pub fn resolve(data_dir: Option<&Path>) -> Result<DataPaths> {
+        match data_dir {
+            Some(dir) => Ok(DataPaths::new(dir)),
+            None => xdg_dir("XDG_DATA_HOME", ".local/share")
+                .map(|dir| DataPaths::new(dir.join(APP_DIR)))
+                .ok_or_else(|| {
+                    ClipmateError::InvalidInput(
+                        "cannot determine the data directory, set --data-dir or HOME".to_string(),
+                    )
+                }),
+        }
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn history_file(&self) -> PathBuf {
+        self.data_dir.join(HISTORY_FILE)
+    }
+
+    /// The SQLite database holding the history once it was migrated to
+    /// SQLite, which replaces `history_file`.
+    pub fn database_file(&self) -> PathBuf {
+        self.data_dir.join(DATABASE_FILE)
+    }
+
+    /// The journal holding the history once it was migrated to the
+    /// journal format, which replaces `history_file`.
+    pub fn journal_file(&self) -> PathBuf {
+        self.data_dir.join(JOURNAL_FILE)
+    }
+
+    /// Where images were stored before they went to the blob store.
+    pub fn images_dir(&self) -> PathBuf {
+        self.data_dir.join(IMAGES_DIR)
+    }
+
+    /// The content-addressed store holding images and large texts.
+    pub fn blobs_dir(&self) -> PathBuf {
+        self.data_dir.join(BLOBS_DIR)
+    }
+
+    /// Where copied files that were deleted since are written back from
+    /// their snapshots when the item is restored.
+    pub fn restored_dir(&self) -> PathBuf {
+        self.data_dir.join(RESTORED_DIR)
+    }
+
+    /// The lock coordinating the processes reading and changing the
+    /// history.
+    pub fn lock_file(&self) -> PathBuf {
+        self.data_dir.join(LOCK_FILE)
+    }
+
+    /// The pid file of the daemon recording into this history.
+    pub fn pid_file(&self) -> PathBuf {
+        self.data_dir.join(PID_FILE)
+    }
+}
+
+/// Where clipmate keeps state worth keeping across sessions but not worth
+/// backing up, such as the daemon's log: `$XDG_STATE_HOME/clipmate`,
+/// defaulting to `~/.local/state/clipmate`.
+pub fn state_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+        .map(|dir| dir.join(APP_DIR))
+        .ok_or_else(|| {
+            ClipmateError::InvalidInput(
+                "cannot determine the state directory, set HOME".to_string(),
+            )
+        })
+}
+
+/// Where clipmate keeps files that must not outlive the session or reach
+/// the disk, such as the cached key: `$XDG_RUNTIME_DIR/clipmate`, or `None`
+/// if `$XDG_RUNTIME_DIR` is not set.
+pub fn runtime_dir() -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .map(|dir| dir.join(APP_DIR))
+}
+
+/// Returns `$var` if it holds an absolute path, as the XDG base directory
+/// spec requires, or `$HOME/<fallback>` otherwise.
+pub(crate) fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| {
+            env::var_os("HOME")
+                .filter(|home| !home.is_empty())
+                .map(|home| PathBuf::from(home).join(fallback))
+        })
+}
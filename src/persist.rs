@@ -0,0 +1,93 @@
+use std::ffi::OsString;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Mode of the files holding the history, which only the user may read.
+pub const FILE_MODE: u32 = 0o600;
+const DIR_MODE: u32 = 0o700;
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Path of the rolling backup kept next to `path`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".bak")
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".tmp")
+}
+
+/// Deletes the backup kept next to `path`, if there is one.
+pub fn remove_backup(path: &Path) -> io::Result<()> {
+    match fs::remove_file(backup_path(path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Creates `dir` and its missing parents, only accessible to the user.
+/// Existing directories are left as they are.
+pub fn create_dir(dir: &Path) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(DIR_MODE).create(dir)
+}
+
+/// Opens `path` for appending, creating it only readable by the user.
+pub fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(FILE_MODE)
+        .open(path)
+}
+
+/// Replaces the contents of `path` without ever leaving it half written.
+///
+/// The data goes to a sibling temp file which is fsynced and renamed over
+/// `path`. The temp file is truncated first, so that contents shorter than
+/// a file left by an interrupted write do not keep its tail. The previous
+/// version of `path`, if any, is kept as the `.bak` file so a later
+/// corruption can be recovered from. The new file is only readable by the
+/// user.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp = temp_path(path);
+    {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(FILE_MODE)
+            .open(&temp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+
+    if path.exists() {
+        let backup = backup_path(path);
+        match fs::remove_file(&backup) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        // A hard link keeps `path` in place until the rename below; copying
+        // is the fallback for filesystems without link support.
+        if fs::hard_link(path, &backup).is_err() {
+            fs::copy(path, &backup)?;
+        }
+    }
+
+    fs::rename(&temp, path)?;
+    sync_parent(path)
+}
+
+fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
@@ -0,0 +1,112 @@
+use crate::error::{ClipmateError, Result};
+use crate::manager::{ClipboardItem, ClipboardItemType};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How the search pattern is matched against item data.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchMode {
+    /// Case-sensitive substring match.
+    #[default]
+    Substring,
+    /// Substring match ignoring case.
+    IgnoreCase,
+    /// Regular expression match.
+    Regex,
+    /// The pattern's characters appear in order, ignoring case, as in
+    /// `gco` matching `git checkout`.
+    Fuzzy,
+}
+
+/// A search over the clipboard history. Filters left as `None` match every
+/// item.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SearchQuery {
+    pub pattern: String,
+    pub mode: SearchMode,
+    pub item_type: Option<ClipboardItemType>,
+    pub pinned: Option<bool>,
+    /// Only items copied at most this long ago.
+    pub newer_than: Option<Duration>,
+    /// Only items copied at least this long ago.
+    pub older_than: Option<Duration>,
+}
+
+enum Matcher {
+    Substring(String),
+    IgnoreCase(String),
+    Regex(Regex),
+    Fuzzy(Vec<char>),
+}
+
+impl Matcher {
+    fn new(pattern: &str, mode: SearchMode) -> Result<Matcher> {
+        Ok(match mode {
+            SearchMode::Substring => Matcher::Substring(pattern.to_string()),
+            SearchMode::IgnoreCase => Matcher::IgnoreCase(pattern.to_lowercase()),
+            SearchMode::Regex => Matcher::Regex(
+                RegexBuilder::new(pattern)
+                    .multi_line(true)
+                    .build()
+                    .map_err(|e| ClipmateError::InvalidInput(e.to_string()))?,
+            ),
+            SearchMode::Fuzzy => Matcher::Fuzzy(pattern.to_lowercase().chars().collect()),
+        })
+    }
+
+    fn is_match(&self, data: &str) -> bool {
+        match self {
+            Matcher::Substring(pattern) => data.contains(pattern.as_str()),
+            Matcher::IgnoreCase(pattern) => data.to_lowercase().contains(pattern.as_str()),
+            Matcher::Regex(re) => re.is_match(data),
+            Matcher::Fuzzy(pattern) => {
+                let mut wanted = pattern.iter().peekable();
+                for c in data.chars().flat_map(char::to_lowercase) {
+                    if wanted.peek() == Some(&&c) {
+                        wanted.next();
+                    }
+                }
+                wanted.peek().is_none()
+            }
+        }
+    }
+}
+
+/// Returns the items matching `query`, in history order, paired with their
+/// 1-based item numbers.
+pub fn search<'a>(
+    items: &'a [ClipboardItem],
+    query: &SearchQuery,
+) -> Result<Vec<(usize, &'a ClipboardItem)>> {
+    search_where(items, query, |_| true)
+}
+
+/// Like `search`, but skips the items for which `candidate` is false, such
+/// as those an index ruled out.
+pub fn search_where<'a>(
+    items: &'a [ClipboardItem],
+    query: &SearchQuery,
+    candidate: impl Fn(&ClipboardItem) -> bool,
+) -> Result<Vec<(usize, &'a ClipboardItem)>> {
+    let matcher = Matcher::new(&query.pattern, query.mode)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+
+    Ok(items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| {
+            let age = now.saturating_sub(item.time);
+            candidate(item)
+                && query.item_type.is_none_or(|t| t == item.item_type)
+                && query.pinned.is_none_or(|p| p == item.pinned)
+                && query.newer_than.is_none_or(|d| age <= d.as_nanos())
+                && query.older_than.is_none_or(|d| age >= d.as_nanos())
+                && matcher.is_match(&item.data)
+        })
+        .map(|(i, item)| (i + 1, item))
+        .collect())
+}
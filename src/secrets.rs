@@ -0,0 +1,201 @@
+use crate::config::SecretsConfig;
+use crate::error::{ClipmateError, Result};
+use regex::Regex;
+use std::collections::HashMap;
+
+/// A span of text recognized as a secret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretMatch {
+    /// Short name of what was found, such as `aws-access-key`.
+    pub kind: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Finds secrets in copied text.
+pub trait SecretDetector: Send {
+    fn find(&self, text: &str) -> Vec<SecretMatch>;
+}
+
+/// Detector matching a regular expression.
+pub struct PatternDetector {
+    kind: String,
+    regex: Regex,
+}
+
+impl PatternDetector {
+    pub fn new(kind: &str, pattern: &str) -> Result<PatternDetector> {
+        let regex = Regex::new(pattern).map_err(|e| {
+            ClipmateError::Config(format!("invalid secret pattern '{}': {}", pattern, e))
+        })?;
+        Ok(PatternDetector {
+            kind: kind.to_string(),
+            regex,
+        })
+    }
+}
+
+impl SecretDetector for PatternDetector {
+    fn find(&self, text: &str) -> Vec<SecretMatch> {
+        self.regex
+            .find_iter(text)
+            .map(|m| SecretMatch {
+                kind: self.kind.clone(),
+                start: m.start(),
+                end: m.end(),
+            })
+            .collect()
+    }
+}
+
+const BUILTIN_PATTERNS: &[(&str, &str)] = &[
+    ("aws-access-key", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b"),
+    (
+        "aws-secret-key",
+        r"(?i)aws_?secret_?access_?key\s*[:=]\s*[A-Za-z0-9/+=]{40}",
+    ),
+    (
+        "github-token",
+        r"\b(?:gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{60,})\b",
+    ),
+    (
+        "private-key",
+        r"-----BEGIN (?:[A-Z0-9]+ )*PRIVATE KEY-----[\s\S]*?-----END (?:[A-Z0-9]+ )*PRIVATE KEY-----",
+    ),
+    (
+        "jwt",
+        r"\beyJ[A-Za-z0-9_-]{8,}\.eyJ[A-Za-z0-9_-]{8,}\.[A-Za-z0-9_-]{8,}",
+    ),
+];
+
+/// Detector flagging long random-looking tokens, such as generated
+/// passwords and API keys without a recognizable prefix.
+pub struct EntropyDetector {
+    min_length: usize,
+    min_entropy: f64,
+}
+
+impl Default for EntropyDetector {
+    fn default() -> Self {
+        EntropyDetector {
+            min_length: 20,
+            min_entropy: 4.0,
+        }
+    }
+}
+
+/// Shannon entropy of `token`, in bits per character.
+fn entropy(token: &str) -> f64 {
+    let mut counts = HashMap::new();
+    for c in token.chars() {
+        *counts.entry(c).or_insert(0usize) += 1;
+    }
+    let len = token.chars().count() as f64;
+    counts
+        .values()
+        .map(|&n| {
+            let p = n as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+impl EntropyDetector {
+    fn looks_random(&self, token: &str) -> bool {
+        if token.len() < self.min_length || token.contains("://") {
+            return false;
+        }
+        let has_lower = token.chars().any(|c| c.is_ascii_lowercase());
+        let has_upper = token.chars().any(|c| c.is_ascii_uppercase());
+        let has_digit = token.chars().any(|c| c.is_ascii_digit());
+        // Words, paths and identifiers rarely mix all three character
+        // classes; keys and generated passwords nearly always do.
+        has_lower && has_upper && has_digit && entropy(token) >= self.min_entropy
+    }
+}
+
+impl SecretDetector for EntropyDetector {
+    fn find(&self, text: &str) -> Vec<SecretMatch> {
+        let mut matches = Vec::new();
+        let mut start = None;
+        for (i, c) in text
+            .char_indices()
+            .chain(std::iter::once((text.len(), ' ')))
+        {
+            if c.is_whitespace() || matches!(c, '"' | '\'' | '`' | ',' | ';') {
+                if let Some(s) = start.take() {
+                    if self.looks_random(&text[s..i]) {
+                        matches.push(SecretMatch {
+                            kind: "high-entropy".to_string(),
+                            start: s,
+                            end: i,
+                        });
+                    }
+                }
+            } else if start.is_none() {
+                start = Some(i);
+            }
+        }
+        matches
+    }
+}
+
+/// The set of detectors run on every copied text.
+#[derive(Default)]
+pub struct SecretScanner {
+    detectors: Vec<Box<dyn SecretDetector>>,
+}
+
+impl SecretScanner {
+    /// Builds the detectors enabled in `config`: the built-in patterns, the
+    /// entropy check if requested, and the user's own patterns.
+    pub fn from_config(config: &SecretsConfig) -> Result<SecretScanner> {
+        let mut scanner = SecretScanner::default();
+        if !config.enabled {
+            return Ok(scanner);
+        }
+        for (kind, pattern) in BUILTIN_PATTERNS {
+            scanner.add(Box::new(PatternDetector::new(kind, pattern)?));
+        }
+        if config.entropy {
+            scanner.add(Box::new(EntropyDetector::default()));
+        }
+        for pattern in &config.patterns {
+            scanner.add(Box::new(PatternDetector::new("custom", pattern)?));
+        }
+        Ok(scanner)
+    }
+
+    pub fn add(&mut self, detector: Box<dyn SecretDetector>) {
+        self.detectors.push(detector);
+    }
+
+    /// Returns every secret found in `text`, ordered by position.
+    pub fn scan(&self, text: &str) -> Vec<SecretMatch> {
+        let mut matches: Vec<SecretMatch> = self
+            .detectors
+            .iter()
+            .flat_map(|detector| detector.find(text))
+            .collect();
+        matches.sort_by_key(|m| (m.start, std::cmp::Reverse(m.end)));
+        matches
+    }
+}
+
+/// Replaces each match in `text` with a `[redacted <kind>]` marker.
+/// Overlapping matches are merged into the first one.
+pub fn mask(text: &str, matches: &[SecretMatch]) -> String {
+    let mut masked = String::with_capacity(text.len());
+    let mut position = 0;
+    for m in matches {
+        if m.start < position {
+            position = position.max(m.end);
+            continue;
+        }
+        masked.push_str(&text[position..m.start]);
+        masked.push_str(&format!("[redacted {}]", m.kind));
+        position = m.end;
+    }
+    masked.push_str(&text[position..]);
+    masked
+}
@@ -0,0 +1,167 @@
+use crate::error::{ClipmateError, Result};
+use crate::ipc::Client;
+use crate::lock;
+use crate::paths;
+use crate::persist;
+use rustix::process::{self as rustix_process, Pid, Signal};
+use std::ffi::OsString;
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SERVICE_FILE: &str = "clipmate.service";
+
+/// How long `start` waits for the daemon to accept commands.
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long `stop` waits for the daemon to exit.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+const WAIT_STEP: Duration = Duration::from_millis(50);
+/// Size past which the log is moved aside when the daemon starts, keeping
+/// one old log.
+const MAX_LOG_SIZE: u64 = 1 << 20;
+
+/// Runs `command`, which runs a daemon in the foreground, in the background
+/// instead: detached from the terminal, with its output appended to `log`.
+/// Waits until the daemon listens at `socket` and returns its pid.
+///
+/// A log grown past `MAX_LOG_SIZE` is renamed with a `.1` suffix first.
+pub fn start(mut command: Command, socket: &Path, log: &Path) -> Result<u32> {
+    if let Some(parent) = log.parent() {
+        persist::create_dir(parent)?;
+    }
+    if fs::metadata(log).is_ok_and(|meta| meta.len() > MAX_LOG_SIZE) {
+        let mut old = log.as_os_str().to_owned();
+        old.push(".1");
+        fs::rename(log, old)?;
+    }
+    // The log may quote what was copied, so it is as private as the
+    // history, including one written before it was made so.
+    let log_file = persist::open_append(log)?;
+    log_file.set_permissions(Permissions::from_mode(persist::FILE_MODE))?;
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(log_file.try_clone()?)
+        .stderr(log_file)
+        // Its own process group, so that Ctrl-C in the terminal it was
+        // started from does not stop it.
+        .process_group(0)
+        .spawn()?;
+
+    let deadline = Instant::now() + START_TIMEOUT;
+    loop {
+        if Client::connect(socket)?.is_some() {
+            return Ok(child.id());
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(ClipmateError::Daemon(format!(
+                "the daemon exited with {}, see {}",
+                status,
+                log.display()
+            )));
+        }
+        if Instant::now() >= deadline {
+            return Err(ClipmateError::Daemon(format!(
+                "the daemon did not start within {} seconds, see {}",
+                START_TIMEOUT.as_secs(),
+                log.display()
+            )));
+        }
+        thread::sleep(WAIT_STEP);
+    }
+}
+
+/// Asks the daemon holding `pid_file` to stop, and waits until it did.
+/// Returns its pid, or `None` if no daemon was running.
+pub fn stop(pid_file: &Path) -> Result<Option<u32>> {
+    let Some(pid) = lock::running_daemon(pid_file)? else {
+        return Ok(None);
+    };
+    let cannot_stop = |e: io::Error| {
+        ClipmateError::Daemon(format!("cannot stop the daemon (pid {}): {}", pid, e))
+    };
+    let process = i32::try_from(pid)
+        .ok()
+        .and_then(Pid::from_raw)
+        .ok_or_else(|| cannot_stop(io::Error::other("invalid pid")))?;
+    rustix_process::kill_process(process, Signal::TERM).map_err(|e| cannot_stop(e.into()))?;
+
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while lock::running_daemon(pid_file)?.is_some() {
+        if Instant::now() >= deadline {
+            return Err(ClipmateError::Daemon(format!(
+                "the daemon (pid {}) did not stop within {} seconds",
+                pid,
+                STOP_TIMEOUT.as_secs()
+            )));
+        }
+        thread::sleep(WAIT_STEP);
+    }
+    Ok(Some(pid))
+}
+
+/// Quotes `arg` as one word of a systemd command line.
+fn quote(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    if !escaped.is_empty()
+        && !escaped
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'))
+    {
+        return escaped;
+    }
+    format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A systemd user unit running `exe` with `args`, which should run the
+/// daemon in the foreground.
+pub fn unit(exe: &Path, args: &[OsString]) -> String {
+    let command = std::iter::once(exe.as_os_str())
+        .chain(args.iter().map(OsString::as_os_str))
+        .map(|arg| quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "[Unit]
+Description=clipmate clipboard history daemon
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+ExecStart={}
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+
+[Install]
+WantedBy=graphical-session.target
+",
+        command
+    )
+}
+
+/// Where systemd looks for the user's own units:
+/// `$XDG_CONFIG_HOME/systemd/user/clipmate.service`.
+pub fn unit_path() -> Result<PathBuf> {
+    paths::xdg_dir("XDG_CONFIG_HOME", ".config")
+        .map(|dir| dir.join("systemd/user").join(SERVICE_FILE))
+        .ok_or_else(|| {
+            ClipmateError::InvalidInput(
+                "cannot determine the systemd user unit directory, set HOME".to_string(),
+            )
+        })
+}
+
+/// Writes the unit returned by `unit` where systemd finds it, and returns
+/// its path.
+pub fn install(exe: &Path, args: &[OsString]) -> Result<PathBuf> {
+    let path = unit_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, unit(exe, args))?;
+    Ok(path)
+}
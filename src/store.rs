@@ -0,0 +1,209 @@
+use crate::config::StorageConfig;
+use crate::crypto::Key;
+use crate::error::{ClipmateError, Result};
+use crate::lock::HistoryLock;
+use crate::manager::{ClipboardHistory, ClipboardItem};
+use crate::paths::DataPaths;
+use crate::persist;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+mod journal;
+mod json;
+mod sqlite;
+
+pub use journal::JournalStore;
+pub use json::JsonStore;
+pub use sqlite::SqliteStore;
+
+/// A change to the history, passed to `HistoryStore::commit` after it was
+/// made in memory.
+pub enum Change<'a> {
+    Insert(&'a ClipboardItem),
+    /// An item was pinned or unpinned, or its image moved to the blobs.
+    Update(&'a ClipboardItem),
+    /// The contents of an item were replaced.
+    Edit(&'a ClipboardItem),
+    /// Items were deleted, cleared or otherwise removed on request.
+    Remove(&'a [ClipboardItem]),
+    /// Items were dropped by the retention limits or expired. Unlike
+    /// removed ones, they may linger in backups and the journal until it is
+    /// next compacted, so that pruning on every copy stays cheap.
+    Prune(&'a [ClipboardItem]),
+}
+
+impl Change<'_> {
+    /// Whether the change drops contents the store held on request: removed
+    /// items, or the previous text of an edited one. Stores must not leave those
+    /// behind, since deleting an item is how a secret copied by mistake is
+    /// gotten rid of.
+    pub fn drops_contents(&self) -> bool {
+        match self {
+            Change::Insert(_) | Change::Update(_) | Change::Prune(_) => false,
+            Change::Edit(_) => true,
+            Change::Remove(items) => !items.is_empty(),
+        }
+    }
+}
+
+/// Where the history is persisted.
+pub trait HistoryStore: Send {
+    /// Reads the whole history, oldest item first.
+    fn load(&mut self) -> Result<ClipboardHistory>;
+
+    /// Persists `changes`, which turned the stored history into `history`.
+    /// Stores that cannot apply changes one by one write `history` instead.
+    fn commit(&mut self, history: &ClipboardHistory, changes: &[Change]) -> Result<()>;
+
+    /// Replaces the stored history with `history`, leaving none of the
+    /// previous contents behind.
+    fn replace(&mut self, history: &ClipboardHistory) -> Result<()>;
+
+    /// Sets the key data is encrypted with from the next write on.
+    fn set_key(&mut self, key: Option<Key>);
+
+    /// Rewrites the store more compactly if it has grown enough to be worth
+    /// it, given that it holds `history`. Returns whether it did.
+    fn compact(&mut self, _history: &ClipboardHistory) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Returns the ids of the text items containing `pattern`, ignoring
+    /// case, or `None` if the store has no index able to tell. The result
+    /// may include items that do not match.
+    fn find_text(&self, _pattern: &str) -> Result<Option<HashSet<u64>>> {
+        Ok(None)
+    }
+}
+
+/// The formats the history can be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreFormat {
+    /// A single JSON file rewritten on every change.
+    Json,
+    /// A JSON-lines journal that changes are appended to.
+    Journal,
+    /// An SQLite database updated in place.
+    Sqlite,
+}
+
+impl FromStr for StoreFormat {
+    type Err = ClipmateError;
+
+    fn from_str(s: &str) -> Result<StoreFormat> {
+        match s {
+            "json" => Ok(StoreFormat::Json),
+            "journal" => Ok(StoreFormat::Journal),
+            "sqlite" => Ok(StoreFormat::Sqlite),
+            _ => Err(ClipmateError::InvalidInput(format!(
+                "unknown storage format '{}', expected json, journal or sqlite",
+                s
+            ))),
+        }
+    }
+}
+
+impl StoreFormat {
+    /// The format of the history in `paths`: SQLite if there is a
+    /// database, a journal if there is one, and JSON otherwise, including
+    /// when there is no history yet.
+    pub fn detect(paths: &DataPaths) -> StoreFormat {
+        if paths.database_file().exists() {
+            StoreFormat::Sqlite
+        } else if paths.journal_file().exists() {
+            StoreFormat::Journal
+        } else {
+            StoreFormat::Json
+        }
+    }
+
+    /// The file holding a history in this format.
+    fn file(self, paths: &DataPaths) -> PathBuf {
+        match self {
+            StoreFormat::Json => paths.history_file(),
+            StoreFormat::Journal => paths.journal_file(),
+            StoreFormat::Sqlite => paths.database_file(),
+        }
+    }
+}
+
+/// Reads a plaintext JSON history, or returns `None` if there is no such
+/// file.
+pub(crate) fn read_legacy_history(path: &Path) -> Result<Option<ClipboardHistory>> {
+    json::read_history(path, None)
+}
+
+/// Opens the history in `paths` in the given format.
+pub fn open(
+    format: StoreFormat,
+    paths: &DataPaths,
+    key: Option<Key>,
+    config: &StorageConfig,
+) -> Result<Box<dyn HistoryStore>> {
+    Ok(match format {
+        StoreFormat::Json => Box::new(JsonStore::new(paths.history_file(), key)),
+        StoreFormat::Journal => Box::new(JournalStore::new(
+            paths.journal_file(),
+            key,
+            config.compact_after,
+        )),
+        StoreFormat::Sqlite => Box::new(SqliteStore::open(&paths.database_file(), key)?),
+    })
+}
+
+/// Converts the history in `paths` to `format`, keeping every item as it
+/// is. The old history file is renamed with a `.migrated` suffix.
+///
+/// Returns `false` if the history already is in that format.
+pub fn migrate(
+    paths: &DataPaths,
+    key: Option<Key>,
+    format: StoreFormat,
+    config: &StorageConfig,
+) -> Result<bool> {
+    let lock = HistoryLock::open(paths)?;
+    let _guard = lock.exclusive()?;
+    let from = StoreFormat::detect(paths);
+    if from == format {
+        return Ok(false);
+    }
+
+    let history = open(from, paths, key.clone(), config)?.load()?;
+    persist::create_dir(paths.data_dir())?;
+    match format {
+        StoreFormat::Json | StoreFormat::Journal => {
+            open(format, paths, key, config)?.replace(&history)?
+        }
+        StoreFormat::Sqlite => {
+            // Build the database under another name, as its presence alone
+            // makes it the history.
+            let mut tmp = paths.database_file().into_os_string();
+            tmp.push(".tmp");
+            match fs::remove_file(&tmp) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            SqliteStore::open(Path::new(&tmp), key)?.replace(&history)?;
+            fs::rename(&tmp, paths.database_file())?;
+        }
+    }
+
+    let old_file = from.file(paths);
+    let mut migrated = old_file.as_os_str().to_owned();
+    migrated.push(".migrated");
+    match fs::rename(&old_file, migrated) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    if from != StoreFormat::Sqlite {
+        match fs::remove_file(persist::backup_path(&old_file)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    lock.bump()?;
+    Ok(true)
+}
@@ -0,0 +1,263 @@
+use super::{Change, HistoryStore};
+use crate::crypto::{self, Key};
+use crate::error::{ClipmateError, Result};
+use crate::manager::{ClipboardHistory, ClipboardItem};
+use crate::persist;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// One line of the journal.
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Record {
+    /// The whole history. A compacted journal starts with one.
+    Snapshot(ClipboardHistory),
+    Add(ClipboardItem),
+    /// An item was pinned, unpinned or edited.
+    Update(ClipboardItem),
+    /// The items with these ids were removed.
+    Delete(Vec<u64>),
+}
+
+/// A `Record` to write, borrowing its contents.
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum RecordRef<'a> {
+    Snapshot(&'a ClipboardHistory),
+    Add(&'a ClipboardItem),
+    Update(&'a ClipboardItem),
+    Delete(Vec<u64>),
+}
+
+/// The outcome of replaying a journal file.
+struct Replay {
+    history: ClipboardHistory,
+    /// Records after the last snapshot.
+    records: usize,
+    /// Length up to the last complete record, if the file ends with a torn
+    /// one.
+    valid_len: Option<u64>,
+}
+
+/// Stores the history as an append-only journal of JSON lines, one per
+/// change, so that a copy appends a line instead of rewriting the history.
+///
+/// `compact` rewrites the journal into a single snapshot once it holds more
+/// than a set number of records. Changes dropping contents, which earlier
+/// records would still hold, are written as a snapshot right away. A final
+/// line without its newline is the remains of an interrupted write: it is
+/// ignored when replaying and cut off before the next append. With
+/// encryption enabled every line is encrypted on its own and stored in
+/// base64.
+pub struct JournalStore {
+    path: PathBuf,
+    key: Option<Key>,
+    compact_after: usize,
+    records: usize,
+    valid_len: Option<u64>,
+    /// Set when the history was recovered from the backup, which the
+    /// journal must not be appended to.
+    needs_snapshot: bool,
+}
+
+impl JournalStore {
+    /// Opens the journal at `path`, compacting it once more than
+    /// `compact_after` records were appended since the last snapshot.
+    pub fn new(path: PathBuf, key: Option<Key>, compact_after: usize) -> JournalStore {
+        JournalStore {
+            path,
+            key,
+            compact_after,
+            records: 0,
+            valid_len: None,
+            needs_snapshot: false,
+        }
+    }
+
+    fn encode(&self, record: &RecordRef) -> Result<Vec<u8>> {
+        let json = serde_json::to_vec(record)?;
+        let mut line = match &self.key {
+            Some(key) => BASE64.encode(crypto::encrypt(key, &json)?).into_bytes(),
+            None => json,
+        };
+        line.push(b'\n');
+        Ok(line)
+    }
+
+    fn decode(&self, line: &[u8]) -> Result<Record> {
+        if line.starts_with(b"{") {
+            let line = crypto::open(self.key.as_ref(), line.to_vec())?;
+            return Ok(serde_json::from_slice(&line)?);
+        }
+        let data = BASE64
+            .decode(line)
+            .map_err(|_| ClipmateError::Crypto("corrupted journal record".to_string()))?;
+        Ok(serde_json::from_slice(&crypto::open(
+            self.key.as_ref(),
+            data,
+        )?)?)
+    }
+
+    /// Replays the journal at `path`, or returns `None` if there is no such
+    /// file.
+    fn replay(&self, path: &Path) -> Result<Option<Replay>> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut replay = Replay {
+            history: ClipboardHistory::default(),
+            records: 0,
+            valid_len: None,
+        };
+        let mut offset = 0;
+        for line in contents.split_inclusive(|&b| b == b'\n') {
+            let Some(line) = line.strip_suffix(b"\n") else {
+                replay.valid_len = Some(offset as u64);
+                break;
+            };
+            offset += line.len() + 1;
+            if line.is_empty() {
+                continue;
+            }
+            let history = &mut replay.history;
+            match self.decode(line)? {
+                Record::Snapshot(snapshot) => {
+                    *history = snapshot;
+                    replay.records = 0;
+                    continue;
+                }
+                Record::Add(item) => history.push(item),
+                Record::Update(item) => {
+                    if let Some(old) = history.items.iter_mut().find(|old| old.id == item.id) {
+                        *old = item;
+                    }
+                }
+                Record::Delete(ids) => {
+                    let keep: Vec<bool> = history
+                        .items
+                        .iter()
+                        .map(|item| !ids.contains(&item.id))
+                        .collect();
+                    history.remove_items(&keep);
+                }
+            }
+            replay.records += 1;
+        }
+        replay.history.assign_ids();
+        Ok(Some(replay))
+    }
+
+    fn append(&mut self, records: &[RecordRef]) -> Result<()> {
+        let mut lines = Vec::new();
+        for record in records {
+            lines.extend(self.encode(record)?);
+        }
+        if let Some(dir) = self.path.parent() {
+            persist::create_dir(dir)?;
+        }
+        let mut file = persist::open_append(&self.path)?;
+        if let Some(len) = self.valid_len {
+            file.set_len(len)?;
+            self.valid_len = None;
+        }
+        // One write, so that a crash leaves at most one torn line.
+        file.write_all(&lines)?;
+        file.sync_data()?;
+        self.records += records.len();
+        Ok(())
+    }
+
+    /// Replaces the journal with a snapshot of `history`.
+    fn snapshot(&mut self, history: &ClipboardHistory) -> Result<()> {
+        let line = self.encode(&RecordRef::Snapshot(history))?;
+        if let Some(dir) = self.path.parent() {
+            persist::create_dir(dir)?;
+        }
+        persist::write_atomic(&self.path, &line)?;
+        self.records = 0;
+        self.valid_len = None;
+        self.needs_snapshot = false;
+        Ok(())
+    }
+}
+
+impl HistoryStore for JournalStore {
+    /// Replays the journal, falling back to the backup kept by the last
+    /// compaction when the journal is missing or corrupt.
+    fn load(&mut self) -> Result<ClipboardHistory> {
+        let backup = persist::backup_path(&self.path);
+        let replay = match self.replay(&self.path) {
+            Ok(Some(replay)) => replay,
+            Ok(None) => match self.replay(&backup)? {
+                Some(replay) => {
+                    self.needs_snapshot = true;
+                    replay
+                }
+                None => return Ok(ClipboardHistory::default()),
+            },
+            Err(e) => match self.replay(&backup) {
+                Ok(Some(replay)) => {
+                    self.needs_snapshot = true;
+                    replay
+                }
+                _ => return Err(e),
+            },
+        };
+        self.records = replay.records;
+        if !self.needs_snapshot {
+            self.valid_len = replay.valid_len;
+        }
+        Ok(replay.history)
+    }
+
+    fn commit(&mut self, history: &ClipboardHistory, changes: &[Change]) -> Result<()> {
+        if changes.iter().any(Change::drops_contents) {
+            return self.replace(history);
+        }
+        if self.needs_snapshot {
+            return self.snapshot(history);
+        }
+        let records: Vec<RecordRef> = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Insert(item) => Some(RecordRef::Add(item)),
+                Change::Update(item) | Change::Edit(item) => Some(RecordRef::Update(item)),
+                Change::Remove([]) | Change::Prune([]) => None,
+                Change::Remove(items) | Change::Prune(items) => Some(RecordRef::Delete(
+                    items.iter().map(|item| item.id).collect(),
+                )),
+            })
+            .collect();
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.append(&records)
+    }
+
+    /// Writes a snapshot and deletes the backup, which holds the previous
+    /// contents.
+    fn replace(&mut self, history: &ClipboardHistory) -> Result<()> {
+        self.snapshot(history)?;
+        persist::remove_backup(&self.path)?;
+        Ok(())
+    }
+
+    fn set_key(&mut self, key: Option<Key>) {
+        self.key = key;
+    }
+
+    fn compact(&mut self, history: &ClipboardHistory) -> Result<bool> {
+        if self.records <= self.compact_after && !self.needs_snapshot {
+            return Ok(false);
+        }
+        self.snapshot(history)?;
+        Ok(true)
+    }
+}
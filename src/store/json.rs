@@ -0,0 +1,81 @@
+use super::{Change, HistoryStore};
+use crate::crypto::{self, Key};
+use crate::error::Result;
+use crate::manager::ClipboardHistory;
+use crate::persist;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Stores the history as a single JSON file, rewritten atomically on every
+/// change with the previous version kept as a backup, unless the change
+/// dropped contents the backup would still hold.
+pub struct JsonStore {
+    path: PathBuf,
+    key: Option<Key>,
+}
+
+impl JsonStore {
+    pub fn new(path: PathBuf, key: Option<Key>) -> JsonStore {
+        JsonStore { path, key }
+    }
+
+    fn write(&self, history: &ClipboardHistory) -> Result<()> {
+        let contents = crypto::seal(self.key.as_ref(), serde_json::to_vec(history)?)?;
+        if let Some(dir) = self.path.parent() {
+            persist::create_dir(dir)?;
+        }
+        persist::write_atomic(&self.path, &contents)?;
+        Ok(())
+    }
+}
+
+impl HistoryStore for JsonStore {
+    /// Loads the history, falling back to the backup written by the previous
+    /// save when the primary file is missing or corrupt.
+    fn load(&mut self) -> Result<ClipboardHistory> {
+        let key = self.key.as_ref();
+        let backup = persist::backup_path(&self.path);
+        let mut history = match read_history(&self.path, key) {
+            Ok(Some(history)) => history,
+            Ok(None) => read_history(&backup, key)?.unwrap_or_default(),
+            Err(e) => match read_history(&backup, key) {
+                Ok(Some(history)) => history,
+                _ => return Err(e),
+            },
+        };
+        history.assign_ids();
+        Ok(history)
+    }
+
+    fn commit(&mut self, history: &ClipboardHistory, changes: &[Change]) -> Result<()> {
+        if changes.iter().any(Change::drops_contents) {
+            return self.replace(history);
+        }
+        self.write(history)
+    }
+
+    /// Rewrites the history and deletes the backup, which holds the
+    /// previous contents.
+    fn replace(&mut self, history: &ClipboardHistory) -> Result<()> {
+        self.write(history)?;
+        persist::remove_backup(&self.path)?;
+        Ok(())
+    }
+
+    fn set_key(&mut self, key: Option<Key>) {
+        self.key = key;
+    }
+}
+
+/// Reads the history at `path`, or `None` if there is no such file.
+pub(crate) fn read_history(path: &Path, key: Option<&Key>) -> Result<Option<ClipboardHistory>> {
+    let mut contents = Vec::new();
+    match File::open(path) {
+        Ok(mut f) => f.read_to_end(&mut contents)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let contents = crypto::open(key, contents)?;
+    Ok(Some(serde_json::from_slice(&contents)?))
+}
@@ -0,0 +1,331 @@
+use super::{Change, HistoryStore};
+use crate::backend::Selection;
+use crate::crypto::{self, Key};
+use crate::error::{ClipmateError, Result};
+use crate::manager::{ClipboardHistory, ClipboardItem, ClipboardItemType};
+use crate::persist;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+const SCHEMA_VERSION: i64 = 5;
+
+const SCHEMA: &str = "
+    CREATE TABLE items (
+        id INTEGER PRIMARY KEY,
+        time INTEGER NOT NULL,
+        item_type TEXT NOT NULL,
+        data BLOB NOT NULL,
+        hash TEXT,
+        pinned INTEGER NOT NULL DEFAULT 0,
+        expires_at INTEGER,
+        blob TEXT,
+        formats TEXT,
+        snapshots TEXT,
+        selection TEXT
+    );
+    CREATE INDEX items_time ON items (time);
+    CREATE INDEX items_type ON items (item_type);
+    CREATE INDEX items_hash ON items (hash);
+    CREATE TABLE counters (
+        item_type TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    );
+    CREATE VIRTUAL TABLE items_fts USING fts5 (
+        data,
+        content = '',
+        contentless_delete = 1,
+        tokenize = 'trigram'
+    );
+";
+
+/// Upgrades a database from the version it is indexed by to the next.
+const MIGRATIONS: &[&str] = &[
+    // 1: items reference the blob store.
+    "ALTER TABLE items ADD COLUMN blob TEXT;",
+    // 2: items carry other representations of their content.
+    "ALTER TABLE items ADD COLUMN formats TEXT;",
+    // 3: files items keep snapshots of their files.
+    "ALTER TABLE items ADD COLUMN snapshots TEXT;",
+    // 4: items record the selection they were copied to.
+    "ALTER TABLE items ADD COLUMN selection TEXT;",
+];
+
+/// Stores the history in an SQLite database, so that a change only writes
+/// the items it touches. `load` still reads every item, as the manager
+/// keeps the whole history in memory.
+///
+/// Items are indexed by time, type and content hash, and text items by a
+/// trigram full-text index. Deleted items are overwritten with zeros, and
+/// the write-ahead log holding their previous pages is emptied. With
+/// encryption enabled each item's data is encrypted on its own, and neither
+/// the hash nor the text index is kept, as they would reveal the contents.
+pub struct SqliteStore {
+    conn: Connection,
+    key: Option<Key>,
+}
+
+const ITEM_TYPES: [ClipboardItemType; 3] = [
+    ClipboardItemType::TEXT,
+    ClipboardItemType::IMAGE,
+    ClipboardItemType::FILES,
+];
+
+fn item_type_name(item_type: ClipboardItemType) -> &'static str {
+    match item_type {
+        ClipboardItemType::TEXT => "TEXT",
+        ClipboardItemType::IMAGE => "IMAGE",
+        ClipboardItemType::FILES => "FILES",
+    }
+}
+
+fn parse_item_type(name: &str) -> Result<ClipboardItemType> {
+    match name {
+        "TEXT" => Ok(ClipboardItemType::TEXT),
+        "IMAGE" => Ok(ClipboardItemType::IMAGE),
+        "FILES" => Ok(ClipboardItemType::FILES),
+        _ => Err(ClipmateError::InvalidInput(format!(
+            "unknown item type '{}' in the database",
+            name
+        ))),
+    }
+}
+
+/// Converts a time in nanoseconds to an SQLite integer, which holds times
+/// up to the year 2262.
+fn to_sql_time(time: u128) -> Result<i64> {
+    i64::try_from(time)
+        .map_err(|_| ClipmateError::InvalidInput(format!("time {} is out of range", time)))
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open(path: &Path, key: Option<Key>) -> Result<SqliteStore> {
+        let mut conn = Connection::open(path)?;
+        // The write-ahead log and its index are created with the same mode.
+        fs::set_permissions(path, fs::Permissions::from_mode(persist::FILE_MODE))?;
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "secure_delete", true)?;
+
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        match version {
+            0 => {
+                let tx = conn.transaction()?;
+                tx.execute_batch(SCHEMA)?;
+                tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+                tx.commit()?;
+            }
+            SCHEMA_VERSION => {}
+            1..SCHEMA_VERSION => {
+                let tx = conn.transaction()?;
+                for migration in &MIGRATIONS[version as usize - 1..] {
+                    tx.execute_batch(migration)?;
+                }
+                tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+                tx.commit()?;
+            }
+            _ => {
+                return Err(ClipmateError::InvalidInput(format!(
+                    "{} was written by a newer version of clipmate",
+                    path.display()
+                )))
+            }
+        }
+        Ok(SqliteStore { conn, key })
+    }
+}
+
+fn insert(tx: &Transaction, key: Option<&Key>, item: &ClipboardItem) -> Result<()> {
+    let (data, hash) = match key {
+        Some(key) => (crypto::encrypt(key, item.data.as_bytes())?, None),
+        None => (
+            item.data.clone().into_bytes(),
+            Some(format!("{:x}", Sha256::digest(item.data.as_bytes()))),
+        ),
+    };
+    let expires_at = item.expires_at.map(to_sql_time).transpose()?;
+    let formats = if item.formats.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_string(&item.formats)?)
+    };
+    // Snapshots name the copied files, so they are encrypted like the data.
+    let snapshots = if item.snapshots.is_empty() {
+        None
+    } else {
+        Some(crypto::seal(key, serde_json::to_vec(&item.snapshots)?)?)
+    };
+    tx.execute(
+        "INSERT INTO items
+             (id, time, item_type, data, hash, pinned, expires_at, blob, formats, snapshots,
+              selection)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            item.id as i64,
+            to_sql_time(item.time)?,
+            item_type_name(item.item_type),
+            data,
+            hash,
+            item.pinned,
+            expires_at,
+            item.blob,
+            formats,
+            snapshots,
+            (!item.selection.is_clipboard()).then(|| item.selection.name()),
+        ],
+    )?;
+    if key.is_none() && item.item_type == ClipboardItemType::TEXT {
+        tx.execute(
+            "INSERT INTO items_fts (rowid, data) VALUES (?1, ?2)",
+            params![item.id as i64, item.data],
+        )?;
+    }
+    Ok(())
+}
+
+fn remove(tx: &Transaction, item: &ClipboardItem) -> Result<()> {
+    tx.execute("DELETE FROM items WHERE id = ?1", [item.id as i64])?;
+    tx.execute("DELETE FROM items_fts WHERE rowid = ?1", [item.id as i64])?;
+    Ok(())
+}
+
+fn write_counters(tx: &Transaction, history: &ClipboardHistory) -> Result<()> {
+    for item_type in ITEM_TYPES {
+        tx.execute(
+            "INSERT OR REPLACE INTO counters (item_type, value) VALUES (?1, ?2)",
+            params![
+                item_type_name(item_type),
+                history.get_counter(item_type) as i64
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+impl HistoryStore for SqliteStore {
+    fn load(&mut self) -> Result<ClipboardHistory> {
+        let mut history = ClipboardHistory::default();
+        let mut stmt = self.conn.prepare(
+            "SELECT id, time, item_type, data, pinned, expires_at, blob, formats, snapshots,
+                    selection
+             FROM items ORDER BY id",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let data = crypto::open(self.key.as_ref(), row.get(3)?)?;
+            let data = String::from_utf8(data)
+                .map_err(|_| ClipmateError::InvalidInput("item data is not UTF-8".to_string()))?;
+            history.items.push(ClipboardItem {
+                id: row.get::<_, i64>(0)? as u64,
+                time: row.get::<_, i64>(1)? as u128,
+                item_type: parse_item_type(&row.get::<_, String>(2)?)?,
+                data,
+                pinned: row.get(4)?,
+                expires_at: row.get::<_, Option<i64>>(5)?.map(|at| at as u128),
+                blob: row.get(6)?,
+                formats: match row.get::<_, Option<String>>(7)? {
+                    Some(formats) => serde_json::from_str(&formats)?,
+                    None => Vec::new(),
+                },
+                snapshots: match row.get::<_, Option<Vec<u8>>>(8)? {
+                    Some(snapshots) => {
+                        serde_json::from_slice(&crypto::open(self.key.as_ref(), snapshots)?)?
+                    }
+                    None => Vec::new(),
+                },
+                selection: match row.get::<_, Option<String>>(9)? {
+                    Some(selection) => selection.parse()?,
+                    None => Selection::Clipboard,
+                },
+            });
+        }
+
+        for item_type in ITEM_TYPES {
+            let value: Option<i64> = self
+                .conn
+                .query_row(
+                    "SELECT value FROM counters WHERE item_type = ?1",
+                    [item_type_name(item_type)],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let value = value.unwrap_or(0) as u128;
+            match item_type {
+                ClipboardItemType::TEXT => history.text_counter = value,
+                ClipboardItemType::IMAGE => history.image_counter = value,
+                ClipboardItemType::FILES => history.files_counter = value,
+            }
+        }
+        Ok(history)
+    }
+
+    fn commit(&mut self, history: &ClipboardHistory, changes: &[Change]) -> Result<()> {
+        let key = self.key.as_ref();
+        let tx = self.conn.transaction()?;
+        for change in changes {
+            match change {
+                Change::Insert(item) => insert(&tx, key, item)?,
+                Change::Update(item) | Change::Edit(item) => {
+                    remove(&tx, item)?;
+                    insert(&tx, key, item)?;
+                }
+                Change::Remove(items) | Change::Prune(items) => {
+                    for item in items.iter() {
+                        remove(&tx, item)?;
+                    }
+                }
+            }
+        }
+        write_counters(&tx, history)?;
+        tx.commit()?;
+        if changes.iter().any(Change::drops_contents) {
+            self.conn
+                .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites every item and compacts the database, so that no data
+    /// written before, possibly under another key, is left in free pages.
+    fn replace(&mut self, history: &ClipboardHistory) -> Result<()> {
+        let key = self.key.as_ref();
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM items", [])?;
+        tx.execute("DELETE FROM items_fts", [])?;
+        for item in &history.items {
+            insert(&tx, key, item)?;
+        }
+        write_counters(&tx, history)?;
+        tx.commit()?;
+        self.conn.execute_batch("VACUUM")?;
+        self.conn
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    fn set_key(&mut self, key: Option<Key>) {
+        self.key = key;
+    }
+
+    /// Looks `pattern` up in the trigram index, which only works for
+    /// patterns of three characters or more. Non-ASCII patterns are left to
+    /// the caller, as the index folds case differently from Rust.
+    fn find_text(&self, pattern: &str) -> Result<Option<HashSet<u64>>> {
+        if self.key.is_some() || pattern.len() < 3 || !pattern.is_ascii() {
+            return Ok(None);
+        }
+        let query = format!("\"{}\"", pattern.replace('"', "\"\""));
+        let mut stmt = self
+            .conn
+            .prepare("SELECT rowid FROM items_fts WHERE items_fts MATCH ?1")?;
+        let ids = stmt
+            .query_map([query], |row| row.get::<_, i64>(0))?
+            .map(|id| id.map(|id| id as u64))
+            .collect::<rusqlite::Result<HashSet<u64>>>()?;
+        Ok(Some(ids))
+    }
+}
@@ -0,0 +1,248 @@
+use crate::backend::Selection;
+use crate::config::DaemonConfig;
+use std::env;
+use std::io::{self, BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use x11rb::connection::Connection;
+use x11rb::protocol::xfixes::{ConnectionExt as _, SelectionEventMask};
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _};
+use x11rb::protocol::Event as X11Event;
+
+/// What ended a `Watcher::wait`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The watched selections may have changed and should be read.
+    Changed,
+    /// The timeout passed first.
+    Timeout,
+}
+
+/// Tells the daemon when to read the clipboard.
+pub trait Watcher {
+    /// Short name for messages, such as `xfixes` or `poll`.
+    fn name(&self) -> &'static str;
+
+    /// Blocks until the watched selections may have changed, or for at
+    /// most `timeout`. An error means no more events will come.
+    fn wait(&mut self, timeout: Duration) -> io::Result<Event>;
+
+    /// Tells the watcher whether reading the clipboard after its last event
+    /// found anything new.
+    fn checked(&mut self, _changed: bool) {}
+
+    /// Applies a reloaded daemon configuration.
+    fn reconfigure(&mut self, _config: &DaemonConfig) {}
+}
+
+/// Polls the clipboard, more often while it changes and less often while
+/// it does not.
+///
+/// The interval starts at `min`, doubles after every poll that found
+/// nothing new up to `max`, and drops back to `min` once one does.
+pub struct PollWatcher {
+    min: Duration,
+    max: Duration,
+    interval: Duration,
+    next_poll: Instant,
+}
+
+impl PollWatcher {
+    pub fn new(min: Duration, max: Duration) -> PollWatcher {
+        PollWatcher {
+            min,
+            max: max.max(min),
+            interval: min,
+            next_poll: Instant::now(),
+        }
+    }
+
+    /// Polls between `poll_interval_ms` and `max_poll_interval_ms`.
+    pub fn from_config(config: &DaemonConfig) -> PollWatcher {
+        PollWatcher::new(
+            Duration::from_millis(config.poll_interval_ms),
+            Duration::from_millis(config.max_poll_interval_ms),
+        )
+    }
+
+    /// The interval until the next poll.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+impl Watcher for PollWatcher {
+    fn name(&self) -> &'static str {
+        "poll"
+    }
+
+    fn wait(&mut self, timeout: Duration) -> io::Result<Event> {
+        let now = Instant::now();
+        let until_poll = self.next_poll.saturating_duration_since(now);
+        if until_poll > timeout {
+            thread::sleep(timeout);
+            return Ok(Event::Timeout);
+        }
+        thread::sleep(until_poll);
+        Ok(Event::Changed)
+    }
+
+    fn checked(&mut self, changed: bool) {
+        self.interval = if changed {
+            self.min
+        } else {
+            (self.interval * 2).min(self.max)
+        };
+        self.next_poll = Instant::now() + self.interval;
+    }
+
+    fn reconfigure(&mut self, config: &DaemonConfig) {
+        let reloaded = PollWatcher::from_config(config);
+        self.min = reloaded.min;
+        self.max = reloaded.max;
+        self.interval = self.interval.clamp(self.min, self.max);
+    }
+}
+
+/// Receives change notifications from a thread watching the clipboard, or
+/// from a test through the `Sender` returned by `ChannelWatcher::new`.
+///
+/// Notifications arriving while the daemon reads the clipboard are merged
+/// into one. Once every sender is gone, `wait` fails.
+pub struct ChannelWatcher {
+    name: &'static str,
+    events: Receiver<()>,
+    /// Processes sending the notifications, killed with the watcher.
+    children: Vec<Child>,
+}
+
+impl ChannelWatcher {
+    pub fn new(name: &'static str) -> (Sender<()>, ChannelWatcher) {
+        let (sender, events) = mpsc::channel();
+        let watcher = ChannelWatcher {
+            name,
+            events,
+            children: Vec::new(),
+        };
+        (sender, watcher)
+    }
+}
+
+impl Watcher for ChannelWatcher {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn wait(&mut self, timeout: Duration) -> io::Result<Event> {
+        match self.events.recv_timeout(timeout) {
+            Ok(()) => {
+                while self.events.try_recv().is_ok() {}
+                Ok(Event::Changed)
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(Event::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                format!("the {} watcher stopped", self.name),
+            )),
+        }
+    }
+}
+
+impl Drop for ChannelWatcher {
+    fn drop(&mut self) {
+        for child in &mut self.children {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn x11_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// Watches the selections on X11 through XFixes, which notifies clients
+/// whenever a selection changes owner, as it does on every copy.
+pub fn xfixes(selections: &[Selection]) -> io::Result<ChannelWatcher> {
+    let (conn, screen) = x11rb::connect(None).map_err(x11_error)?;
+    conn.xfixes_query_version(5, 0)
+        .map_err(x11_error)?
+        .reply()
+        .map_err(x11_error)?;
+    let root = conn.setup().roots[screen].root;
+    let mask = SelectionEventMask::SET_SELECTION_OWNER
+        | SelectionEventMask::SELECTION_WINDOW_DESTROY
+        | SelectionEventMask::SELECTION_CLIENT_CLOSE;
+    for &selection in selections {
+        let atom = match selection {
+            Selection::Clipboard => {
+                conn.intern_atom(false, b"CLIPBOARD")
+                    .map_err(x11_error)?
+                    .reply()
+                    .map_err(x11_error)?
+                    .atom
+            }
+            Selection::Primary => AtomEnum::PRIMARY.into(),
+            Selection::Secondary => AtomEnum::SECONDARY.into(),
+        };
+        conn.xfixes_select_selection_input(root, atom, mask)
+            .map_err(x11_error)?;
+    }
+    conn.flush().map_err(x11_error)?;
+
+    let (sender, watcher) = ChannelWatcher::new("xfixes");
+    thread::spawn(move || {
+        while let Ok(event) = conn.wait_for_event() {
+            if let X11Event::XfixesSelectionNotify(_) = event {
+                if sender.send(()).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(watcher)
+}
+
+/// Watches the selections on Wayland through `wl-paste --watch`, which
+/// runs a command on every copy. Wayland has no secondary selection.
+pub fn wl_paste(selections: &[Selection]) -> io::Result<ChannelWatcher> {
+    let (sender, mut watcher) = ChannelWatcher::new("wl-paste");
+    for &selection in selections {
+        let mut command = Command::new("wl-paste");
+        match selection {
+            Selection::Clipboard => {}
+            Selection::Primary => {
+                command.arg("--primary");
+            }
+            Selection::Secondary => continue,
+        }
+        let mut child = command
+            .args(["--watch", "echo"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        watcher.children.push(child);
+        let sender = sender.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                if line.is_err() || sender.send(()).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    Ok(watcher)
+}
+
+/// Watches the selections with the event source of the session, XFixes on
+/// X11 or `wl-paste` on Wayland.
+pub fn events(selections: &[Selection]) -> io::Result<ChannelWatcher> {
+    match env::var_os("WAYLAND_DISPLAY") {
+        Some(display) if !display.is_empty() => wl_paste(selections),
+        _ => xfixes(selections),
+    }
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend};
+use clipboard_manager_lib::manager::ClipboardItemType;
+use clipboard_manager_lib::ClipmateError;
+use common::{memory_manager, texts};
+use tempfile::TempDir;
+
+#[test]
+fn copies_are_added_to_the_history() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    assert!(matches!(
+        manager.update_clipboard_content(),
+        Err(ClipmateError::Backend(_))
+    ));
+    assert!(manager.get_history().is_empty());
+
+    for text in ["one", "two"] {
+        manager.backend_mut().write_text(text).unwrap();
+        manager.update_clipboard_content().unwrap();
+    }
+    assert_eq!(texts(&manager), vec!["one", "two"]);
+    assert_eq!(texts(&memory_manager(dir.path())), vec!["one", "two"]);
+}
+
+#[test]
+fn repeated_copies_are_recorded_once() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    manager.backend_mut().write_text("one").unwrap();
+    manager.update_clipboard_content().unwrap();
+    manager.update_clipboard_content().unwrap();
+    manager.backend_mut().write_text("one").unwrap();
+    manager.update_clipboard_content().unwrap();
+    assert_eq!(texts(&manager), vec!["one"]);
+
+    manager
+        .backend_mut()
+        .write_image("image/png", &[1; 100])
+        .unwrap();
+    manager.update_image_content().unwrap();
+    manager.update_image_content().unwrap();
+    assert_eq!(manager.get_history().len(), 2);
+    assert_eq!(manager.get_counter(ClipboardItemType::IMAGE), 1);
+}
+
+#[test]
+fn restoring_writes_the_item_back() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    manager.save_text("one".to_string()).unwrap();
+    manager
+        .backend_mut()
+        .write_image("image/png", &[2; 100])
+        .unwrap();
+    manager.update_image_content().unwrap();
+    manager.save_text("two".to_string()).unwrap();
+
+    manager.set_clipboard_text(1).unwrap();
+    assert_eq!(manager.backend().text(), Some("one"));
+    manager.set_clipboard_text(2).unwrap();
+    assert_eq!(manager.backend().image("image/png"), Some(&[2; 100][..]));
+    assert_eq!(manager.backend().text(), None);
+    assert!(manager.set_clipboard_text(4).is_err());
+    assert_eq!(manager.get_history().len(), 3);
+}
+
+#[test]
+fn memory_backend_holds_one_item() {
+    let mut backend = MemoryBackend::new();
+    assert!(backend.read_text().is_err());
+    backend.write_text("text").unwrap();
+    backend.write_image("image/png", &[3; 10]).unwrap();
+    assert!(backend.read_text().is_err());
+    assert_eq!(backend.read_type("image/png").unwrap(), vec![3; 10]);
+    backend.write_text("text").unwrap();
+    assert!(backend.read_type("image/png").unwrap().is_empty());
+    assert_eq!(backend.read_text().unwrap(), "text");
+}
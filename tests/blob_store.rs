@@ -0,0 +1,139 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend};
+use clipboard_manager_lib::blobs::BlobStore;
+use clipboard_manager_lib::manager::{ClipboardItemType, ClipboardManager};
+use common::{blob_files, config_for, memory_manager, texts};
+use std::fs;
+use tempfile::TempDir;
+
+fn copy_image(manager: &mut ClipboardManager<MemoryBackend>, seed: u8) {
+    manager
+        .backend_mut()
+        .write_image("image/png", &[seed; 100])
+        .unwrap();
+    manager.update_image_content().unwrap();
+}
+
+#[test]
+fn blobs_are_sharded_by_id_and_shared() {
+    let dir = TempDir::new().unwrap();
+    let mut blobs = BlobStore::new(dir.path().to_path_buf(), None, false);
+    let id = blobs.put(b"contents").unwrap();
+    assert_eq!(blobs.put(b"contents").unwrap(), id);
+    assert_eq!(blobs.refcount(&id), 2);
+    let path = dir.path().join(&id[..2]).join(&id[2..]);
+    assert_eq!(fs::read(&path).unwrap(), b"contents");
+
+    blobs.release(&id).unwrap();
+    assert!(path.exists());
+    blobs.release(&id).unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn compressed_blobs_read_back() {
+    let dir = TempDir::new().unwrap();
+    let mut blobs = BlobStore::new(dir.path().to_path_buf(), None, true);
+    let data = vec![b'a'; 10_000];
+    let id = blobs.put(&data).unwrap();
+    assert!(blobs.size(&id) < 1000);
+    assert_eq!(blobs.get(&id).unwrap(), data);
+
+    // Blobs stay readable after compression is turned off.
+    let blobs = BlobStore::new(dir.path().to_path_buf(), None, false);
+    assert_eq!(blobs.get(&id).unwrap(), data);
+}
+
+#[test]
+fn long_text_is_kept_in_a_blob() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config_for(dir.path());
+    config.storage.blob_threshold = 2000;
+    let mut manager = ClipboardManager::with_backend(config.clone(), MemoryBackend::new()).unwrap();
+    let long = "é".repeat(1500);
+    manager.save_text(long.clone()).unwrap();
+    manager.save_text("short".to_string()).unwrap();
+
+    let item = manager.get_item(1).unwrap();
+    assert!(item.blob.is_some());
+    assert!(item.data.len() <= 1024 && long.starts_with(&item.data));
+    assert!(manager.get_item(2).unwrap().blob.is_none());
+    assert_eq!(blob_files(dir.path()).len(), 1);
+    drop(manager);
+
+    let mut manager = ClipboardManager::with_backend(config, MemoryBackend::new()).unwrap();
+    assert_eq!(manager.item_text(1).unwrap(), long);
+    manager.set_clipboard_text(1).unwrap();
+    assert_eq!(manager.backend_mut().read_text().unwrap(), long);
+
+    manager.edit_text(1, "now short".to_string()).unwrap();
+    assert_eq!(texts(&manager), vec!["now short", "short"]);
+    assert!(blob_files(dir.path()).is_empty());
+}
+
+#[test]
+fn the_same_image_is_stored_once() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    copy_image(&mut manager, 1);
+    copy_image(&mut manager, 1);
+    copy_image(&mut manager, 2);
+
+    assert_eq!(manager.get_counter(ClipboardItemType::IMAGE), 2);
+    assert_eq!(blob_files(dir.path()).len(), 2);
+    manager.delete(&[1]).unwrap();
+    assert_eq!(blob_files(dir.path()).len(), 1);
+}
+
+#[test]
+fn fsck_reports_damaged_and_unreferenced_blobs() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    copy_image(&mut manager, 1);
+    copy_image(&mut manager, 2);
+    assert!(manager.fsck().unwrap().is_ok());
+
+    let ids: Vec<String> = manager
+        .get_history()
+        .iter()
+        .map(|item| item.blob.clone().unwrap())
+        .collect();
+    let path = |id: &str| dir.path().join("blobs").join(&id[..2]).join(&id[2..]);
+    fs::write(path(&ids[0]), b"damaged").unwrap();
+    fs::remove_file(path(&ids[1])).unwrap();
+    let stray = dir.path().join("blobs").join("00").join("1234");
+    fs::create_dir_all(stray.parent().unwrap()).unwrap();
+    fs::write(&stray, b"stray").unwrap();
+
+    let report = manager.fsck().unwrap();
+    assert_eq!(report.checked, 1);
+    assert_eq!(report.corrupt, vec![ids[0].clone()]);
+    assert_eq!(report.missing, vec![ids[1].clone()]);
+    assert_eq!(report.orphaned, vec![stray.clone()]);
+
+    assert_eq!(manager.collect_garbage().unwrap(), 1);
+    assert!(!stray.exists());
+}
+
+#[test]
+fn images_stored_before_the_blob_store_are_moved_into_it() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("history.json"),
+        r#"{"items":[{"id":1,"time":1,"item_type":"IMAGE","data":"abc.png"}],"image_counter":1,"text_counter":0}"#,
+    )
+    .unwrap();
+    fs::create_dir(dir.path().join("images")).unwrap();
+    fs::write(dir.path().join("images").join("abc.png"), [5; 100]).unwrap();
+
+    let mut manager = memory_manager(dir.path());
+    assert!(!dir.path().join("images").exists());
+    assert!(manager.get_item(1).unwrap().blob.is_some());
+    assert_eq!(texts(&manager), vec!["abc.png"]);
+    manager.set_clipboard_text(1).unwrap();
+    assert_eq!(manager.backend().image("image/png"), Some(&[5; 100][..]));
+
+    let reopened = memory_manager(dir.path());
+    assert!(reopened.fsck().unwrap().is_ok());
+}
@@ -0,0 +1,194 @@
+use clipboard_manager_lib::lock::PidFile;
+use clipboard_manager_lib::paths::DataPaths;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+/// A `clipmate` command with `args` and every XDG directory under `dir`,
+/// outside any display session, so that no daemon or clipboard is reached.
+fn command(dir: &Path, args: &[&str]) -> Command {
+    let runtime_dir = dir.join("run");
+    fs::create_dir_all(&runtime_dir).unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_clipmate"));
+    command
+        .args(args)
+        .current_dir(dir)
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_STATE_HOME", dir.join("state"))
+        .env("XDG_RUNTIME_DIR", runtime_dir)
+        .env_remove("DISPLAY")
+        .env_remove("WAYLAND_DISPLAY");
+    command
+}
+
+fn clipmate(dir: &Path, args: &[&str]) -> Output {
+    command(dir, args).output().unwrap()
+}
+
+/// Asserts that `output` is a failure with `code` and an error message
+/// containing `message`.
+fn assert_fails(output: &Output, code: i32, message: &str) {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(code), "{}", stderr);
+    assert!(stderr.starts_with("clipmate: "), "{}", stderr);
+    assert!(stderr.contains(message), "{}", stderr);
+}
+
+fn write_history(dir: &Path, contents: &str) {
+    let data_dir = dir.join("data").join("clipmate");
+    fs::create_dir_all(&data_dir).unwrap();
+    fs::write(data_dir.join("history.json"), contents).unwrap();
+}
+
+fn write_config(dir: &Path, contents: &str) {
+    let config_dir = dir.join("config").join("clipmate");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::write(config_dir.join("config.toml"), contents).unwrap();
+}
+
+const HISTORY: &str =
+    r#"{"items":[{"time":1,"item_type":"TEXT","data":"hi"}],"image_counter":0,"text_counter":1}"#;
+
+#[test]
+fn prints_an_item() {
+    let dir = TempDir::new().unwrap();
+    write_history(dir.path(), HISTORY);
+    let output = clipmate(dir.path(), &["get", "1"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"hi\n");
+}
+
+#[test]
+fn missing_item_exits_with_2() {
+    let dir = TempDir::new().unwrap();
+    write_history(dir.path(), HISTORY);
+    let output = clipmate(dir.path(), &["get", "5"]);
+    assert_fails(&output, 2, "item 5 not found in clipboard history");
+}
+
+#[test]
+fn clear_keeps_pinned_items() {
+    let dir = TempDir::new().unwrap();
+    write_history(
+        dir.path(),
+        r#"{"items":[{"time":1,"item_type":"TEXT","data":"kept","pinned":true},{"time":2,"item_type":"TEXT","data":"cleared"}],"image_counter":0,"text_counter":2}"#,
+    );
+    assert!(clipmate(dir.path(), &["clear"]).status.success());
+    let output = clipmate(dir.path(), &["get", "1"]);
+    assert_eq!(output.stdout, b"kept\n");
+    assert_fails(&clipmate(dir.path(), &["get", "2"]), 2, "item 2 not found");
+
+    let output = clipmate(dir.path(), &["clear", "--keep-pinned"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn invalid_input_exits_with_2() {
+    let dir = TempDir::new().unwrap();
+    let output = clipmate(dir.path(), &["clear", "--older-than", "soon"]);
+    assert_fails(&output, 2, "invalid duration 'soon'");
+}
+
+#[test]
+fn invalid_config_exits_with_2() {
+    let dir = TempDir::new().unwrap();
+    write_config(dir.path(), "[daemon]\nlog_level = \"loud\"\n");
+    let output = clipmate(dir.path(), &["history"]);
+    assert_fails(&output, 2, "invalid configuration: ");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stderr.matches("invalid configuration").count(),
+        1,
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn corrupt_history_exits_with_1() {
+    let dir = TempDir::new().unwrap();
+    write_history(dir.path(), "{\"items\":[");
+    let output = clipmate(dir.path(), &["history"]);
+    assert_fails(&output, 1, "failed to parse clipboard history");
+}
+
+#[test]
+fn unreachable_clipboard_exits_with_3() {
+    let dir = TempDir::new().unwrap();
+    write_history(dir.path(), HISTORY);
+    let output = clipmate(dir.path(), &["1"]);
+    assert_fails(&output, 3, "clipboard unavailable");
+}
+
+#[test]
+fn locked_history_exits_with_4() {
+    let dir = TempDir::new().unwrap();
+    write_config(dir.path(), "[encryption]\nenabled = true\n");
+    let output = clipmate(dir.path(), &["history"]);
+    assert_fails(&output, 4, "run 'clipmate unlock' first");
+}
+
+#[test]
+fn second_daemon_exits_with_1() {
+    let dir = TempDir::new().unwrap();
+    let paths = DataPaths::new(dir.path().join("data").join("clipmate"));
+    let _running = PidFile::acquire(&paths.pid_file()).unwrap();
+    let output = clipmate(dir.path(), &["daemon", "start", "--foreground"]);
+    assert_fails(&output, 1, "another daemon is already running");
+}
+
+#[test]
+fn bare_daemon_runs_in_the_foreground() {
+    let dir = TempDir::new().unwrap();
+    let paths = DataPaths::new(dir.path().join("data").join("clipmate"));
+    let _running = PidFile::acquire(&paths.pid_file()).unwrap();
+
+    // In the foreground the daemon fails on the locked pid file, while
+    // `start` finds a daemon running and leaves it be.
+    let output = clipmate(dir.path(), &["daemon"]);
+    assert_fails(&output, 1, "another daemon is already running");
+    let output = clipmate(dir.path(), &["daemon", "start"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("already running"));
+}
+
+/// Writes an editor script recording the path and mode of the file it is
+/// given to `dir/edited`, then replacing its contents with `edited` and
+/// exiting with `status`.
+fn write_editor(dir: &Path, status: i32) -> String {
+    let editor = dir.join("editor.sh");
+    fs::write(
+        &editor,
+        format!(
+            "#!/bin/sh\necho \"$1\" > {0}/edited\nstat -c %a \"$1\" >> {0}/edited\nprintf edited > \"$1\"\nexit {1}\n",
+            dir.display(),
+            status
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&editor, fs::Permissions::from_mode(0o755)).unwrap();
+    editor.to_string_lossy().into_owned()
+}
+
+#[test]
+fn edits_in_a_private_runtime_file() {
+    let dir = TempDir::new().unwrap();
+    write_history(dir.path(), HISTORY);
+    for status in [1, 0] {
+        let output = command(dir.path(), &["edit", "1"])
+            .env("VISUAL", write_editor(dir.path(), status))
+            .output()
+            .unwrap();
+        assert_eq!(output.status.success(), status == 0);
+
+        let edited = fs::read_to_string(dir.path().join("edited")).unwrap();
+        let (path, mode) = edited.trim().split_once('\n').unwrap();
+        assert!(Path::new(path).starts_with(dir.path().join("run").join("clipmate")));
+        assert_eq!(mode, "600");
+        assert!(!Path::new(path).exists());
+    }
+    assert_eq!(clipmate(dir.path(), &["get", "1"]).stdout, b"edited\n");
+}
@@ -0,0 +1,54 @@
+#![allow(dead_code)]
+
+use clipboard_manager_lib::backend::MemoryBackend;
+use clipboard_manager_lib::config::Config;
+use clipboard_manager_lib::manager::ClipboardManager;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Default config storing everything under `data_dir`.
+pub fn config_for(data_dir: &Path) -> Config {
+    let mut config = Config::default();
+    config.storage.data_dir = Some(data_dir.to_path_buf());
+    config
+}
+
+/// Manager over an in-memory clipboard storing everything under `data_dir`.
+pub fn memory_manager(data_dir: &Path) -> ClipboardManager<MemoryBackend> {
+    ClipboardManager::with_backend(config_for(data_dir), MemoryBackend::new()).unwrap()
+}
+
+/// Every file in the blob store under `data_dir`.
+pub fn blob_files(data_dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for shard in fs::read_dir(data_dir.join("blobs")).into_iter().flatten() {
+        for entry in fs::read_dir(shard.unwrap().path()).unwrap() {
+            files.push(entry.unwrap().path());
+        }
+    }
+    files
+}
+
+pub fn texts<B: clipboard_manager_lib::backend::ClipboardBackend>(
+    manager: &ClipboardManager<B>,
+) -> Vec<String> {
+    manager
+        .get_history()
+        .iter()
+        .map(|i| i.data.clone())
+        .collect()
+}
+
+/// A stand-in for `clipmate serve-selection`, recording its arguments and
+/// the offer it is given in `dir`.
+pub fn stub_server(dir: &Path, answer: &str) -> PathBuf {
+    let script = format!(
+        "#!/bin/sh\nprintf '%s\\n' \"$*\" > \"{dir}/args\"\ncat > \"{dir}/offer\"\n{answer}\n",
+        dir = dir.display(),
+    );
+    let path = dir.join("clipmate");
+    fs::write(&path, script).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use clipboard_manager_lib::backend::ClipboardBackend;
+use common::{memory_manager, texts};
+use tempfile::TempDir;
+
+#[test]
+fn concealed_text_is_never_recorded() {
+    for hint in [
+        "x-kde-passwordManagerHint",
+        "application/x-nspasteboard-concealed-type",
+        "org.nspasteboard.TransientType",
+    ] {
+        let dir = TempDir::new().unwrap();
+        let mut manager = memory_manager(dir.path());
+        manager.backend_mut().write_text("correct horse").unwrap();
+        manager.backend_mut().offer_type(hint);
+        manager.update_clipboard_content().unwrap();
+        assert!(texts(&manager).is_empty(), "{} was ignored", hint);
+    }
+}
+
+#[test]
+fn recording_resumes_once_the_hint_is_gone() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    manager.backend_mut().write_text("s3cret").unwrap();
+    manager
+        .backend_mut()
+        .offer_type("x-kde-passwordManagerHint");
+    manager.update_clipboard_content().unwrap();
+
+    // Copying the same text without the hint records it.
+    manager.backend_mut().write_text("s3cret").unwrap();
+    manager.update_clipboard_content().unwrap();
+    assert_eq!(texts(&manager), vec!["s3cret"]);
+}
+
+#[test]
+fn concealed_images_are_never_recorded() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    manager
+        .backend_mut()
+        .write_image("image/png", &[5; 100])
+        .unwrap();
+    manager
+        .backend_mut()
+        .offer_type("application/x-nspasteboard-concealed-type");
+    manager.update_image_content().unwrap();
+    assert!(manager.get_history().is_empty());
+}
+
+#[test]
+fn unrelated_extra_types_do_not_block_capture() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    manager.backend_mut().write_text("<b>hi</b>").unwrap();
+    manager.backend_mut().offer_type("text/html");
+    manager.update_clipboard_content().unwrap();
+    assert_eq!(texts(&manager), vec!["<b>hi</b>"]);
+}
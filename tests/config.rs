@@ -0,0 +1,113 @@
+mod common;
+
+use clipboard_manager_lib::backend::MemoryBackend;
+use clipboard_manager_lib::config::{Config, LogLevel};
+use clipboard_manager_lib::manager::ClipboardManager;
+use clipboard_manager_lib::ClipmateError;
+use common::{config_for, texts};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+#[test]
+fn empty_file_gives_defaults() {
+    let config = Config::parse("").unwrap();
+    assert_eq!(config, Config::default());
+    assert_eq!(config.daemon.poll_interval_ms, 250);
+    assert_eq!(config.daemon.max_poll_interval_ms, 500);
+    assert_eq!(config.capture.image_types, vec!["image/png"]);
+    assert_eq!(config.capture.min_image_size, 50);
+}
+
+#[test]
+fn partial_file_overrides_only_listed_settings() {
+    let config = Config::parse(
+        r#"
+        [daemon]
+        poll_interval_ms = 100
+
+        [history]
+        max_items = 10
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.daemon.poll_interval_ms, 100);
+    assert_eq!(config.history.max_items, Some(10));
+    assert_eq!(config.capture, Config::default().capture);
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+    for contents in [
+        "[daemon]\npoll_interval_ms = 0",
+        "[daemon]\npoll_interval_ms = 500\nmax_poll_interval_ms = 100",
+        "[daemon]\nwatch = \"inotify\"",
+        "[capture]\nselection = \"secondary-ish\"",
+        "[capture]\nignore = [\"(unclosed\"]",
+        "[daemon]\nunknown = 1",
+        "[daemon]\nlog_level = \"loud\"",
+    ] {
+        assert!(
+            matches!(Config::parse(contents), Err(ClipmateError::Config(_))),
+            "{}",
+            contents
+        );
+    }
+}
+
+#[test]
+fn shown_config_parses_back() {
+    let mut config = Config::default();
+    config.history.max_items = Some(3);
+    config.storage.data_dir = Some("/tmp/clipmate".into());
+    assert_eq!(Config::parse(&config.to_toml().unwrap()).unwrap(), config);
+}
+
+#[test]
+fn explicit_missing_file_is_an_error() {
+    let dir = TempDir::new().unwrap();
+    assert!(Config::load(Some(&dir.path().join("missing.toml"))).is_err());
+
+    let path = dir.path().join("config.toml");
+    fs::write(&path, "[history]\nmax_items = 2\n").unwrap();
+    assert_eq!(
+        Config::load(Some(&path)).unwrap().history.max_items,
+        Some(2)
+    );
+}
+
+#[test]
+fn manager_applies_ignore_rules_and_max_items() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config_for(dir.path());
+    config.capture.ignore = vec!["^secret:".to_string()];
+    config.history.max_items = Some(2);
+    let mut manager = ClipboardManager::with_backend(config, MemoryBackend::new()).unwrap();
+
+    for text in ["one", "secret: hunter2", "two", "three"] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    assert_eq!(texts(&manager), vec!["two", "three"]);
+}
+
+#[test]
+fn restart_is_needed_only_for_settings_read_at_start() {
+    let running = config_for(Path::new("/tmp/clipmate"));
+    let mut reloaded = running.clone();
+    reloaded.daemon.poll_interval_ms = 1000;
+    reloaded.history.max_items = Some(5);
+    reloaded.storage.compact_after = 10;
+    assert!(running.restart_needed(&reloaded).is_empty());
+
+    reloaded.daemon.log_level = LogLevel::Debug;
+    reloaded.daemon.own_clipboard = !running.daemon.own_clipboard;
+    reloaded.storage.data_dir = None;
+    assert_eq!(
+        running.restart_needed(&reloaded),
+        vec![
+            "daemon.own_clipboard",
+            "daemon.log_level",
+            "storage.data_dir"
+        ]
+    );
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use clipboard_manager_lib::doctor::{self, Check, Status};
+use clipboard_manager_lib::logging::RateLimit;
+use common::memory_manager;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn find<'a>(checks: &'a [Check], name: &str) -> &'a Check {
+    checks
+        .iter()
+        .find(|check| check.name == name)
+        .unwrap_or_else(|| panic!("no {} check in {:?}", name, checks))
+}
+
+fn checks_for(data_dir: &Path) -> Vec<Check> {
+    let config = data_dir.with_extension("toml");
+    fs::write(&config, "").unwrap();
+    doctor::run(Some(&config), Some(data_dir))
+}
+
+#[test]
+fn finds_only_executable_tools() {
+    let dir = TempDir::new().unwrap();
+    let bin = dir.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let tool = bin.join("xclip");
+    fs::write(&tool, "#!/bin/sh\n").unwrap();
+    let mut path = OsString::from("/nonexistent:");
+    path.push(&bin);
+
+    assert_eq!(doctor::find_tool("xclip", &path), None);
+    fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+    assert_eq!(doctor::find_tool("xclip", &path), Some(tool));
+    assert_eq!(doctor::find_tool("wl-paste", &path), None);
+}
+
+#[test]
+fn checks_the_data_directory_and_history() {
+    let dir = TempDir::new().unwrap();
+    let data_dir = dir.path().join("data");
+    let checks = checks_for(&data_dir);
+    assert_eq!(find(&checks, "config").status, Status::Ok);
+    assert_eq!(find(&checks, "data directory").status, Status::Ok);
+    assert!(!checks.iter().any(|check| check.name == "history"));
+    assert!(!data_dir.exists());
+
+    let mut manager = memory_manager(&data_dir);
+    manager.save_text("copied".to_string()).unwrap();
+    fs::set_permissions(&data_dir, fs::Permissions::from_mode(0o700)).unwrap();
+    let checks = checks_for(&data_dir);
+    assert_eq!(find(&checks, "data directory").status, Status::Ok);
+    let history = find(&checks, "history");
+    assert_eq!(history.status, Status::Ok);
+    assert!(history.detail.starts_with("1 item "), "{}", history.detail);
+    assert_eq!(find(&checks, "daemon").status, Status::Warning);
+
+    fs::set_permissions(&data_dir, fs::Permissions::from_mode(0o755)).unwrap();
+    let checks = checks_for(&data_dir);
+    assert_eq!(find(&checks, "data directory").status, Status::Warning);
+}
+
+#[test]
+fn reports_a_data_directory_that_is_a_file() {
+    let dir = TempDir::new().unwrap();
+    let data_dir = dir.path().join("data");
+    fs::write(&data_dir, "").unwrap();
+    let checks = checks_for(&data_dir);
+    let check = find(&checks, "data directory");
+    assert_eq!(check.status, Status::Problem);
+    assert!(check.detail.contains("not a directory"), "{}", check.detail);
+}
+
+#[test]
+fn invalid_config_is_a_problem_but_the_rest_is_checked() {
+    let dir = TempDir::new().unwrap();
+    let config = dir.path().join("config.toml");
+    fs::write(&config, "[daemon]\nlog_level = \"loud\"").unwrap();
+    let checks = doctor::run(Some(&config), Some(dir.path()));
+    assert_eq!(find(&checks, "config").status, Status::Problem);
+    assert_eq!(find(&checks, "history").status, Status::Ok);
+}
+
+#[test]
+fn rate_limit_holds_back_repeated_messages() {
+    let mut limit = RateLimit::new(Duration::from_millis(50));
+    assert_eq!(limit.check("failed"), Some(0));
+    assert_eq!(limit.check("failed"), None);
+    assert_eq!(limit.check("failed"), None);
+    assert_eq!(limit.check("other"), Some(0));
+
+    thread::sleep(Duration::from_millis(60));
+    assert_eq!(limit.check("failed"), Some(2));
+    assert_eq!(limit.check("other"), Some(0));
+    assert_eq!(limit.check("failed"), None);
+}
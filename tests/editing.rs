@@ -0,0 +1,100 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend};
+use clipboard_manager_lib::manager::{ClipboardItemType, ClipboardManager};
+use clipboard_manager_lib::ClipmateError;
+use common::{blob_files, memory_manager, texts};
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn filled(dir: &TempDir, items: &[&str]) -> ClipboardManager<MemoryBackend> {
+    let mut manager = memory_manager(dir.path());
+    for item in items {
+        manager.save_text(item.to_string()).unwrap();
+    }
+    manager
+}
+
+#[test]
+fn delete_removes_listed_items_and_updates_counters() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = filled(&dir, &["a", "b", "c", "d"]);
+    manager.set_pinned(2, true).unwrap();
+
+    assert_eq!(manager.delete(&[2, 4, 2]).unwrap(), 2);
+    assert_eq!(texts(&manager), vec!["a", "c"]);
+    assert_eq!(manager.get_counter(ClipboardItemType::TEXT), 2);
+    assert_eq!(texts(&memory_manager(dir.path())), vec!["a", "c"]);
+}
+
+#[test]
+fn delete_with_a_missing_number_deletes_nothing() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = filled(&dir, &["a", "b"]);
+    assert!(matches!(
+        manager.delete(&[1, 5]),
+        Err(ClipmateError::ItemNotFound(5))
+    ));
+    assert_eq!(texts(&manager), vec!["a", "b"]);
+}
+
+#[test]
+fn delete_range_skips_pinned_items() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = filled(&dir, &["a", "b", "c", "d", "e"]);
+    manager.set_pinned(3, true).unwrap();
+
+    assert_eq!(manager.delete_range(2, 20).unwrap(), 3);
+    assert_eq!(texts(&manager), vec!["a", "c"]);
+    assert!(manager.delete_range(3, 2).is_err());
+    assert!(matches!(
+        manager.delete_range(9, 10),
+        Err(ClipmateError::ItemNotFound(9))
+    ));
+}
+
+#[test]
+fn clear_keeps_pinned_and_recent_items() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = filled(&dir, &["a", "b", "c"]);
+    manager.set_pinned(1, true).unwrap();
+
+    assert_eq!(manager.clear(Some(Duration::from_secs(3600))).unwrap(), 0);
+    assert_eq!(manager.clear(None).unwrap(), 2);
+    assert_eq!(texts(&manager), vec!["a"]);
+    assert_eq!(manager.get_counter(ClipboardItemType::TEXT), 1);
+}
+
+#[test]
+fn deleting_an_image_removes_its_file() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    manager
+        .backend_mut()
+        .write_image("image/png", &[9; 100])
+        .unwrap();
+    manager.update_image_content().unwrap();
+    let image = blob_files(dir.path()).pop().unwrap();
+    assert!(image.exists());
+
+    manager.delete(&[1]).unwrap();
+    assert!(!image.exists());
+    assert_eq!(manager.get_counter(ClipboardItemType::IMAGE), 0);
+}
+
+#[test]
+fn edit_replaces_text_items_only() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = filled(&dir, &["password123"]);
+    manager
+        .backend_mut()
+        .write_image("image/png", &[1; 100])
+        .unwrap();
+    manager.update_image_content().unwrap();
+
+    manager.edit_text(1, "redacted".to_string()).unwrap();
+    assert_eq!(texts(&memory_manager(dir.path()))[0], "redacted");
+    assert!(manager.edit_text(2, "text".to_string()).is_err());
+    assert!(manager.edit_text(1, String::new()).is_err());
+    assert_eq!(blob_files(dir.path()).len(), 1);
+}
@@ -0,0 +1,262 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend};
+use clipboard_manager_lib::config::Config;
+use clipboard_manager_lib::crypto::{self, Key, KeyParams};
+use clipboard_manager_lib::manager::ClipboardManager;
+use clipboard_manager_lib::store::{self, StoreFormat};
+use clipboard_manager_lib::ClipmateError;
+use common::{blob_files, config_for, texts};
+use std::fs;
+use std::path::Path;
+
+/// Config encrypting everything under `dir` with the keyfile `dir/keyfile`.
+fn encrypted_config(dir: &Path) -> Config {
+    let mut config = config_for(&dir.join("data"));
+    config.encryption.enabled = true;
+    config.encryption.keyfile = Some(dir.join("keyfile"));
+    config
+}
+
+fn encrypted_manager(dir: &Path) -> clipboard_manager_lib::Result<ClipboardManager<MemoryBackend>> {
+    ClipboardManager::with_backend(encrypted_config(dir), MemoryBackend::new())
+}
+
+#[test]
+fn history_and_images_are_not_stored_in_plaintext() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("keyfile"), "secret key material").unwrap();
+    let mut manager = encrypted_manager(dir.path()).unwrap();
+    manager
+        .save_text("hunter2 is my password".to_string())
+        .unwrap();
+    manager
+        .backend_mut()
+        .write_image("image/png", &[7; 100])
+        .unwrap();
+    manager.update_image_content().unwrap();
+
+    let history = fs::read(manager.paths().history_file()).unwrap();
+    assert!(crypto::is_encrypted(&history));
+    assert!(!String::from_utf8_lossy(&history).contains("hunter2"));
+    for path in blob_files(manager.paths().data_dir()) {
+        let image = fs::read(path).unwrap();
+        assert!(crypto::is_encrypted(&image));
+    }
+
+    manager.set_clipboard_text(2).unwrap();
+    assert_eq!(manager.backend().image("image/png"), Some(&[7; 100][..]));
+}
+
+#[test]
+fn reopening_with_the_same_keyfile_decrypts_the_history() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("keyfile"), "secret key material").unwrap();
+    encrypted_manager(dir.path())
+        .unwrap()
+        .save_text("kept".to_string())
+        .unwrap();
+
+    let manager = encrypted_manager(dir.path()).unwrap();
+    assert_eq!(texts(&manager), vec!["kept"]);
+}
+
+#[test]
+fn a_different_keyfile_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("keyfile"), "secret key material").unwrap();
+    encrypted_manager(dir.path())
+        .unwrap()
+        .save_text("kept".to_string())
+        .unwrap();
+
+    fs::write(dir.path().join("keyfile"), "other key material").unwrap();
+    assert!(matches!(
+        encrypted_manager(dir.path()),
+        Err(ClipmateError::Crypto(_))
+    ));
+}
+
+#[test]
+fn encrypted_history_without_a_key_is_locked() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("keyfile"), "secret key material").unwrap();
+    encrypted_manager(dir.path())
+        .unwrap()
+        .save_text("kept".to_string())
+        .unwrap();
+
+    let mut config = encrypted_config(dir.path());
+    config.encryption.enabled = false;
+    assert!(matches!(
+        ClipboardManager::with_backend(config, MemoryBackend::new()),
+        Err(ClipmateError::Locked)
+    ));
+}
+
+#[test]
+fn unencrypted_history_is_refused_until_rekeyed() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("keyfile"), "secret key material").unwrap();
+    let mut config = encrypted_config(dir.path());
+    config.encryption.enabled = false;
+    ClipboardManager::with_backend(config, MemoryBackend::new())
+        .unwrap()
+        .save_text("planted".to_string())
+        .unwrap();
+
+    assert!(matches!(
+        encrypted_manager(dir.path()),
+        Err(ClipmateError::Crypto(_))
+    ));
+    let mut manager =
+        ClipboardManager::for_rekey(encrypted_config(dir.path()), MemoryBackend::new()).unwrap();
+    let (key, params) = KeyParams::generate(b"secret key material").unwrap();
+    manager.rekey(key, &params).unwrap();
+    drop(manager);
+
+    let manager = encrypted_manager(dir.path()).unwrap();
+    assert_eq!(texts(&manager), vec!["planted"]);
+    assert!(crypto::is_encrypted(
+        &fs::read(manager.paths().history_file()).unwrap()
+    ));
+}
+
+#[test]
+fn unencrypted_journal_records_are_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("keyfile"), "secret key material").unwrap();
+    let manager = encrypted_manager(dir.path()).unwrap();
+    let paths = manager.paths().clone();
+    let key = crypto::load_key(&manager.config().encryption, &paths).unwrap();
+    drop(manager);
+    store::migrate(&paths, key, StoreFormat::Journal, &Default::default()).unwrap();
+    encrypted_manager(dir.path())
+        .unwrap()
+        .save_text("kept".to_string())
+        .unwrap();
+
+    let mut journal = fs::read_to_string(paths.journal_file()).unwrap();
+    journal.push_str(r#"{"add":{"id":2,"time":2,"item_type":"TEXT","data":"planted"}}"#);
+    journal.push('\n');
+    fs::write(paths.journal_file(), journal).unwrap();
+    assert!(matches!(
+        encrypted_manager(dir.path()),
+        Err(ClipmateError::Crypto(_))
+    ));
+}
+
+#[test]
+fn rekey_reencrypts_under_the_new_key() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("keyfile"), "secret key material").unwrap();
+    let mut manager = encrypted_manager(dir.path()).unwrap();
+    manager.save_text("kept".to_string()).unwrap();
+    manager
+        .backend_mut()
+        .write_image("image/png", &[3; 100])
+        .unwrap();
+    manager.update_image_content().unwrap();
+
+    let (key, params) = KeyParams::generate(b"new key material").unwrap();
+    manager.rekey(key, &params).unwrap();
+    drop(manager);
+
+    assert!(matches!(
+        encrypted_manager(dir.path()),
+        Err(ClipmateError::Crypto(_))
+    ));
+    fs::write(dir.path().join("keyfile"), "new key material").unwrap();
+    let mut manager = encrypted_manager(dir.path()).unwrap();
+    assert_eq!(manager.get_history().len(), 2);
+    manager.set_clipboard_text(2).unwrap();
+    assert_eq!(manager.backend().image("image/png"), Some(&[3; 100][..]));
+}
+
+#[test]
+fn failed_rekey_keeps_the_old_key() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("keyfile"), "secret key material").unwrap();
+    let mut manager = encrypted_manager(dir.path()).unwrap();
+    manager.save_text("kept".to_string()).unwrap();
+
+    // A directory in place of the history makes writing it fail.
+    let history = manager.paths().history_file();
+    let contents = fs::read(&history).unwrap();
+    fs::remove_file(&history).unwrap();
+    fs::create_dir(&history).unwrap();
+    fs::write(history.join("entry"), "").unwrap();
+    let (key, params) = KeyParams::generate(b"new key material").unwrap();
+    assert!(manager.rekey(key, &params).is_err());
+    drop(manager);
+
+    fs::remove_dir_all(&history).unwrap();
+    fs::write(&history, contents).unwrap();
+    let manager = encrypted_manager(dir.path()).unwrap();
+    assert_eq!(texts(&manager), vec!["kept"]);
+}
+
+#[test]
+fn tampered_data_fails_to_decrypt() {
+    let key = Key::derive(b"passphrase", b"0123456789abcdef").unwrap();
+    let mut data = crypto::encrypt(&key, b"plaintext").unwrap();
+    assert_eq!(crypto::decrypt(&key, &data).unwrap(), b"plaintext");
+
+    let last = data.len() - 1;
+    data[last] ^= 1;
+    assert!(matches!(
+        crypto::decrypt(&key, &data),
+        Err(ClipmateError::Crypto(_))
+    ));
+}
+
+#[test]
+fn sqlite_history_is_encrypted_too() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("keyfile"), "secret key material").unwrap();
+    let mut manager = encrypted_manager(dir.path()).unwrap();
+    manager
+        .save_text("hunter2 is my password".to_string())
+        .unwrap();
+    let paths = manager.paths().clone();
+    let key = crypto::load_key(&manager.config().encryption, &paths).unwrap();
+    drop(manager);
+    store::migrate(&paths, key, StoreFormat::Sqlite, &Default::default()).unwrap();
+
+    let mut manager = encrypted_manager(dir.path()).unwrap();
+    manager
+        .save_text("hunter3 is the next one".to_string())
+        .unwrap();
+    assert_eq!(
+        texts(&manager),
+        vec!["hunter2 is my password", "hunter3 is the next one"]
+    );
+    drop(manager);
+    let database = fs::read(paths.database_file()).unwrap();
+    assert!(!String::from_utf8_lossy(&database).contains("hunter"));
+}
+
+#[test]
+fn journal_records_are_encrypted_one_by_one() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("keyfile"), "secret key material").unwrap();
+    let manager = encrypted_manager(dir.path()).unwrap();
+    let paths = manager.paths().clone();
+    let key = crypto::load_key(&manager.config().encryption, &paths).unwrap();
+    drop(manager);
+    store::migrate(&paths, key, StoreFormat::Journal, &Default::default()).unwrap();
+
+    let mut manager = encrypted_manager(dir.path()).unwrap();
+    manager
+        .save_text("hunter2 is my password".to_string())
+        .unwrap();
+    manager.set_pinned(1, true).unwrap();
+    drop(manager);
+    let journal = fs::read_to_string(paths.journal_file()).unwrap();
+    assert_eq!(journal.lines().count(), 3);
+    assert!(!journal.contains("hunter"));
+
+    let manager = encrypted_manager(dir.path()).unwrap();
+    assert_eq!(texts(&manager), vec!["hunter2 is my password"]);
+    assert!(manager.get_item(1).unwrap().pinned);
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend, Selection, X11Backend};
+use clipboard_manager_lib::files::{self, GNOME_COPIED_FILES, URI_LIST};
+use clipboard_manager_lib::manager::{ClipboardItemType, ClipboardManager};
+use clipboard_manager_lib::owner::Offer;
+use common::{config_for, memory_manager, stub_server};
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// Puts `uris` on the clipboard the way Nautilus does.
+fn copy_files(backend: &mut MemoryBackend, uris: &[&str]) {
+    backend
+        .write_text(&uris.join("\n").replace("file://", ""))
+        .unwrap();
+    backend.offer(
+        GNOME_COPIED_FILES,
+        format!("copy\n{}", uris.join("\n")).as_bytes(),
+    );
+    backend.offer(URI_LIST, format!("{}\r\n", uris.join("\r\n")).as_bytes());
+}
+
+#[test]
+fn parses_file_uris() {
+    assert_eq!(
+        files::parse_uri_list("# comment\r\nfile:///tmp/a%20b.txt\r\nfile://host/c\r\n"),
+        Some(vec![PathBuf::from("/tmp/a b.txt"), PathBuf::from("/c")])
+    );
+    assert_eq!(files::parse_uri_list("https://example.com/\r\n"), None);
+    assert_eq!(files::parse_uri_list(""), None);
+    assert_eq!(
+        files::parse_gnome_copied_files("cut\nfile:///tmp/x"),
+        Some(vec![PathBuf::from("/tmp/x")])
+    );
+
+    let paths = vec![PathBuf::from("/tmp/a b#1.txt"), PathBuf::from("/tmp/ü")];
+    assert_eq!(
+        files::parse_uri_list(&files::to_uri_list(&paths)),
+        Some(paths)
+    );
+}
+
+#[test]
+fn file_names_with_line_breaks_are_refused() {
+    assert_eq!(files::parse_uri_list("file:///tmp/a%0Ab\r\n"), None);
+    assert_eq!(
+        files::parse_gnome_copied_files("copy\nfile:///tmp/a%0Db"),
+        None
+    );
+
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    let newline = PathBuf::from("/tmp/a\nb");
+    assert!(manager.save_files(vec![newline]).is_err());
+    assert!(manager.get_history().is_empty());
+}
+
+#[test]
+fn copied_files_are_recorded_as_files() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    copy_files(
+        manager.backend_mut(),
+        &[
+            "file:///home/me/report.pdf",
+            "file:///home/me/My%20Photo.jpg",
+        ],
+    );
+    manager.update_clipboard_content().unwrap();
+    manager.update_clipboard_content().unwrap();
+
+    assert_eq!(manager.get_history().len(), 1);
+    let item = manager.get_item(1).unwrap();
+    assert_eq!(item.item_type, ClipboardItemType::FILES);
+    assert_eq!(files::summary(&item.paths()), "report.pdf, My Photo.jpg");
+    assert_eq!(manager.get_counter(ClipboardItemType::FILES), 1);
+}
+
+#[test]
+fn links_are_not_files() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    manager
+        .backend_mut()
+        .write_text("https://example.com/")
+        .unwrap();
+    manager
+        .backend_mut()
+        .offer(URI_LIST, b"https://example.com/\r\n");
+    manager.update_clipboard_content().unwrap();
+
+    let item = manager.get_item(1).unwrap();
+    assert_eq!(item.item_type, ClipboardItemType::TEXT);
+    assert_eq!(item.formats[0].mime_type, URI_LIST);
+}
+
+#[test]
+fn restoring_files_offers_file_manager_types() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    copy_files(manager.backend_mut(), &["file:///tmp/a%20b"]);
+    manager.update_clipboard_content().unwrap();
+    manager.backend_mut().write_text("other").unwrap();
+
+    manager.set_clipboard_text(1).unwrap();
+    let backend = manager.backend();
+    assert_eq!(
+        backend.contents(URI_LIST),
+        Some(&b"file:///tmp/a%20b\r\n"[..])
+    );
+    assert_eq!(
+        backend.contents(GNOME_COPIED_FILES),
+        Some(&b"copy\nfile:///tmp/a%20b"[..])
+    );
+    assert_eq!(
+        backend.contents("text/plain;charset=utf-8"),
+        Some(&b"/tmp/a b"[..])
+    );
+}
+
+#[test]
+fn restoring_files_on_x11_offers_every_type() {
+    let dir = TempDir::new().unwrap();
+    let server = stub_server(dir.path(), "echo serving");
+    let backend = X11Backend::for_selection(Selection::Clipboard).serve_with(server);
+    let mut manager = ClipboardManager::with_backend(config_for(dir.path()), backend).unwrap();
+    manager.save_files(vec![PathBuf::from("/tmp/a b")]).unwrap();
+
+    manager.set_clipboard_text(1).unwrap();
+    let offer = fs::read(dir.path().join("offer")).unwrap();
+    let offer = Offer::read_from(&mut &offer[..]).unwrap();
+    assert_eq!(offer.get(URI_LIST), Some(&b"file:///tmp/a%20b\r\n"[..]));
+    assert_eq!(
+        offer.get(GNOME_COPIED_FILES),
+        Some(&b"copy\nfile:///tmp/a%20b"[..])
+    );
+    assert_eq!(offer.get("UTF8_STRING"), Some(&b"/tmp/a b"[..]));
+}
+
+#[test]
+fn snapshots_bring_back_deleted_files() {
+    let dir = TempDir::new().unwrap();
+    let source = dir.path().join("notes.txt");
+    fs::write(&source, "remember").unwrap();
+    let mut config = config_for(&dir.path().join("data"));
+    config.capture.snapshot_files = true;
+    let mut manager = ClipboardManager::with_backend(config, MemoryBackend::new()).unwrap();
+    manager.save_files(vec![source.clone()]).unwrap();
+    fs::remove_file(&source).unwrap();
+
+    manager.set_clipboard_text(1).unwrap();
+    let restored = manager.paths().restored_dir().join("notes.txt");
+    assert_eq!(fs::read_to_string(&restored).unwrap(), "remember");
+    assert_eq!(
+        manager.backend().contents(URI_LIST),
+        Some(files::to_uri_list(&[restored]).as_bytes())
+    );
+
+    manager.delete(&[1]).unwrap();
+    assert!(manager.fsck().unwrap().is_ok());
+}
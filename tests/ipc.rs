@@ -0,0 +1,211 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend};
+use clipboard_manager_lib::daemon::Daemon;
+use clipboard_manager_lib::ipc::{self, Client, Request, Response};
+use clipboard_manager_lib::manager::ClipboardManager;
+use clipboard_manager_lib::search::SearchQuery;
+use clipboard_manager_lib::ClipmateError;
+use common::memory_manager;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use tempfile::TempDir;
+
+fn manager_with(dir: &Path, texts: &[&str]) -> ClipboardManager<MemoryBackend> {
+    let mut manager = memory_manager(dir);
+    for text in texts {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    manager
+}
+
+fn numbers(response: Response) -> Vec<usize> {
+    match response {
+        Response::Items { items } => items.iter().map(|item| item.number).collect(),
+        response => panic!("unexpected response {:?}", response),
+    }
+}
+
+/// A daemon over `texts`, answering commands at `dir/clipmate.sock`.
+fn serving(dir: &Path, texts: &[&str]) -> (Sender<()>, Daemon<MemoryBackend>) {
+    let (events, watcher) = clipboard_manager_lib::watch::ChannelWatcher::new("fake");
+    let daemon = Daemon::new(manager_with(dir, texts), Box::new(watcher));
+    let listener = ipc::bind(&dir.join("clipmate.sock")).unwrap();
+    ipc::serve(listener, daemon.shared_manager(), daemon.subscribers());
+    (events, daemon)
+}
+
+fn connect(dir: &Path) -> Client {
+    Client::connect(&dir.join("clipmate.sock"))
+        .unwrap()
+        .unwrap()
+}
+
+#[test]
+fn requests_are_handled_like_the_cli_commands() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = manager_with(dir.path(), &["one", "two", "three"]);
+
+    let pin = Request::Pin {
+        item: 2,
+        pinned: true,
+    };
+    assert!(matches!(
+        ipc::handle(&mut manager, pin),
+        Response::Done { count: 1 }
+    ));
+    let pinned = ipc::handle(&mut manager, Request::List { pinned: true });
+    assert_eq!(numbers(pinned), vec![2]);
+
+    let query = SearchQuery {
+        pattern: "t".to_string(),
+        ..Default::default()
+    };
+    let found = ipc::handle(&mut manager, Request::Search { query });
+    assert_eq!(numbers(found), vec![2, 3]);
+
+    match ipc::handle(&mut manager, Request::Get { item: 3 }) {
+        Response::Item { item, text } => {
+            assert_eq!(item.number, 3);
+            assert_eq!(text.as_deref(), Some("three"));
+        }
+        response => panic!("unexpected response {:?}", response),
+    }
+
+    let delete = Request::Delete {
+        items: Vec::new(),
+        range: Some((1, 3)),
+    };
+    assert!(matches!(
+        ipc::handle(&mut manager, delete),
+        Response::Done { count: 2 }
+    ));
+    match ipc::handle(&mut manager, Request::Stats) {
+        Response::Stats { stats } => {
+            assert_eq!((stats.items, stats.pinned, stats.text), (1, 1, 1));
+            assert_eq!(stats.bytes, 3);
+        }
+        response => panic!("unexpected response {:?}", response),
+    }
+}
+
+#[test]
+fn errors_come_back_as_the_same_error() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = manager_with(dir.path(), &["one"]);
+    let response = ipc::handle(&mut manager, Request::Get { item: 7 });
+    assert!(matches!(
+        response.into_result(),
+        Err(ClipmateError::ItemNotFound(7))
+    ));
+}
+
+#[test]
+fn the_daemon_answers_over_its_socket() {
+    let dir = TempDir::new().unwrap();
+    let (_events, daemon) = serving(dir.path(), &["one", "two"]);
+    let mut client = connect(dir.path());
+
+    let listed = client.request(Request::List { pinned: false }).unwrap();
+    assert_eq!(numbers(listed), vec![1, 2]);
+
+    client
+        .request(Request::Set {
+            item: 1,
+            selection: Default::default(),
+        })
+        .unwrap();
+    assert_eq!(daemon.manager().backend().text(), Some("one"));
+
+    assert!(matches!(
+        client.request(Request::Get { item: 9 }),
+        Err(ClipmateError::ItemNotFound(9))
+    ));
+}
+
+#[test]
+fn subscribers_get_every_recorded_item() {
+    let dir = TempDir::new().unwrap();
+    let (events, mut daemon) = serving(dir.path(), &["one"]);
+    let mut client = connect(dir.path());
+    assert!(matches!(
+        client.request(Request::Subscribe).unwrap(),
+        Response::Subscribed
+    ));
+
+    daemon.manager().backend_mut().write_text("two").unwrap();
+    events.send(()).unwrap();
+    daemon.step();
+    match client.receive().unwrap() {
+        Response::Recorded { item } => {
+            assert_eq!(item.number, 2);
+            assert_eq!(item.item.data, "two");
+        }
+        response => panic!("unexpected response {:?}", response),
+    }
+}
+
+#[test]
+fn other_protocol_versions_are_refused() {
+    let dir = TempDir::new().unwrap();
+    let (_events, _daemon) = serving(dir.path(), &[]);
+    let mut stream = UnixStream::connect(dir.path().join("clipmate.sock")).unwrap();
+    stream
+        .write_all(b"{\"version\":99,\"request\":\"stats\"}\n")
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).unwrap();
+    assert!(line.contains("\"unsupported_version\""), "{}", line);
+}
+
+#[test]
+fn oversized_requests_are_refused() {
+    let dir = TempDir::new().unwrap();
+    let (_events, _daemon) = serving(dir.path(), &[]);
+    let mut stream = UnixStream::connect(dir.path().join("clipmate.sock")).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let request = format!(
+        "{{\"version\":1,\"request\":{{\"search\":{{\"query\":\"{}\"}}}}}}\n",
+        "a".repeat(100 * 1024)
+    );
+    // The daemon may hang up before it read everything.
+    let _ = stream.write_all(request.as_bytes());
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert!(line.contains("\"invalid_input\""), "{}", line);
+    assert!(line.contains("request longer than"), "{}", line);
+    // Then it hangs up, resetting the connection over the unread rest.
+    line.clear();
+    assert!(!matches!(reader.read_line(&mut line), Ok(read) if read > 0));
+}
+
+#[test]
+fn socket_is_private_from_the_start() {
+    let dir = TempDir::new().unwrap();
+    let socket = dir.path().join("run").join("clipmate.sock");
+    let _listener = ipc::bind(&socket).unwrap();
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(&socket), 0o600);
+    assert_eq!(mode(socket.parent().unwrap()), 0o700);
+    let names: Vec<_> = fs::read_dir(socket.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, vec!["clipmate.sock"]);
+}
+
+#[test]
+fn only_one_daemon_listens_and_stale_sockets_are_replaced() {
+    let dir = TempDir::new().unwrap();
+    let socket = dir.path().join("clipmate.sock");
+    assert!(Client::connect(&socket).unwrap().is_none());
+
+    drop(UnixListener::bind(&socket).unwrap());
+    assert!(Client::connect(&socket).unwrap().is_none());
+    let _listener = ipc::bind(&socket).unwrap();
+    assert!(matches!(ipc::bind(&socket), Err(ClipmateError::Daemon(_))));
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use clipboard_manager_lib::backend::MemoryBackend;
+use clipboard_manager_lib::config::StorageConfig;
+use clipboard_manager_lib::manager::{ClipboardItemType, ClipboardManager};
+use clipboard_manager_lib::paths::DataPaths;
+use clipboard_manager_lib::store::{self, StoreFormat};
+use clipboard_manager_lib::ClipmateError;
+use common::{config_for, memory_manager, texts};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use tempfile::TempDir;
+
+/// Manager over a journal history, migrated from an empty one.
+fn journal_manager(dir: &TempDir) -> ClipboardManager<MemoryBackend> {
+    let paths = DataPaths::new(dir.path());
+    store::migrate(&paths, None, StoreFormat::Journal, &Default::default()).unwrap();
+    memory_manager(dir.path())
+}
+
+fn journal_lines(dir: &TempDir) -> Vec<String> {
+    fs::read_to_string(DataPaths::new(dir.path()).journal_file())
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn changes_are_appended_and_replayed() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = journal_manager(&dir);
+    for text in ["one", "two", "three"] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    let before = journal_lines(&dir);
+    manager.set_pinned(1, true).unwrap();
+
+    let after = journal_lines(&dir);
+    assert_eq!(after[..before.len()], before[..]);
+    assert_eq!(after.len(), before.len() + 1);
+    assert!(after[after.len() - 1].starts_with(r#"{"update":"#));
+
+    // Edits and deletions rewrite the journal, so that it keeps no copy of
+    // what they dropped.
+    manager.edit_text(2, "edited".to_string()).unwrap();
+    manager.delete(&[3]).unwrap();
+    let lines = journal_lines(&dir);
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].starts_with(r#"{"snapshot":"#));
+
+    let manager = memory_manager(dir.path());
+    assert_eq!(texts(&manager), vec!["one", "edited"]);
+    assert!(manager.get_item(1).unwrap().pinned);
+    assert_eq!(manager.get_counter(ClipboardItemType::TEXT), 2);
+}
+
+#[test]
+fn torn_final_line_is_dropped_and_overwritten() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = journal_manager(&dir);
+    manager.save_text("one".to_string()).unwrap();
+    manager.save_text("two".to_string()).unwrap();
+    drop(manager);
+
+    // Simulate a crash half way through appending the record for "two".
+    let path = DataPaths::new(dir.path()).journal_file();
+    let contents = fs::read(&path).unwrap();
+    fs::write(&path, &contents[..contents.len() - 10]).unwrap();
+
+    let mut manager = memory_manager(dir.path());
+    assert_eq!(texts(&manager), vec!["one"]);
+    manager.save_text("three".to_string()).unwrap();
+    drop(manager);
+
+    assert!(journal_lines(&dir)
+        .iter()
+        .all(|line| line.starts_with('{') && line.ends_with('}')));
+    assert_eq!(texts(&memory_manager(dir.path())), vec!["one", "three"]);
+}
+
+#[test]
+fn complete_record_missing_its_newline_is_dropped() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = journal_manager(&dir);
+    manager.save_text("one".to_string()).unwrap();
+    manager.save_text("two".to_string()).unwrap();
+    drop(manager);
+
+    let path = DataPaths::new(dir.path()).journal_file();
+    let contents = fs::read(&path).unwrap();
+    fs::write(&path, &contents[..contents.len() - 1]).unwrap();
+
+    assert_eq!(texts(&memory_manager(dir.path())), vec!["one"]);
+}
+
+#[test]
+fn corrupt_record_before_the_end_is_an_error() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = journal_manager(&dir);
+    manager.save_text("one".to_string()).unwrap();
+    drop(manager);
+
+    let path = DataPaths::new(dir.path()).journal_file();
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"{\"add\":\n").unwrap();
+    file.write_all(b"{\"delete\":[]}\n").unwrap();
+    drop(file);
+
+    let result = ClipboardManager::with_backend(config_for(dir.path()), MemoryBackend::new());
+    assert!(matches!(result, Err(ClipmateError::Parse(_))));
+}
+
+#[test]
+fn compaction_rewrites_the_journal_as_a_snapshot() {
+    let dir = TempDir::new().unwrap();
+    let paths = DataPaths::new(dir.path());
+    let storage = StorageConfig {
+        compact_after: 3,
+        ..StorageConfig::default()
+    };
+    store::migrate(&paths, None, StoreFormat::Journal, &storage).unwrap();
+    let mut config = config_for(dir.path());
+    config.storage = storage;
+    config.storage.data_dir = Some(dir.path().to_path_buf());
+    let mut manager = ClipboardManager::with_backend(config, MemoryBackend::new()).unwrap();
+
+    for text in ["one", "two", "three"] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    assert!(!manager.compact_history().unwrap());
+    manager.save_text("four".to_string()).unwrap();
+    manager.set_pinned(1, true).unwrap();
+    assert!(manager.compact_history().unwrap());
+
+    let lines = journal_lines(&dir);
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].starts_with(r#"{"snapshot":"#));
+    manager.save_text("five".to_string()).unwrap();
+    drop(manager);
+
+    assert_eq!(journal_lines(&dir).len(), 2);
+    let manager = memory_manager(dir.path());
+    assert_eq!(texts(&manager), vec!["one", "two", "three", "four", "five"]);
+    assert!(manager.get_item(1).unwrap().pinned);
+    assert_eq!(manager.get_counter(ClipboardItemType::TEXT), 5);
+}
+
+#[test]
+fn migration_to_journal_and_back_is_lossless() {
+    let dir = TempDir::new().unwrap();
+    let paths = DataPaths::new(dir.path());
+    let original = r#"{"items":[{"id":1,"time":1,"item_type":"TEXT","data":"one","pinned":true},{"id":2,"time":2,"item_type":"IMAGE","data":"abc.png","pinned":false}],"image_counter":4,"text_counter":7}"#;
+    fs::write(paths.history_file(), original).unwrap();
+
+    let storage = StorageConfig::default();
+    assert!(store::migrate(&paths, None, StoreFormat::Journal, &storage).unwrap());
+    assert_eq!(StoreFormat::detect(&paths), StoreFormat::Journal);
+    assert_eq!(texts(&memory_manager(dir.path())), vec!["one", "abc.png"]);
+
+    assert!(store::migrate(&paths, None, StoreFormat::Json, &storage).unwrap());
+    assert_eq!(fs::read_to_string(paths.history_file()).unwrap(), original);
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend, Selection};
+use clipboard_manager_lib::lock::{self, PidFile};
+use clipboard_manager_lib::manager::ClipboardManager;
+use clipboard_manager_lib::paths::DataPaths;
+use clipboard_manager_lib::service;
+use common::{config_for, memory_manager, texts};
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+use tempfile::TempDir;
+
+#[test]
+fn flushing_records_debounced_text() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config_for(dir.path());
+    config.capture.selections = vec![Selection::Clipboard, Selection::Primary];
+    config.capture.debounce_ms = 60_000;
+    let mut manager = ClipboardManager::with_backend(config, MemoryBackend::new()).unwrap();
+    let backend = manager.backend_mut();
+    backend.select(Selection::Primary).unwrap();
+    backend.write_text("selected").unwrap();
+    backend.select(Selection::Clipboard).unwrap();
+    backend.write_text("copied").unwrap();
+
+    manager.update_clipboard_content().unwrap();
+    assert_eq!(texts(&manager), vec!["copied"]);
+    manager.flush().unwrap();
+    assert_eq!(
+        texts(&memory_manager(dir.path())),
+        vec!["copied", "selected"]
+    );
+    assert_eq!(manager.get_item(2).unwrap().selection, Selection::Primary);
+    assert!(manager.debounce_deadline().is_none());
+
+    // Already recorded, so reading it again adds nothing.
+    manager.update_clipboard_content().unwrap();
+    manager.flush().unwrap();
+    assert_eq!(texts(&manager), vec!["copied", "selected"]);
+}
+
+#[test]
+fn reloaded_config_applies_to_new_copies() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    let mut config = config_for(dir.path());
+    config.capture.ignore = vec!["^secret".to_string()];
+    config.storage.data_dir = Some(dir.path().join("elsewhere"));
+    manager.set_config(config).unwrap();
+
+    manager.save_text("secret stuff".to_string()).unwrap();
+    manager.save_text("kept".to_string()).unwrap();
+    assert_eq!(texts(&manager), vec!["kept"]);
+    assert_eq!(
+        manager.config().data_paths().unwrap().data_dir(),
+        dir.path()
+    );
+
+    let mut invalid = config_for(dir.path());
+    invalid.capture.ignore = vec!["(".to_string()];
+    assert!(manager.set_config(invalid).is_err());
+    manager.save_text("secret again".to_string()).unwrap();
+    assert_eq!(texts(&manager), vec!["kept"]);
+}
+
+#[test]
+fn reloaded_limits_apply_right_away() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    for text in ["one", "two", "three"] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    let mut config = config_for(dir.path());
+    config.history.max_items = Some(2);
+    manager.set_config(config).unwrap();
+    assert_eq!(texts(&manager), vec!["two", "three"]);
+    assert_eq!(texts(&memory_manager(dir.path())), vec!["two", "three"]);
+}
+
+#[test]
+fn running_daemon_is_found_through_its_pid_file() {
+    let dir = TempDir::new().unwrap();
+    let pid_file = DataPaths::new(dir.path()).pid_file();
+    assert_eq!(lock::running_daemon(&pid_file).unwrap(), None);
+    assert_eq!(service::stop(&pid_file).unwrap(), None);
+
+    let running = PidFile::acquire(&pid_file).unwrap();
+    assert_eq!(
+        lock::running_daemon(&pid_file).unwrap(),
+        Some(std::process::id())
+    );
+    drop(running);
+    assert_eq!(lock::running_daemon(&pid_file).unwrap(), None);
+    assert!(!pid_file.exists());
+}
+
+#[test]
+fn exiting_daemon_leaves_a_newer_pid_file_alone() {
+    let dir = TempDir::new().unwrap();
+    let pid_file = DataPaths::new(dir.path()).pid_file();
+    let running = PidFile::acquire(&pid_file).unwrap();
+
+    // Another daemon replaced the file, as if it started after this one
+    // unlinked its own.
+    fs::remove_file(&pid_file).unwrap();
+    fs::write(&pid_file, "1\n").unwrap();
+    drop(running);
+    assert_eq!(fs::read_to_string(&pid_file).unwrap(), "1\n");
+
+    let running = PidFile::acquire(&pid_file).unwrap();
+    assert_eq!(
+        lock::running_daemon(&pid_file).unwrap(),
+        Some(std::process::id())
+    );
+    drop(running);
+}
+
+#[test]
+fn daemon_log_is_private() {
+    let dir = TempDir::new().unwrap();
+    let log = dir.path().join("state").join("daemon.log");
+    fs::create_dir_all(log.parent().unwrap()).unwrap();
+    fs::write(&log, "").unwrap();
+    fs::set_permissions(&log, fs::Permissions::from_mode(0o644)).unwrap();
+
+    let mut command = Command::new("sh");
+    command.args(["-c", "echo copied; exit 1"]);
+    let socket = dir.path().join("clipmate.sock");
+    assert!(service::start(command, &socket, &log).is_err());
+    assert_eq!(fs::read_to_string(&log).unwrap(), "copied\n");
+    let mode = fs::metadata(&log).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn unit_runs_the_daemon_in_the_foreground() {
+    let args: Vec<OsString> = ["--data-dir", "/home/me/my clips", "daemon", "--foreground"]
+        .iter()
+        .map(OsString::from)
+        .collect();
+    let unit = service::unit(Path::new("/usr/bin/clipmate"), &args);
+    assert!(unit.contains(
+        "\nExecStart=/usr/bin/clipmate --data-dir \"/home/me/my clips\" daemon --foreground\n"
+    ));
+    assert!(unit.contains("\nExecReload=/bin/kill -HUP $MAINPID\n"));
+
+    let args = [OsString::from("100%$HOME")];
+    let unit = service::unit(Path::new("/usr/bin/clipmate"), &args);
+    assert!(unit.contains("ExecStart=/usr/bin/clipmate 100%%$$HOME\n"));
+}
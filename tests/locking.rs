@@ -0,0 +1,110 @@
+mod common;
+
+use clipboard_manager_lib::lock::{HistoryLock, PidFile};
+use clipboard_manager_lib::paths::DataPaths;
+use clipboard_manager_lib::store::{self, StoreFormat};
+use clipboard_manager_lib::ClipmateError;
+use common::{config_for, memory_manager, texts};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+#[test]
+fn writes_keep_changes_made_by_another_process() {
+    let dir = TempDir::new().unwrap();
+    let mut daemon = memory_manager(dir.path());
+    daemon.save_text("one".to_string()).unwrap();
+    daemon.save_text("two".to_string()).unwrap();
+
+    let mut cli = memory_manager(dir.path());
+    cli.delete(&[1]).unwrap();
+
+    // The daemon still has "one" in memory, but must not write it back.
+    daemon.save_text("three".to_string()).unwrap();
+    assert_eq!(texts(&daemon), vec!["two", "three"]);
+    assert_eq!(texts(&memory_manager(dir.path())), vec!["two", "three"]);
+}
+
+#[test]
+fn refresh_loads_the_history_only_once_it_changed() {
+    let dir = TempDir::new().unwrap();
+    let mut reader = memory_manager(dir.path());
+    assert!(!reader.refresh().unwrap());
+
+    memory_manager(dir.path())
+        .save_text("copied".to_string())
+        .unwrap();
+    assert!(reader.refresh().unwrap());
+    assert_eq!(texts(&reader), vec!["copied"]);
+    assert!(!reader.refresh().unwrap());
+}
+
+#[test]
+fn ids_stay_unique_across_processes() {
+    let dir = TempDir::new().unwrap();
+    let mut first = memory_manager(dir.path());
+    let mut second = memory_manager(dir.path());
+    first.save_text("a".to_string()).unwrap();
+    second.save_text("b".to_string()).unwrap();
+    first.save_text("c".to_string()).unwrap();
+
+    let history = memory_manager(dir.path());
+    let mut ids: Vec<u64> = history.get_history().iter().map(|item| item.id).collect();
+    assert_eq!(texts(&history), vec!["a", "b", "c"]);
+    ids.dedup();
+    assert_eq!(ids.len(), 3);
+}
+
+#[test]
+fn writers_wait_for_the_lock() {
+    let dir = TempDir::new().unwrap();
+    let paths = DataPaths::new(dir.path());
+    let lock = HistoryLock::open(&paths).unwrap();
+    let guard = lock.exclusive().unwrap();
+
+    let data_dir = dir.path().to_path_buf();
+    let writer = thread::spawn(move || {
+        memory_manager(&data_dir)
+            .save_text("waited".to_string())
+            .unwrap();
+    });
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(lock.generation().unwrap(), 0);
+
+    drop(guard);
+    writer.join().unwrap();
+    assert_eq!(lock.generation().unwrap(), 1);
+}
+
+#[test]
+fn migration_is_seen_by_running_processes() {
+    let dir = TempDir::new().unwrap();
+    let mut daemon = memory_manager(dir.path());
+    daemon.save_text("before".to_string()).unwrap();
+
+    let config = config_for(dir.path());
+    let paths = config.data_paths().unwrap();
+    assert!(store::migrate(&paths, None, StoreFormat::Sqlite, &config.storage).unwrap());
+
+    daemon.save_text("after".to_string()).unwrap();
+    assert_eq!(StoreFormat::detect(&paths), StoreFormat::Sqlite);
+    assert_eq!(texts(&memory_manager(dir.path())), vec!["before", "after"]);
+}
+
+#[test]
+fn a_second_daemon_is_refused() {
+    let dir = TempDir::new().unwrap();
+    let pid_file = DataPaths::new(dir.path()).pid_file();
+
+    let running = PidFile::acquire(&pid_file).unwrap();
+    match PidFile::acquire(&pid_file) {
+        Err(ClipmateError::Daemon(msg)) => {
+            assert!(msg.contains(&std::process::id().to_string()), "{}", msg)
+        }
+        other => panic!("expected a daemon error, got {:?}", other.err()),
+    }
+
+    drop(running);
+    assert!(!pid_file.exists());
+    PidFile::acquire(&pid_file).unwrap();
+}
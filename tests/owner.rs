@@ -0,0 +1,141 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend, Selection, X11Backend};
+use clipboard_manager_lib::daemon::Daemon;
+use clipboard_manager_lib::manager::ClipboardManager;
+use clipboard_manager_lib::owner::{Offer, Owner};
+use clipboard_manager_lib::watch::ChannelWatcher;
+use common::{config_for, memory_manager, stub_server};
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use std::sync::mpsc::Sender;
+use tempfile::TempDir;
+
+/// Records what it is asked to serve.
+struct FakeOwner(Rc<RefCell<Vec<Offer>>>);
+
+impl Owner for FakeOwner {
+    fn own(&mut self, offer: Offer) -> io::Result<()> {
+        self.0.borrow_mut().push(offer);
+        Ok(())
+    }
+}
+
+fn owning_daemon(
+    manager: ClipboardManager<MemoryBackend>,
+) -> (Sender<()>, Daemon<MemoryBackend>, Rc<RefCell<Vec<Offer>>>) {
+    let (events, watcher) = ChannelWatcher::new("fake");
+    let mut daemon = Daemon::new(manager, Box::new(watcher));
+    let offers = Rc::new(RefCell::new(Vec::new()));
+    daemon.set_owner(Box::new(FakeOwner(offers.clone())));
+    (events, daemon, offers)
+}
+
+#[test]
+fn text_is_offered_under_every_text_target() {
+    let offer = Offer::new(
+        Some("hello".to_string()),
+        vec![("text/html".to_string(), b"<b>hello</b>".to_vec())],
+    );
+    for target in ["UTF8_STRING", "STRING", "TEXT", "text/plain;charset=utf-8"] {
+        assert_eq!(offer.get(target), Some(&b"hello"[..]), "{}", target);
+    }
+    assert_eq!(offer.get("text/html"), Some(&b"<b>hello</b>"[..]));
+    assert_eq!(offer.get("image/png"), None);
+    assert!(offer.targets().contains(&"UTF8_STRING"));
+    assert!(offer.targets().contains(&"text/html"));
+}
+
+#[test]
+fn images_are_offered_without_text_targets() {
+    let offer = Offer::new(None, vec![("image/png".to_string(), vec![1, 2, 3])]);
+    assert_eq!(offer.targets(), vec!["image/png"]);
+    assert_eq!(offer.get("UTF8_STRING"), None);
+}
+
+#[test]
+fn files_are_offered_as_uris_and_as_text() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    let file = dir.path().join("notes.txt");
+    manager.save_files(vec![file.clone()]).unwrap();
+
+    let offer = manager.offer(1).unwrap();
+    let uri = format!("file://{}\r\n", file.display());
+    assert_eq!(offer.get("text/uri-list"), Some(uri.as_bytes()));
+    let path = file.to_string_lossy();
+    assert_eq!(offer.get("UTF8_STRING"), Some(path.as_bytes()));
+}
+
+#[test]
+fn the_daemon_takes_over_what_is_copied_to_the_clipboard() {
+    let dir = TempDir::new().unwrap();
+    let (events, mut daemon, offers) = owning_daemon(memory_manager(dir.path()));
+    {
+        let mut manager = daemon.manager();
+        let backend = manager.backend_mut();
+        backend.write_text("copied").unwrap();
+        backend.offer("text/html", b"<i>copied</i>");
+    }
+    events.send(()).unwrap();
+    assert!(daemon.step().is_empty());
+
+    let offers = offers.borrow();
+    assert_eq!(offers.len(), 1);
+    assert_eq!(offers[0].get("UTF8_STRING"), Some(&b"copied"[..]));
+    assert_eq!(offers[0].get("text/html"), Some(&b"<i>copied</i>"[..]));
+}
+
+#[test]
+fn the_daemon_leaves_other_selections_and_old_contents_alone() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config_for(dir.path());
+    config.capture.selections = vec![Selection::Clipboard, Selection::Primary];
+    let manager = ClipboardManager::with_backend(config, MemoryBackend::new()).unwrap();
+    let (events, mut daemon, offers) = owning_daemon(manager);
+    {
+        let mut manager = daemon.manager();
+        let backend = manager.backend_mut();
+        backend.select(Selection::Primary).unwrap();
+        backend.write_text("selected").unwrap();
+        backend.select(Selection::Clipboard).unwrap();
+    }
+    events.send(()).unwrap();
+    daemon.step();
+    assert_eq!(daemon.manager().get_history().len(), 1);
+    assert!(offers.borrow().is_empty());
+
+    // Reading the clipboard again finds nothing new.
+    events.send(()).unwrap();
+    daemon.step();
+    assert!(offers.borrow().is_empty());
+}
+
+#[test]
+fn x11_backend_serves_every_format_from_a_helper() {
+    let dir = TempDir::new().unwrap();
+    let server = stub_server(dir.path(), "echo serving");
+    let mut backend = X11Backend::for_selection(Selection::Clipboard).serve_with(server);
+    backend.select(Selection::Primary).unwrap();
+    let formats = vec![("text/html".to_string(), b"<b>hello</b>".to_vec())];
+    backend.write_formats(Some("hello"), &formats).unwrap();
+
+    let args = fs::read_to_string(dir.path().join("args")).unwrap();
+    assert_eq!(args, "serve-selection --selection primary\n");
+    let offered = Offer::read_from(&mut &fs::read(dir.path().join("offer")).unwrap()[..]);
+    let offered = offered.unwrap();
+    assert_eq!(offered, Offer::new(Some("hello".to_string()), formats));
+    assert!(offered.targets().contains(&"UTF8_STRING"));
+    assert!(offered.targets().contains(&"text/html"));
+}
+
+#[test]
+fn x11_backend_fails_when_the_helper_cannot_serve() {
+    let dir = TempDir::new().unwrap();
+    let server = stub_server(dir.path(), "exit 1");
+    let mut backend = X11Backend::for_selection(Selection::Clipboard).serve_with(server);
+    let formats = [("text/html".to_string(), b"<b>hello</b>".to_vec())];
+    assert!(backend.write_formats(Some("hello"), &formats).is_err());
+}
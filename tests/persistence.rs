@@ -0,0 +1,264 @@
+mod common;
+
+use clipboard_manager_lib::backend::MemoryBackend;
+use clipboard_manager_lib::config::StorageConfig;
+use clipboard_manager_lib::crypto::{self, KeyParams};
+use clipboard_manager_lib::manager::{self, ClipboardManager};
+use clipboard_manager_lib::paths::DataPaths;
+use clipboard_manager_lib::store::{self, StoreFormat};
+use clipboard_manager_lib::ClipmateError;
+use common::{config_for, texts};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn history_path(dir: &TempDir) -> PathBuf {
+    DataPaths::new(dir.path()).history_file()
+}
+
+fn open(path: &Path) -> clipboard_manager_lib::Result<ClipboardManager<MemoryBackend>> {
+    ClipboardManager::with_backend(config_for(path.parent().unwrap()), MemoryBackend::new())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}", path.display(), suffix))
+}
+
+#[test]
+fn saves_are_readable_and_keep_a_backup() {
+    let dir = TempDir::new().unwrap();
+    let path = history_path(&dir);
+
+    let mut manager = open(&path).unwrap();
+    manager.save_text("one".to_string()).unwrap();
+    assert!(!with_suffix(&path, ".bak").exists());
+    manager.save_text("two".to_string()).unwrap();
+
+    assert_eq!(texts(&open(&path).unwrap()), vec!["one", "two"]);
+    assert!(with_suffix(&path, ".bak").exists());
+    assert!(!with_suffix(&path, ".tmp").exists());
+}
+
+#[test]
+fn shorter_history_leaves_no_trailing_garbage() {
+    let dir = TempDir::new().unwrap();
+    let path = history_path(&dir);
+    let padded = format!(
+        r#"{{"items":[{{"time":1,"item_type":"TEXT","data":"one"}}],{}"image_counter":0,"text_counter":1}}"#,
+        " ".repeat(4096)
+    );
+    fs::write(&path, &padded).unwrap();
+
+    let mut manager = open(&path).unwrap();
+    manager.save_text("two".to_string()).unwrap();
+
+    let saved = fs::read_to_string(&path).unwrap();
+    assert!(saved.len() < padded.len());
+    assert!(!saved.contains("    "));
+    assert_eq!(texts(&open(&path).unwrap()), vec!["one", "two"]);
+}
+
+#[test]
+fn truncated_primary_recovers_from_backup() {
+    let dir = TempDir::new().unwrap();
+    let path = history_path(&dir);
+
+    let mut manager = open(&path).unwrap();
+    manager.save_text("one".to_string()).unwrap();
+    manager.save_text("two".to_string()).unwrap();
+
+    let contents = fs::read(&path).unwrap();
+    fs::write(&path, &contents[..contents.len() / 2]).unwrap();
+
+    assert_eq!(texts(&open(&path).unwrap()), vec!["one"]);
+}
+
+#[test]
+fn interrupted_temp_write_does_not_touch_primary() {
+    let dir = TempDir::new().unwrap();
+    let path = history_path(&dir);
+
+    let mut manager = open(&path).unwrap();
+    manager.save_text("one".to_string()).unwrap();
+    // A crash while writing the temp file leaves it behind half written.
+    fs::write(with_suffix(&path, ".tmp"), b"{\"items\":[{\"ti").unwrap();
+
+    let mut manager = open(&path).unwrap();
+    assert_eq!(texts(&manager), vec!["one"]);
+    manager.save_text("two".to_string()).unwrap();
+    assert_eq!(texts(&open(&path).unwrap()), vec!["one", "two"]);
+}
+
+#[test]
+fn longer_leftover_temp_file_is_truncated() {
+    let dir = TempDir::new().unwrap();
+    let path = history_path(&dir);
+    fs::create_dir_all(dir.path()).unwrap();
+    fs::write(with_suffix(&path, ".tmp"), " ".repeat(4096)).unwrap();
+
+    let mut manager = open(&path).unwrap();
+    manager.save_text("one".to_string()).unwrap();
+    assert!(!fs::read_to_string(&path).unwrap().contains("    "));
+    assert_eq!(texts(&open(&path).unwrap()), vec!["one"]);
+}
+
+#[test]
+fn missing_primary_recovers_from_backup() {
+    let dir = TempDir::new().unwrap();
+    let path = history_path(&dir);
+
+    let mut manager = open(&path).unwrap();
+    manager.save_text("one".to_string()).unwrap();
+    manager.save_text("two".to_string()).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(texts(&open(&path).unwrap()), vec!["one"]);
+}
+
+#[test]
+fn corrupt_primary_without_backup_is_a_parse_error() {
+    let dir = TempDir::new().unwrap();
+    let path = history_path(&dir);
+    fs::write(&path, b"{\"items\":[").unwrap();
+
+    assert!(matches!(open(&path), Err(ClipmateError::Parse(_))));
+}
+
+#[test]
+fn migrates_legacy_history_and_images() {
+    let legacy_dir = TempDir::new().unwrap();
+    let data_dir = TempDir::new().unwrap();
+    let legacy_file = legacy_dir.path().join(".clipboard_history.json");
+    fs::write(
+        &legacy_file,
+        r#"{"items":[{"time":1,"item_type":"TEXT","data":"hi"},{"time":2,"item_type":"IMAGE","data":"abc.png"}],"image_counter":1,"text_counter":1}"#,
+    )
+    .unwrap();
+    fs::write(legacy_dir.path().join("abc.png"), b"png").unwrap();
+
+    let paths = DataPaths::new(data_dir.path());
+    let storage = StorageConfig::default();
+    assert!(manager::migrate_legacy_history(&legacy_file, &paths, None, &storage).unwrap());
+    assert!(!manager::migrate_legacy_history(&legacy_file, &paths, None, &storage).unwrap());
+
+    assert_eq!(
+        fs::read(paths.images_dir().join("abc.png")).unwrap(),
+        b"png"
+    );
+    assert!(!legacy_dir.path().join("abc.png").exists());
+    assert!(!legacy_file.exists());
+    assert_eq!(
+        texts(&open(&paths.history_file()).unwrap()),
+        vec!["hi", "abc.png"]
+    );
+}
+
+#[test]
+fn legacy_history_is_encrypted_when_migrated() {
+    let legacy_dir = TempDir::new().unwrap();
+    let data_dir = TempDir::new().unwrap();
+    let legacy_file = legacy_dir.path().join(".clipboard_history.json");
+    fs::write(
+        &legacy_file,
+        r#"{"items":[{"time":1,"item_type":"TEXT","data":"hunter2"}],"image_counter":0,"text_counter":1}"#,
+    )
+    .unwrap();
+
+    let paths = DataPaths::new(data_dir.path());
+    let (key, _) = KeyParams::generate(b"secret key material").unwrap();
+    assert!(manager::migrate_legacy_history(
+        &legacy_file,
+        &paths,
+        Some(key),
+        &StorageConfig::default()
+    )
+    .unwrap());
+    let history = fs::read(paths.history_file()).unwrap();
+    assert!(crypto::is_encrypted(&history));
+    assert_eq!(
+        files_containing(data_dir.path(), b"hunter2"),
+        Vec::<PathBuf>::new()
+    );
+}
+
+#[test]
+fn corrupt_legacy_history_is_left_in_place() {
+    let legacy_dir = TempDir::new().unwrap();
+    let data_dir = TempDir::new().unwrap();
+    let legacy_file = legacy_dir.path().join(".clipboard_history.json");
+    fs::write(&legacy_file, "{\"items\": [").unwrap();
+
+    let paths = DataPaths::new(data_dir.path());
+    let result =
+        manager::migrate_legacy_history(&legacy_file, &paths, None, &StorageConfig::default());
+    assert!(matches!(result, Err(ClipmateError::Parse(_))));
+    assert!(legacy_file.exists());
+    assert!(!paths.history_file().exists());
+}
+
+/// Every file under `dir` whose contents include `needle`.
+fn files_containing(dir: &Path, needle: &[u8]) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            found.extend(files_containing(&path, needle));
+        } else if fs::read(&path)
+            .unwrap()
+            .windows(needle.len())
+            .any(|window| window == needle)
+        {
+            found.push(path);
+        }
+    }
+    found
+}
+
+#[test]
+fn deleted_items_leave_nothing_behind() {
+    for format in [StoreFormat::Json, StoreFormat::Journal, StoreFormat::Sqlite] {
+        let dir = TempDir::new().unwrap();
+        let config = config_for(dir.path());
+        let paths = config.data_paths().unwrap();
+        store::migrate(&paths, None, format, &config.storage).unwrap();
+
+        let mut manager = open(&history_path(&dir)).unwrap();
+        manager.save_text("hunter2 deleted".to_string()).unwrap();
+        manager.save_text("hunter2 edited".to_string()).unwrap();
+        manager.save_text("kept".to_string()).unwrap();
+        manager.delete(&[1]).unwrap();
+        manager.edit_text(1, "rewritten".to_string()).unwrap();
+        manager.save_text("copied after".to_string()).unwrap();
+
+        assert_eq!(texts(&manager), vec!["rewritten", "kept", "copied after"]);
+        assert_eq!(
+            files_containing(dir.path(), b"hunter2"),
+            Vec::<PathBuf>::new(),
+            "{:?}",
+            format
+        );
+    }
+}
+
+#[test]
+fn history_files_are_only_readable_by_the_user() {
+    for format in [StoreFormat::Json, StoreFormat::Journal, StoreFormat::Sqlite] {
+        let dir = TempDir::new().unwrap();
+        let data_dir = dir.path().join("data");
+        let config = config_for(&data_dir);
+        let paths = config.data_paths().unwrap();
+        store::migrate(&paths, None, format, &config.storage).unwrap();
+        let mut manager = open(&paths.history_file()).unwrap();
+        manager.save_text("private".to_string()).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&data_dir), 0o700);
+        let file = match format {
+            StoreFormat::Json => paths.history_file(),
+            StoreFormat::Journal => paths.journal_file(),
+            StoreFormat::Sqlite => paths.database_file(),
+        };
+        assert_eq!(mode(&file), 0o600, "{:?}", format);
+    }
+}
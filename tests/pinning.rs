@@ -0,0 +1,70 @@
+mod common;
+
+use clipboard_manager_lib::backend::MemoryBackend;
+use clipboard_manager_lib::manager::ClipboardManager;
+use clipboard_manager_lib::ClipmateError;
+use common::{config_for, memory_manager, texts};
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn history_without_pinned_field_loads_unpinned() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("history.json"),
+        r#"{"items":[{"time":1,"item_type":"TEXT","data":"old"}],"image_counter":0,"text_counter":1}"#,
+    )
+    .unwrap();
+
+    let manager = memory_manager(dir.path());
+    assert_eq!(texts(&manager), vec!["old"]);
+    assert!(!manager.get_history()[0].pinned);
+}
+
+#[test]
+fn pin_state_is_persisted() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    manager.save_text("ssh config".to_string()).unwrap();
+    manager.save_text("other".to_string()).unwrap();
+
+    manager.set_pinned(1, true).unwrap();
+    let pinned: Vec<bool> = memory_manager(dir.path())
+        .get_history()
+        .iter()
+        .map(|i| i.pinned)
+        .collect();
+    assert_eq!(pinned, vec![true, false]);
+
+    manager.set_pinned(1, false).unwrap();
+    assert!(!memory_manager(dir.path()).get_history()[0].pinned);
+}
+
+#[test]
+fn pinning_a_missing_item_fails() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    assert!(matches!(
+        manager.set_pinned(0, true),
+        Err(ClipmateError::ItemNotFound(0))
+    ));
+    assert!(matches!(
+        manager.set_pinned(3, true),
+        Err(ClipmateError::ItemNotFound(3))
+    ));
+}
+
+#[test]
+fn pinned_items_survive_retention() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config_for(dir.path());
+    config.history.max_items = Some(1);
+    let mut manager = ClipboardManager::with_backend(config, MemoryBackend::new()).unwrap();
+
+    manager.save_text("keep me".to_string()).unwrap();
+    manager.set_pinned(1, true).unwrap();
+    for text in ["one", "two", "three"] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    assert_eq!(texts(&manager), vec!["keep me", "three"]);
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend};
+use clipboard_manager_lib::config::{parse_duration, Config};
+use clipboard_manager_lib::manager::{ClipboardItemType, ClipboardManager};
+use clipboard_manager_lib::paths::DataPaths;
+use clipboard_manager_lib::store::{self, StoreFormat};
+use common::{blob_files, config_for, texts};
+use std::fs;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn manager_with(
+    dir: &TempDir,
+    configure: impl FnOnce(&mut Config),
+) -> ClipboardManager<MemoryBackend> {
+    let mut config = config_for(dir.path());
+    configure(&mut config);
+    ClipboardManager::with_backend(config, MemoryBackend::new()).unwrap()
+}
+
+fn copy_image(manager: &mut ClipboardManager<MemoryBackend>, seed: u8) {
+    manager
+        .backend_mut()
+        .write_image("image/png", &[seed; 64])
+        .unwrap();
+    manager.update_image_content().unwrap();
+}
+
+fn image_files(dir: &TempDir) -> usize {
+    blob_files(dir.path()).len()
+}
+
+#[test]
+fn parses_durations() {
+    assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+    assert_eq!(
+        parse_duration("7d").unwrap(),
+        Duration::from_secs(7 * 86400)
+    );
+    assert_eq!(
+        parse_duration("2w").unwrap(),
+        Duration::from_secs(14 * 86400)
+    );
+    assert!(parse_duration("7").is_err());
+    assert!(parse_duration("d").is_err());
+    assert!(parse_duration("3 days").is_err());
+}
+
+#[test]
+fn overall_max_items_drops_oldest() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = manager_with(&dir, |c| c.history.max_items = Some(2));
+    for text in ["one", "two", "three"] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    assert_eq!(texts(&manager), vec!["two", "three"]);
+}
+
+#[test]
+fn pruning_on_copy_keeps_the_backup_and_appends_to_the_journal() {
+    let dir = TempDir::new().unwrap();
+    let paths = DataPaths::new(dir.path());
+    let mut manager = manager_with(&dir, |c| c.history.max_items = Some(2));
+    for text in ["one", "two", "three"] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    let backup = fs::read_to_string(dir.path().join("history.json.bak")).unwrap();
+    assert!(backup.contains("\"two\""), "{}", backup);
+    drop(manager);
+
+    store::migrate(&paths, None, StoreFormat::Journal, &Default::default()).unwrap();
+    let mut manager = manager_with(&dir, |c| c.history.max_items = Some(2));
+    let lines = || -> Vec<String> {
+        let journal = fs::read_to_string(paths.journal_file()).unwrap();
+        journal.lines().map(str::to_string).collect()
+    };
+    let before = lines();
+    manager.save_text("four".to_string()).unwrap();
+    let after = lines();
+    assert_eq!(after[..before.len()], before[..]);
+    assert_eq!(after.len(), before.len() + 2);
+    assert!(after[after.len() - 1].starts_with(r#"{"delete":"#));
+    assert_eq!(texts(&manager), vec!["three", "four"]);
+}
+
+#[test]
+fn max_bytes_keeps_newest_items_within_budget() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = manager_with(&dir, |c| c.history.text.max_bytes = Some(10));
+    for text in ["aaaa", "bbbb", "cccc"] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    assert_eq!(texts(&manager), vec!["bbbb", "cccc"]);
+}
+
+#[test]
+fn per_type_limits_only_affect_their_type() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = manager_with(&dir, |c| c.history.image.max_items = Some(1));
+    manager.save_text("one".to_string()).unwrap();
+    copy_image(&mut manager, 1);
+    manager.save_text("two".to_string()).unwrap();
+    copy_image(&mut manager, 2);
+
+    let types: Vec<ClipboardItemType> = manager.get_history().iter().map(|i| i.item_type).collect();
+    assert_eq!(
+        types,
+        vec![
+            ClipboardItemType::TEXT,
+            ClipboardItemType::TEXT,
+            ClipboardItemType::IMAGE
+        ]
+    );
+    // The pruned image's file is gone, the kept one remains.
+    assert_eq!(image_files(&dir), 1);
+}
+
+#[test]
+fn max_age_is_enforced_at_startup() {
+    let dir = TempDir::new().unwrap();
+    fs::write(
+        dir.path().join("history.json"),
+        r#"{"items":[{"time":1,"item_type":"TEXT","data":"ancient"}],"image_counter":0,"text_counter":1}"#,
+    )
+    .unwrap();
+
+    let mut manager = manager_with(&dir, |c| c.history.max_age = Some("30d".to_string()));
+    assert_eq!(texts(&manager), vec!["ancient"]);
+    assert_eq!(manager.enforce_retention().unwrap(), 1);
+    assert!(texts(&manager).is_empty());
+
+    let reopened = manager_with(&dir, |_| {});
+    assert!(texts(&reopened).is_empty());
+}
+
+#[test]
+fn startup_removes_orphaned_image_files() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = manager_with(&dir, |_| {});
+    copy_image(&mut manager, 7);
+    fs::write(blob_files(dir.path())[0].with_extension("tmp"), b"stray").unwrap();
+    assert_eq!(image_files(&dir), 2);
+
+    assert_eq!(manager.enforce_retention().unwrap(), 0);
+    assert_eq!(image_files(&dir), 1);
+    assert_eq!(manager.get_history().len(), 1);
+}
+
+#[test]
+fn invalid_max_age_is_a_config_error() {
+    assert!(Config::parse("[history.image]\nmax_age = \"soon\"").is_err());
+    assert!(Config::parse("[history.image]\nmax_age = \"12h\"").is_ok());
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use clipboard_manager_lib::backend::ClipboardBackend;
+use clipboard_manager_lib::manager::ClipboardItemType;
+use clipboard_manager_lib::search::{SearchMode, SearchQuery};
+use clipboard_manager_lib::ClipmateError;
+use common::memory_manager;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn numbers(results: Vec<(usize, &clipboard_manager_lib::manager::ClipboardItem)>) -> Vec<usize> {
+    results.into_iter().map(|(n, _)| n).collect()
+}
+
+fn query(pattern: &str, mode: SearchMode) -> SearchQuery {
+    SearchQuery {
+        pattern: pattern.to_string(),
+        mode,
+        ..SearchQuery::default()
+    }
+}
+
+#[test]
+fn matches_in_every_mode_and_keeps_item_numbers() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    for text in [
+        "git checkout main",
+        "SELECT *\nFROM users\nWHERE id = 1",
+        "Git Commit",
+        "cargo test",
+    ] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+
+    let search = |q: SearchQuery| numbers(manager.search(&q).unwrap());
+    assert_eq!(search(query("git", SearchMode::Substring)), vec![1]);
+    assert_eq!(search(query("git", SearchMode::IgnoreCase)), vec![1, 3]);
+    assert_eq!(search(query("^FROM", SearchMode::Regex)), vec![2]);
+    assert_eq!(search(query("gco", SearchMode::Fuzzy)), vec![1, 3]);
+    assert_eq!(search(query("ct", SearchMode::Fuzzy)), vec![1, 2, 3, 4]);
+    assert!(search(query("nothing", SearchMode::Substring)).is_empty());
+}
+
+#[test]
+fn filters_by_type_pinned_and_age() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = memory_manager(dir.path());
+    manager.save_text("a.png notes".to_string()).unwrap();
+    manager.save_text("png settings".to_string()).unwrap();
+    manager
+        .backend_mut()
+        .write_image("image/png", &[3; 80])
+        .unwrap();
+    manager.update_image_content().unwrap();
+    manager.set_pinned(2, true).unwrap();
+
+    let mut q = query("png", SearchMode::Substring);
+    assert_eq!(numbers(manager.search(&q).unwrap()), vec![1, 2, 3]);
+
+    q.item_type = Some(ClipboardItemType::IMAGE);
+    assert_eq!(numbers(manager.search(&q).unwrap()), vec![3]);
+
+    q.item_type = Some(ClipboardItemType::TEXT);
+    q.pinned = Some(false);
+    assert_eq!(numbers(manager.search(&q).unwrap()), vec![1]);
+
+    q.pinned = None;
+    q.newer_than = Some(Duration::from_secs(60));
+    assert_eq!(numbers(manager.search(&q).unwrap()), vec![1, 2]);
+    q.older_than = Some(Duration::from_secs(30));
+    assert!(manager.search(&q).unwrap().is_empty());
+}
+
+#[test]
+fn invalid_regex_is_reported() {
+    let dir = TempDir::new().unwrap();
+    let manager = memory_manager(dir.path());
+    assert!(matches!(
+        manager.search(&query("(", SearchMode::Regex)),
+        Err(ClipmateError::InvalidInput(_))
+    ));
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend, Selection};
+use clipboard_manager_lib::config::Config;
+use clipboard_manager_lib::manager::ClipboardManager;
+use clipboard_manager_lib::paths::DataPaths;
+use clipboard_manager_lib::store::{self, StoreFormat};
+use common::{config_for, texts};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn watching(
+    dir: &TempDir,
+    selections: &[Selection],
+    debounce_ms: u64,
+) -> ClipboardManager<MemoryBackend> {
+    let mut config = config_for(dir.path());
+    config.capture.selections = selections.to_vec();
+    config.capture.debounce_ms = debounce_ms;
+    ClipboardManager::with_backend(config, MemoryBackend::new()).unwrap()
+}
+
+fn select_text(manager: &mut ClipboardManager<MemoryBackend>, selection: Selection, text: &str) {
+    let backend = manager.backend_mut();
+    backend.select(selection).unwrap();
+    backend.write_text(text).unwrap();
+    backend.select(Selection::Clipboard).unwrap();
+}
+
+#[test]
+fn selections_are_read_from_config() {
+    let config = Config::parse("[capture]\nselection = \"primary\"").unwrap();
+    assert_eq!(config.capture.selections, vec![Selection::Primary]);
+    let config = Config::parse("[capture]\nselections = [\"clipboard\", \"secondary\"]").unwrap();
+    assert_eq!(
+        config.capture.selections,
+        vec![Selection::Clipboard, Selection::Secondary]
+    );
+    assert!(Config::parse("[capture]\nselections = []").is_err());
+}
+
+#[test]
+fn only_watched_selections_are_recorded() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = watching(&dir, &[Selection::Clipboard], 0);
+    select_text(&mut manager, Selection::Primary, "selected");
+    select_text(&mut manager, Selection::Clipboard, "copied");
+    manager.update_clipboard_content().unwrap();
+    assert_eq!(texts(&manager), vec!["copied"]);
+    drop(manager);
+
+    let mut manager = watching(&dir, &[Selection::Clipboard, Selection::Primary], 0);
+    select_text(&mut manager, Selection::Primary, "selected");
+    select_text(&mut manager, Selection::Clipboard, "copied");
+    manager.update_clipboard_content().unwrap();
+    assert_eq!(texts(&manager), vec!["copied", "selected"]);
+    assert_eq!(manager.get_item(2).unwrap().selection, Selection::Primary);
+    assert_eq!(manager.get_item(1).unwrap().selection, Selection::Clipboard);
+}
+
+#[test]
+fn debounce_records_only_the_finished_selection() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = watching(&dir, &[Selection::Primary], 50);
+    for partial in ["w", "wo", "wor", "word"] {
+        select_text(&mut manager, Selection::Primary, partial);
+        manager.update_clipboard_content().unwrap();
+    }
+    assert!(texts(&manager).is_empty());
+
+    thread::sleep(Duration::from_millis(60));
+    manager.update_clipboard_content().unwrap();
+    manager.update_clipboard_content().unwrap();
+    assert_eq!(texts(&manager), vec!["word"]);
+}
+
+#[test]
+fn items_are_restored_into_the_given_selection() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = watching(&dir, &[Selection::Clipboard], 0);
+    manager.save_text("one".to_string()).unwrap();
+    manager.backend_mut().write_text("current").unwrap();
+
+    manager.restore_to(1, Selection::Primary).unwrap();
+    assert_eq!(manager.backend().text(), Some("current"));
+    manager.backend_mut().select(Selection::Primary).unwrap();
+    assert_eq!(manager.backend().text(), Some("one"));
+}
+
+#[test]
+fn the_selection_survives_the_sqlite_store() {
+    let dir = TempDir::new().unwrap();
+    store::migrate(
+        &DataPaths::new(dir.path()),
+        None,
+        StoreFormat::Sqlite,
+        &Default::default(),
+    )
+    .unwrap();
+    let mut manager = watching(&dir, &[Selection::Primary], 0);
+    select_text(&mut manager, Selection::Primary, "selected");
+    manager.update_clipboard_content().unwrap();
+    drop(manager);
+
+    let manager = watching(&dir, &[Selection::Clipboard], 0);
+    assert_eq!(manager.get_item(1).unwrap().selection, Selection::Primary);
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend};
+use clipboard_manager_lib::manager::{ClipboardItemType, ClipboardManager};
+use clipboard_manager_lib::paths::DataPaths;
+use clipboard_manager_lib::search::{SearchMode, SearchQuery};
+use clipboard_manager_lib::store::{self, StoreFormat};
+use common::{config_for, memory_manager, texts};
+use std::fs;
+use tempfile::TempDir;
+
+/// Manager over an SQLite history, migrated from an empty one.
+fn sqlite_manager(dir: &TempDir) -> ClipboardManager<MemoryBackend> {
+    let paths = DataPaths::new(dir.path());
+    store::migrate(&paths, None, StoreFormat::Sqlite, &Default::default()).unwrap();
+    memory_manager(dir.path())
+}
+
+fn search(
+    manager: &ClipboardManager<MemoryBackend>,
+    pattern: &str,
+    mode: SearchMode,
+) -> Vec<usize> {
+    let query = SearchQuery {
+        pattern: pattern.to_string(),
+        mode,
+        ..SearchQuery::default()
+    };
+    manager
+        .search(&query)
+        .unwrap()
+        .into_iter()
+        .map(|(n, _)| n)
+        .collect()
+}
+
+#[test]
+fn migration_to_sqlite_and_back_is_lossless() {
+    let dir = TempDir::new().unwrap();
+    let paths = DataPaths::new(dir.path());
+    let original = r#"{"items":[{"id":1,"time":1,"item_type":"TEXT","data":"one","pinned":true},{"id":2,"time":2,"item_type":"IMAGE","data":"abc.png","pinned":false},{"id":3,"time":3,"item_type":"TEXT","data":"two","pinned":false,"expires_at":9000000000000000000}],"image_counter":4,"text_counter":7}"#;
+    fs::write(paths.history_file(), original).unwrap();
+
+    assert!(store::migrate(&paths, None, StoreFormat::Sqlite, &Default::default()).unwrap());
+    assert_eq!(StoreFormat::detect(&paths), StoreFormat::Sqlite);
+    assert!(!paths.history_file().exists());
+    let manager = memory_manager(dir.path());
+    assert_eq!(texts(&manager), vec!["one", "abc.png", "two"]);
+    assert_eq!(manager.get_counter(ClipboardItemType::TEXT), 7);
+    drop(manager);
+
+    assert!(store::migrate(&paths, None, StoreFormat::Json, &Default::default()).unwrap());
+    assert!(!paths.database_file().exists());
+    assert_eq!(fs::read_to_string(paths.history_file()).unwrap(), original);
+}
+
+#[test]
+fn changes_are_written_to_the_database() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = sqlite_manager(&dir);
+    for text in ["one", "two", "three"] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    manager.set_pinned(1, true).unwrap();
+    manager.edit_text(2, "edited".to_string()).unwrap();
+    manager.delete(&[3]).unwrap();
+    manager.save_text("four".to_string()).unwrap();
+    drop(manager);
+
+    let manager = memory_manager(dir.path());
+    assert_eq!(texts(&manager), vec!["one", "edited", "four"]);
+    assert!(manager.get_item(1).unwrap().pinned);
+    assert!(!DataPaths::new(dir.path()).history_file().exists());
+}
+
+#[test]
+fn retention_removes_items_from_the_database() {
+    let dir = TempDir::new().unwrap();
+    sqlite_manager(&dir);
+    let mut config = config_for(dir.path());
+    config.history.max_items = Some(2);
+    let mut manager = ClipboardManager::with_backend(config, MemoryBackend::new()).unwrap();
+    for text in ["one", "two", "three"] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    drop(manager);
+
+    assert_eq!(texts(&memory_manager(dir.path())), vec!["two", "three"]);
+}
+
+#[test]
+fn indexed_search_matches_like_a_scan() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = sqlite_manager(&dir);
+    for text in ["git checkout main", "Git Commit", "cargo test"] {
+        manager.save_text(text.to_string()).unwrap();
+    }
+    manager.edit_text(3, "cargo build".to_string()).unwrap();
+    manager
+        .backend_mut()
+        .write_image("image/png", &[1; 100])
+        .unwrap();
+    manager.update_image_content().unwrap();
+
+    assert_eq!(search(&manager, "git", SearchMode::Substring), vec![1]);
+    assert_eq!(search(&manager, "GIT", SearchMode::IgnoreCase), vec![1, 2]);
+    assert_eq!(search(&manager, "build", SearchMode::Substring), vec![3]);
+    assert!(search(&manager, "test", SearchMode::Substring).is_empty());
+    assert_eq!(search(&manager, "png", SearchMode::Substring), vec![4]);
+    assert_eq!(search(&manager, "t", SearchMode::Substring), vec![1, 2]);
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, MemoryBackend, Selection};
+use clipboard_manager_lib::config::Config;
+use clipboard_manager_lib::daemon::{Daemon, Failure};
+use clipboard_manager_lib::manager::ClipboardManager;
+use clipboard_manager_lib::watch::{ChannelWatcher, Event, PollWatcher, Watcher};
+use common::{config_for, texts};
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn daemon(config: Config) -> (std::sync::mpsc::Sender<()>, Daemon<MemoryBackend>) {
+    let manager = ClipboardManager::with_backend(config, MemoryBackend::new()).unwrap();
+    let (events, watcher) = ChannelWatcher::new("fake");
+    (events, Daemon::new(manager, Box::new(watcher)))
+}
+
+#[test]
+fn polling_slows_down_while_nothing_changes() {
+    let mut watcher = PollWatcher::new(Duration::from_millis(10), Duration::from_millis(40));
+    assert_eq!(
+        watcher.wait(Duration::from_secs(1)).unwrap(),
+        Event::Changed
+    );
+    for expected in [20, 40, 40] {
+        watcher.checked(false);
+        assert_eq!(watcher.interval(), Duration::from_millis(expected));
+    }
+    assert_eq!(
+        watcher.wait(Duration::from_millis(5)).unwrap(),
+        Event::Timeout
+    );
+
+    watcher.checked(true);
+    assert_eq!(watcher.interval(), Duration::from_millis(10));
+    assert_eq!(
+        watcher.wait(Duration::from_secs(1)).unwrap(),
+        Event::Changed
+    );
+}
+
+#[test]
+fn reloaded_intervals_apply_to_polling() {
+    let mut watcher = PollWatcher::new(Duration::from_millis(10), Duration::from_millis(40));
+    watcher.checked(false);
+    let mut config = Config::default();
+    config.daemon.poll_interval_ms = 100;
+    config.daemon.max_poll_interval_ms = 300;
+    watcher.reconfigure(&config.daemon);
+    assert_eq!(watcher.interval(), Duration::from_millis(100));
+    for expected in [200, 300, 300] {
+        watcher.checked(false);
+        assert_eq!(watcher.interval(), Duration::from_millis(expected));
+    }
+}
+
+#[test]
+fn the_clipboard_is_read_when_an_event_arrives() {
+    let dir = TempDir::new().unwrap();
+    let (events, mut daemon) = daemon(config_for(dir.path()));
+    daemon.manager().backend_mut().write_text("one").unwrap();
+    events.send(()).unwrap();
+    assert!(daemon.step().is_empty());
+    assert_eq!(texts(&daemon.manager()), vec!["one"]);
+
+    daemon.manager().backend_mut().write_text("two").unwrap();
+    for _ in 0..3 {
+        events.send(()).unwrap();
+    }
+    daemon.step();
+    assert_eq!(texts(&daemon.manager()), vec!["one", "two"]);
+}
+
+#[test]
+fn each_check_lists_the_offered_types_once_per_selection() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config_for(dir.path());
+    config.capture.selections = vec![Selection::Clipboard, Selection::Primary];
+    config.capture.formats = vec!["text/html".to_string()];
+    let (events, mut daemon) = daemon(config);
+    {
+        let mut manager = daemon.manager();
+        let backend = manager.backend_mut();
+        backend.write_text("one").unwrap();
+        backend.offer("text/html", b"<b>one</b>");
+    }
+
+    events.send(()).unwrap();
+    daemon.step();
+    assert_eq!(texts(&daemon.manager()), vec!["one"]);
+    assert_eq!(daemon.manager().get_item(1).unwrap().formats.len(), 1);
+    assert_eq!(daemon.manager().backend().types_listed(), 2);
+}
+
+#[test]
+fn debounced_selections_are_read_again_without_an_event() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config_for(dir.path());
+    config.capture.selections = vec![Selection::Primary];
+    config.capture.debounce_ms = 20;
+    let (events, mut daemon) = daemon(config);
+    {
+        let mut manager = daemon.manager();
+        let backend = manager.backend_mut();
+        backend.select(Selection::Primary).unwrap();
+        backend.write_text("selected").unwrap();
+        backend.select(Selection::Clipboard).unwrap();
+    }
+
+    events.send(()).unwrap();
+    daemon.step();
+    assert!(texts(&daemon.manager()).is_empty());
+    assert!(daemon.manager().debounce_deadline().is_some());
+
+    daemon.step();
+    assert_eq!(texts(&daemon.manager()), vec!["selected"]);
+    assert!(daemon.manager().debounce_deadline().is_none());
+}
+
+#[test]
+fn polling_takes_over_when_the_watcher_stops() {
+    let dir = TempDir::new().unwrap();
+    let (events, mut daemon) = daemon(config_for(dir.path()));
+    assert_eq!(daemon.watcher_name(), "fake");
+    drop(events);
+
+    let failures = daemon.step();
+    assert!(matches!(failures[..], [Failure::Watcher("fake", _)]));
+    assert_eq!(daemon.watcher_name(), "poll");
+
+    daemon.manager().backend_mut().write_text("one").unwrap();
+    daemon.step();
+    assert_eq!(texts(&daemon.manager()), vec!["one"]);
+}
@@ -0,0 +1,225 @@
+mod common;
+
+use clipboard_manager_lib::backend::{ClipboardBackend, SystemBackend, WaylandBackend};
+use clipboard_manager_lib::manager::ClipboardManager;
+use clipboard_manager_lib::paths::DataPaths;
+use common::config_for;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// PATH and WAYLAND_DISPLAY are process-wide, so tests touching them run one
+// at a time.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+const WL_PASTE: &str = r#"#!/bin/sh
+state="@STATE@"
+type=""
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --list-types)
+            [ -s "$state/types" ] || exit 1
+            cat "$state/types"
+            exit 0
+            ;;
+        --type|-t) type="$2"; shift ;;
+    esac
+    shift
+done
+[ "$type" = "text" ] && type="text/plain;charset=utf-8"
+file="$state/$(printf %s "$type" | tr '/;=' '___')"
+[ -f "$file" ] || exit 1
+cat "$file"
+"#;
+
+const WL_COPY: &str = r#"#!/bin/sh
+state="@STATE@"
+type="text/plain;charset=utf-8"
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --type|-t) type="$2"; shift ;;
+    esac
+    shift
+done
+rm -f "$state"/*
+cat > "$state/$(printf %s "$type" | tr '/;=' '___')"
+printf '%s\n' "$type" > "$state/types"
+"#;
+
+struct Stubs {
+    _guard: MutexGuard<'static, ()>,
+    dir: TempDir,
+}
+
+impl Stubs {
+    fn install() -> Stubs {
+        let guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = TempDir::new().unwrap();
+        let bin = dir.path().join("bin");
+        let state = dir.path().join("state");
+        fs::create_dir(&bin).unwrap();
+        fs::create_dir(&state).unwrap();
+        for (name, script) in [("wl-paste", WL_PASTE), ("wl-copy", WL_COPY)] {
+            let path = bin.join(name);
+            fs::write(&path, script.replace("@STATE@", state.to_str().unwrap())).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        env::set_var("PATH", format!("{}:/usr/bin:/bin", bin.display()));
+        Stubs { _guard: guard, dir }
+    }
+
+    fn state(&self) -> std::path::PathBuf {
+        self.dir.path().join("state")
+    }
+
+    /// Puts a single item on the fake clipboard, as if copied by another app.
+    fn offer(&self, mime_type: &str, data: &[u8]) {
+        let file = mime_type.replace(['/', ';', '='], "_");
+        fs::write(self.state().join(file), data).unwrap();
+        fs::write(self.state().join("types"), format!("{}\n", mime_type)).unwrap();
+    }
+
+    fn copied(&self, mime_type: &str) -> Vec<u8> {
+        let file = mime_type.replace(['/', ';', '='], "_");
+        fs::read(self.state().join(file)).unwrap()
+    }
+}
+
+#[test]
+fn reads_text_and_lists_types() {
+    let stubs = Stubs::install();
+    stubs.offer("text/plain;charset=utf-8", b"hello wayland");
+
+    let mut backend = WaylandBackend::new();
+    assert_eq!(backend.read_text().unwrap(), "hello wayland");
+    assert_eq!(
+        backend.list_types().unwrap(),
+        vec!["text/plain;charset=utf-8".to_string()]
+    );
+}
+
+#[test]
+fn reads_image_only_when_offered() {
+    let stubs = Stubs::install();
+    let mut backend = WaylandBackend::new();
+
+    stubs.offer("text/plain;charset=utf-8", b"not an image");
+    assert!(backend.read_type("image/png").unwrap().is_empty());
+
+    stubs.offer("image/png", &[0x89, b'P', b'N', b'G', 1, 2, 3]);
+    assert_eq!(
+        backend.read_type("image/png").unwrap(),
+        vec![0x89, b'P', b'N', b'G', 1, 2, 3]
+    );
+}
+
+#[test]
+fn empty_clipboard_is_an_error_for_text() {
+    let _stubs = Stubs::install();
+    let mut backend = WaylandBackend::new();
+    assert!(backend.read_text().is_err());
+    assert!(backend.is_available());
+}
+
+#[test]
+fn writes_text_and_images_through_wl_copy() {
+    let stubs = Stubs::install();
+    let mut backend = WaylandBackend::new();
+
+    backend.write_text("restored").unwrap();
+    assert_eq!(stubs.copied("text/plain;charset=utf-8"), b"restored");
+
+    backend.write_image("image/png", &[1, 2, 3]).unwrap();
+    assert_eq!(stubs.copied("image/png"), vec![1, 2, 3]);
+    assert_eq!(backend.read_type("image/png").unwrap(), vec![1, 2, 3]);
+}
+
+#[test]
+fn missing_tools_disable_the_backend() {
+    let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let empty = TempDir::new().unwrap();
+    env::set_var("PATH", empty.path());
+
+    let mut backend = WaylandBackend::new();
+    assert!(backend.read_text().is_err());
+    assert!(!backend.is_available());
+    assert!(backend.read_type("image/png").unwrap().is_empty());
+}
+
+#[test]
+fn disabled_backend_is_retried() {
+    let stubs = Stubs::install();
+    let path = env::var_os("PATH").unwrap();
+    let empty = TempDir::new().unwrap();
+    env::set_var("PATH", empty.path());
+
+    let mut backend = WaylandBackend::new().retry_after(Duration::from_millis(50));
+    assert!(backend.read_text().is_err());
+    assert!(!backend.is_available());
+
+    env::set_var("PATH", path);
+    stubs.offer("text/plain;charset=utf-8", b"installed later");
+    assert!(!backend.is_available());
+    thread::sleep(Duration::from_millis(60));
+    assert!(backend.is_available());
+    assert_eq!(backend.read_text().unwrap(), "installed later");
+}
+
+#[test]
+fn detect_prefers_wayland_when_display_is_set() {
+    let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    env::set_var("WAYLAND_DISPLAY", "wayland-0");
+    assert!(matches!(SystemBackend::detect(), SystemBackend::Wayland(_)));
+
+    env::remove_var("WAYLAND_DISPLAY");
+    assert!(matches!(SystemBackend::detect(), SystemBackend::X11(_)));
+}
+
+#[test]
+fn manager_captures_and_restores_text() {
+    let stubs = Stubs::install();
+    let paths = DataPaths::new(stubs.dir.path().join("data"));
+
+    let mut manager =
+        ClipboardManager::with_backend(config_for(paths.data_dir()), WaylandBackend::new())
+            .unwrap();
+    stubs.offer("text/plain;charset=utf-8", b"first");
+    manager.update_clipboard_content().unwrap();
+    stubs.offer("text/plain;charset=utf-8", b"second");
+    manager.update_clipboard_content().unwrap();
+    manager.update_clipboard_content().unwrap();
+
+    let history: Vec<&str> = manager
+        .get_history()
+        .iter()
+        .map(|i| i.data.as_str())
+        .collect();
+    assert_eq!(history, vec!["first", "second"]);
+    assert!(paths.history_file().exists());
+
+    manager.set_clipboard_text(1).unwrap();
+    assert_eq!(stubs.copied("text/plain;charset=utf-8"), b"first");
+}
+
+#[test]
+fn manager_skips_text_with_password_manager_hint() {
+    let stubs = Stubs::install();
+    let paths = DataPaths::new(stubs.dir.path().join("data"));
+    let mut manager =
+        ClipboardManager::with_backend(config_for(paths.data_dir()), WaylandBackend::new())
+            .unwrap();
+
+    stubs.offer("text/plain;charset=utf-8", b"hunter2");
+    fs::write(
+        stubs.state().join("types"),
+        "text/plain;charset=utf-8\nx-kde-passwordManagerHint\n",
+    )
+    .unwrap();
+    manager.update_clipboard_content().unwrap();
+    assert!(manager.get_history().is_empty());
+}